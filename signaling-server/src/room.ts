@@ -15,6 +15,7 @@ interface JoinMessage {
   room_id: string;
   peer_id: string;
   name: string;
+  password?: string | null;
   create?: boolean;
 }
 
@@ -35,18 +36,67 @@ interface MuteStateMessage {
   muted: boolean;
 }
 
+interface RecordingStateMessage {
+  type: "recording_state";
+  recording: boolean;
+}
+
+// ── Host moderation (only accepted from the host) ──
+
+interface LockRoomMessage {
+  type: "lock_room";
+  locked: boolean;
+}
+
+interface KickPeerMessage {
+  type: "kick_peer";
+  peer_id: string;
+}
+
+interface RequestMuteMessage {
+  type: "request_mute";
+  peer_id: string;
+}
+
 type IncomingMessage =
   | JoinMessage
   | LeaveMessage
   | SignalMessage
-  | MuteStateMessage;
+  | MuteStateMessage
+  | RecordingStateMessage
+  | LockRoomMessage
+  | KickPeerMessage
+  | RequestMuteMessage;
 
 interface Attachment {
   peerId: string;
   name: string;
-  // First peer to join "owns" the room - used only to detect that a room
-  // exists (so later peers can join it). There is no host moderation.
-  isOwner: boolean;
+  // The peer that created the room hosts it until they leave for good, when
+  // hosting passes to the longest-connected remaining peer.
+  isHost: boolean;
+  // When the peer first joined (ms since epoch); a reconnect keeps it.
+  joinedAt: number;
+}
+
+interface PendingLeave {
+  peerId: string;
+  timestamp: number;
+  joinedAt?: number;
+}
+
+// Durable storage keys (room state that must outlive any single socket)
+const HOST_KEY = "host_peer_id";
+const LOCKED_KEY = "locked";
+const PASSWORD_KEY = "password_hash";
+const kickedKey = (peerId: string) => `kicked:${peerId}`;
+
+// Room passwords are only ever stored as a salted SHA-256 digest.
+async function hashPassword(roomId: string, password: string): Promise<string> {
+  const data = new TextEncoder().encode(`${roomId}:${password}`);
+  const digest = await crypto.subtle.digest("SHA-256", data);
+  return [...new Uint8Array(digest)]
+    .map((b) => b.toString(16).padStart(2, "0"))
+    .join("");
 }
 
 export class Room extends DurableObject<Env> {
@@ -62,6 +112,34 @@ export class Room extends DurableObject<Env> {
     return false;
   }
 
+  private findSocket(peerId: string): WebSocket | null {
+    for (const sock of this.ctx.getWebSockets()) {
+      const att = sock.deserializeAttachment() as Attachment | null;
+      if (att?.peerId === peerId) return sock;
+    }
+    return null;
+  }
+
+  private broadcast(message: string, except?: WebSocket): void {
+    for (const sock of this.ctx.getWebSockets()) {
+      if (sock === except) continue;
+      const att = sock.deserializeAttachment() as Attachment | null;
+      if (att?.peerId) {
+        try {
+          sock.send(message);
+        } catch {
+          // Socket already closed
+        }
+      }
+    }
+  }
+
+  // Attachment of the sender if they are the room's host, otherwise null.
+  private hostAttachment(ws: WebSocket): Attachment | null {
+    const att = ws.deserializeAttachment() as Attachment | null;
+    return att?.isHost ? att : null;
+  }
+
   private async getTurnCredentials(): Promise<TurnServer[]> {
     const apiKey = this.env.METERED_API_KEY;
     if (!apiKey) return [];
@@ -146,30 +224,85 @@ export class Room extends DurableObject<Env> {
           return;
         }
 
+        // A removed peer can't come back, not even through a reconnect.
+        if (await this.ctx.storage.get(kickedKey(msg.peer_id))) {
+          ws.send(JSON.stringify({ type: "kicked" }));
+          return;
+        }
+
+        // Password-protected rooms check every join, reconnects included.
+        if (hasExistingPeers) {
+          const expected = await this.ctx.storage.get<string>(PASSWORD_KEY);
+          if (
+            expected &&
+            (!msg.password ||
+              (await hashPassword(msg.room_id, msg.password)) !== expected)
+          ) {
+            ws.send(JSON.stringify({ type: "wrong_password" }));
+            return;
+          }
+        }
+
         // Check if this is a rejoin (peer reconnecting after disconnect)
         const pendingKey = `pending_leave:${msg.peer_id}`;
-        const pendingLeave = await this.ctx.storage.get(pendingKey);
+        const pendingLeave =
+          await this.ctx.storage.get<PendingLeave>(pendingKey);
         if (pendingLeave) {
           // Cancel the pending leave - this is a rejoin
           await this.ctx.storage.delete(pendingKey);
         }
+        const previousSocket = this.findSocket(msg.peer_id);
+        const isRejoin = !!pendingLeave || previousSocket !== null;
+        const previousAtt =
+          previousSocket?.deserializeAttachment() as Attachment | null;
+        const joinedAt =
+          previousAtt?.joinedAt ?? pendingLeave?.joinedAt ?? Date.now();
+
+        // Creating a room starts it fresh, hosted by the creator.
+        if (!hasExistingPeers) {
+          await this.ctx.storage.put(HOST_KEY, msg.peer_id);
+          await this.ctx.storage.put(LOCKED_KEY, false);
+          if (msg.password) {
+            await this.ctx.storage.put(
+              PASSWORD_KEY,
+              await hashPassword(msg.room_id, msg.password),
+            );
+          } else {
+            await this.ctx.storage.delete(PASSWORD_KEY);
+          }
+        }
+        const locked =
+          (await this.ctx.storage.get<boolean>(LOCKED_KEY)) ?? false;
 
-        // First peer to join owns the room (room-exists marker only).
-        const isOwner = !hasExistingPeers;
+        // A locked room only lets its current participants reconnect.
+        if (locked && !isRejoin) {
+          ws.send(JSON.stringify({ type: "room_locked" }));
+          return;
+        }
+
+        const isHost =
+          (await this.ctx.storage.get<string>(HOST_KEY)) === msg.peer_id;
 
         // Attach peer ID + name (hibernation-safe)
         ws.serializeAttachment({
           peerId: msg.peer_id,
           name: msg.name,
-          isOwner,
+          isHost,
+          joinedAt,
         } satisfies Attachment);
 
-        // Collect existing peers as {peer_id, name}
-        const peers: { peer_id: string; name: string }[] = [];
+        // Collect existing peers as {peer_id, name, is_host}
+        const peers: { peer_id: string; name: string; is_host: boolean }[] = [];
         for (const sock of this.ctx.getWebSockets()) {
           if (sock === ws) continue;
           const att = sock.deserializeAttachment() as Attachment | null;
-          if (att?.peerId) peers.push({ peer_id: att.peerId, name: att.name });
+          if (att?.peerId && att.peerId !== msg.peer_id) {
+            peers.push({
+              peer_id: att.peerId,
+              name: att.name,
+              is_host: att.isHost,
+            });
+          }
         }
 
         // Fetch TURN credentials
@@ -181,6 +314,8 @@ export class Room extends DurableObject<Env> {
             type: "room_joined",
             room_id: msg.room_id,
             peers,
+            is_host: isHost,
+            locked,
             turn_servers: turnServers,
           }),
         );
@@ -190,6 +325,7 @@ export class Room extends DurableObject<Env> {
           type: "peer_joined",
           peer_id: msg.peer_id,
           name: msg.name,
+          is_host: isHost,
         });
         for (const sock of this.ctx.getWebSockets()) {
           if (sock === ws) continue;
@@ -202,7 +338,7 @@ export class Room extends DurableObject<Env> {
       }
 
       case "leave": {
-        this.handleDisconnect(ws);
+        await this.handleDisconnect(ws);
         ws.close(1000, "Client left");
         break;
       }
@@ -250,6 +386,69 @@ export class Room extends DurableObject<Env> {
         }
         break;
       }
+
+      case "recording_state": {
+        const att = ws.deserializeAttachment() as Attachment | null;
+        if (!att?.peerId) return;
+        // Everyone else is told, so nobody is recorded without knowing
+        this.broadcast(
+          JSON.stringify({
+            type: "peer_recording_state",
+            peer_id: att.peerId,
+            recording: msg.recording,
+          }),
+          ws,
+        );
+        break;
+      }
+
+      case "lock_room": {
+        if (!this.hostAttachment(ws)) return;
+        await this.ctx.storage.put(LOCKED_KEY, msg.locked);
+        this.broadcast(
+          JSON.stringify({ type: "room_lock_changed", locked: msg.locked }),
+        );
+        break;
+      }
+
+      case "kick_peer": {
+        const host = this.hostAttachment(ws);
+        if (!host || msg.peer_id === host.peerId) return;
+
+        await this.ctx.storage.put(kickedKey(msg.peer_id), true);
+        await this.ctx.storage.delete(`pending_leave:${msg.peer_id}`);
+
+        const target = this.findSocket(msg.peer_id);
+        if (target) {
+          try {
+            target.send(JSON.stringify({ type: "kicked" }));
+            target.close(1000, "Removed by host");
+          } catch {
+            // Socket already closed
+          }
+        }
+
+        // No reconnect grace period for a removed peer.
+        this.broadcast(
+          JSON.stringify({ type: "peer_left", peer_id: msg.peer_id }),
+          target ?? undefined,
+        );
+        break;
+      }
+
+      case "request_mute": {
+        const host = this.hostAttachment(ws);
+        if (!host) return;
+        const target = this.findSocket(msg.peer_id);
+        try {
+          target?.send(
+            JSON.stringify({ type: "mute_requested", by: host.peerId }),
+          );
+        } catch {
+          // Socket already closed
+        }
+        break;
+      }
     }
   }
 
@@ -259,22 +458,25 @@ export class Room extends DurableObject<Env> {
     reason: string,
     wasClean: boolean,
   ): Promise<void> {
-    this.handleDisconnect(ws);
+    await this.handleDisconnect(ws);
   }
 
   async webSocketError(ws: WebSocket, error: unknown): Promise<void> {
-    this.handleDisconnect(ws);
+    await this.handleDisconnect(ws);
   }
 
-  private handleDisconnect(ws: WebSocket): void {
+  private async handleDisconnect(ws: WebSocket): Promise<void> {
     const att = ws.deserializeAttachment() as Attachment | null;
     if (!att?.peerId) return;
+    // Removed peers were already announced as gone.
+    if (await this.ctx.storage.get(kickedKey(att.peerId))) return;
 
     // Store pending disconnect - give 15s grace period for reconnection
     this.ctx.storage.put(`pending_leave:${att.peerId}`, {
       peerId: att.peerId,
       timestamp: Date.now(),
-    });
+      joinedAt: att.joinedAt,
+    } satisfies PendingLeave);
 
     // Set alarm for 15 seconds
     this.ctx.storage.setAlarm(Date.now() + 15_000);
@@ -282,10 +484,9 @@ export class Room extends DurableObject<Env> {
 
   async alarm(): Promise<void> {
     // Check all pending leaves
-    const entries = await this.ctx.storage.list<{
-      peerId: string;
-      timestamp: number;
-    }>({ prefix: "pending_leave:" });
+    const entries = await this.ctx.storage.list<PendingLeave>({
+      prefix: "pending_leave:",
+    });
 
     for (const [key, pending] of entries) {
       // Check if peer reconnected (has active WebSocket with same peerId)
@@ -305,23 +506,39 @@ export class Room extends DurableObject<Env> {
       }
 
       // Peer did not reconnect - broadcast peer_left
-      const leftMsg = JSON.stringify({
-        type: "peer_left",
-        peer_id: pending.peerId,
-      });
+      this.broadcast(
+        JSON.stringify({
+          type: "peer_left",
+          peer_id: pending.peerId,
+        }),
+      );
 
-      for (const sock of this.ctx.getWebSockets()) {
-        const att = sock.deserializeAttachment() as Attachment | null;
-        if (att?.peerId) {
-          try {
-            sock.send(leftMsg);
-          } catch {
-            // Socket already closed
-          }
-        }
+      await this.ctx.storage.delete(key);
+
+      if ((await this.ctx.storage.get<string>(HOST_KEY)) === pending.peerId) {
+        await this.promoteNextHost();
       }
+    }
+  }
 
-      await this.ctx.storage.delete(key);
+  // Hand hosting to the longest-connected remaining peer.
+  private async promoteNextHost(): Promise<void> {
+    let next: { sock: WebSocket; att: Attachment } | null = null;
+    for (const sock of this.ctx.getWebSockets()) {
+      const att = sock.deserializeAttachment() as Attachment | null;
+      if (!att?.peerId) continue;
+      // Sockets from before join times were recorded count as the oldest.
+      if (!next || (att.joinedAt ?? 0) < (next.att.joinedAt ?? 0)) {
+        next = { sock, att };
+      }
     }
+    if (!next) return;
+
+    const { sock, att } = next;
+    sock.serializeAttachment({ ...att, isHost: true } satisfies Attachment);
+    await this.ctx.storage.put(HOST_KEY, att.peerId);
+    this.broadcast(
+      JSON.stringify({ type: "host_changed", peer_id: att.peerId }),
+    );
   }
 }
@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+use crate::types::SAMPLE_RATE;
+
+// ── Tuning ──
+
+/// Processing block: 10ms at 48kHz, so each 20ms capture frame is two blocks.
+const BLOCK: usize = 480;
+const FFT_LEN: usize = BLOCK * 2;
+const BINS: usize = FFT_LEN / 2 + 1;
+/// Echo path the filter can model, covering playback/capture buffering plus room reverb.
+const TAIL_MS: usize = 320;
+const PARTITIONS: usize = TAIL_MS * SAMPLE_RATE as usize / 1000 / BLOCK;
+/// NLMS step size (0 < mu < 2; lower is slower but more robust).
+const STEP_SIZE: f32 = 0.4;
+/// Regularization for the per-bin power normalization.
+const REGULARIZATION: f32 = FFT_LEN as f32 * 1e-5;
+/// Far-end peak below this (≈ -70 dBFS) means there is nothing to cancel.
+const FAR_END_SILENCE: f32 = 3e-4;
+/// Geigel double-talk threshold: near-end peak above this fraction of the
+/// recent far-end peak means the local user is talking over the remote side.
+const GEIGEL_THRESHOLD: f32 = 0.6;
+/// Blocks to keep adaptation frozen after double-talk was detected.
+const DOUBLE_TALK_HOLD_BLOCKS: u32 = 15; // 150ms
+/// Floor for the residual echo suppressor gain.
+const MIN_SUPPRESSION_GAIN: f32 = 0.1;
+
+/// Acoustic echo canceller using a partitioned-block frequency-domain
+/// adaptive filter (overlap-save, NLMS update).
+///
+/// `far_end` is what was sent to the speakers; the filter learns the path
+/// from speakers back into the microphone and subtracts its estimate of that
+/// echo from the capture signal. Adaptation freezes during double-talk so the
+/// local speaker does not get cancelled, and a mild residual suppressor cleans
+/// up what the linear filter misses while only the far end is talking.
+pub struct EchoCanceller {
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    /// Last two far-end blocks, the overlap-save input window.
+    far_window: Vec<f32>,
+    /// Spectra of the most recent far-end windows, newest first.
+    far_spectra: VecDeque<Vec<Complex<f32>>>,
+    /// Recent far-end block peaks, newest first, for double-talk detection.
+    far_peaks: VecDeque<f32>,
+    weights: Vec<Vec<Complex<f32>>>,
+    next_constrained: usize,
+    double_talk_hold: u32,
+    suppression_gain: f32,
+    time_buf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    echo_spectrum: Vec<Complex<f32>>,
+    norm: Vec<f32>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+}
+
+impl Default for EchoCanceller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EchoCanceller {
+    pub fn new() -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FFT_LEN);
+        let ifft = planner.plan_fft_inverse(FFT_LEN);
+        let scratch_fwd = fft.make_scratch_vec();
+        let scratch_inv = ifft.make_scratch_vec();
+
+        Self {
+            fft,
+            ifft,
+            far_window: vec![0.0; FFT_LEN],
+            far_spectra: (0..PARTITIONS)
+                .map(|_| vec![Complex::default(); BINS])
+                .collect(),
+            far_peaks: std::iter::repeat_n(0.0, PARTITIONS).collect(),
+            weights: vec![vec![Complex::default(); BINS]; PARTITIONS],
+            next_constrained: 0,
+            double_talk_hold: 0,
+            suppression_gain: 1.0,
+            time_buf: vec![0.0; FFT_LEN],
+            spectrum: vec![Complex::default(); BINS],
+            echo_spectrum: vec![Complex::default(); BINS],
+            norm: vec![0.0; BINS],
+            scratch_fwd,
+            scratch_inv,
+        }
+    }
+
+    /// Remove the echo of `far_end` from `mic` in place. Both slices must be
+    /// the same length and a multiple of the 10ms block size.
+    pub fn process(&mut self, mic: &mut [f32], far_end: &[f32]) {
+        debug_assert_eq!(mic.len(), far_end.len());
+        for (mic_block, far_block) in mic.chunks_exact_mut(BLOCK).zip(far_end.chunks_exact(BLOCK)) {
+            self.process_block(mic_block, far_block);
+        }
+    }
+
+    fn process_block(&mut self, mic: &mut [f32], far: &[f32]) {
+        // Slide the far-end window and transform it into the newest partition.
+        self.far_window.copy_within(BLOCK.., 0);
+        self.far_window[BLOCK..].copy_from_slice(far);
+        let mut newest = self.far_spectra.pop_back().unwrap_or_default();
+        newest.resize(BINS, Complex::default());
+        self.time_buf.copy_from_slice(&self.far_window);
+        self.forward(&mut newest);
+        self.far_spectra.push_front(newest);
+
+        let far_peak = far.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+        self.far_peaks.pop_back();
+        self.far_peaks.push_front(far_peak);
+
+        // Echo estimate: sum of every partition's filter applied to its far-end spectrum.
+        self.echo_spectrum.fill(Complex::default());
+        for (weights, spectrum) in self.weights.iter().zip(&self.far_spectra) {
+            for ((acc, w), x) in self.echo_spectrum.iter_mut().zip(weights).zip(spectrum) {
+                *acc += w * x;
+            }
+        }
+        self.spectrum.copy_from_slice(&self.echo_spectrum);
+        self.inverse();
+        let scale = 1.0 / FFT_LEN as f32;
+
+        let mut mic_energy = 0.0f32;
+        let mut echo_energy = 0.0f32;
+        let mut mic_peak = 0.0f32;
+        for (i, sample) in mic.iter_mut().enumerate() {
+            let echo = self.time_buf[BLOCK + i] * scale;
+            mic_energy += *sample * *sample;
+            echo_energy += echo * echo;
+            mic_peak = mic_peak.max(sample.abs());
+            *sample -= echo;
+        }
+
+        let recent_far_peak = self.far_peaks.iter().fold(0.0f32, |acc, &p| acc.max(p));
+        let far_active = recent_far_peak > FAR_END_SILENCE;
+        if far_active && mic_peak > GEIGEL_THRESHOLD * recent_far_peak {
+            self.double_talk_hold = DOUBLE_TALK_HOLD_BLOCKS;
+        } else {
+            self.double_talk_hold = self.double_talk_hold.saturating_sub(1);
+        }
+        let adapting = far_active && self.double_talk_hold == 0;
+
+        if adapting {
+            self.adapt(mic);
+        }
+
+        // Residual echo suppression: while only the far end talks, duck what is
+        // left in proportion to how much of the mic signal was echo.
+        let target_gain = if adapting && mic_energy > 0.0 {
+            (1.0 - echo_energy / mic_energy).clamp(MIN_SUPPRESSION_GAIN, 1.0)
+        } else {
+            1.0
+        };
+        let coeff = if target_gain < self.suppression_gain {
+            0.5
+        } else {
+            0.1
+        };
+        self.suppression_gain += coeff * (target_gain - self.suppression_gain);
+        for sample in mic.iter_mut() {
+            *sample *= self.suppression_gain;
+        }
+    }
+
+    /// NLMS update of every partition from the block's error signal.
+    fn adapt(&mut self, error: &[f32]) {
+        self.time_buf[..BLOCK].fill(0.0);
+        self.time_buf[BLOCK..].copy_from_slice(error);
+        let mut error_spectrum = std::mem::take(&mut self.spectrum);
+        self.forward(&mut error_spectrum);
+
+        self.norm.fill(REGULARIZATION);
+        for spectrum in &self.far_spectra {
+            for (n, x) in self.norm.iter_mut().zip(spectrum) {
+                *n += x.norm_sqr();
+            }
+        }
+
+        for (weights, spectrum) in self.weights.iter_mut().zip(&self.far_spectra) {
+            for (((w, x), e), n) in weights
+                .iter_mut()
+                .zip(spectrum)
+                .zip(&error_spectrum)
+                .zip(&self.norm)
+            {
+                *w += x.conj() * e * (STEP_SIZE / n);
+            }
+        }
+        self.spectrum = error_spectrum;
+
+        // Gradient constraint, one partition per block: keep each partition's
+        // impulse response to a single block so overlap-save stays linear.
+        let partition = self.next_constrained;
+        self.next_constrained = (partition + 1) % PARTITIONS;
+        self.spectrum.copy_from_slice(&self.weights[partition]);
+        self.inverse();
+        let scale = 1.0 / FFT_LEN as f32;
+        for sample in &mut self.time_buf[..BLOCK] {
+            *sample *= scale;
+        }
+        self.time_buf[BLOCK..].fill(0.0);
+        let mut constrained = std::mem::take(&mut self.weights[partition]);
+        self.forward(&mut constrained);
+        self.weights[partition] = constrained;
+    }
+
+    /// Forward FFT of `time_buf` into `out`.
+    fn forward(&mut self, out: &mut [Complex<f32>]) {
+        let _ = self
+            .fft
+            .process_with_scratch(&mut self.time_buf, out, &mut self.scratch_fwd);
+    }
+
+    /// Inverse FFT of `spectrum` into `time_buf` (unnormalized).
+    fn inverse(&mut self) {
+        self.spectrum[0].im = 0.0;
+        self.spectrum[BINS - 1].im = 0.0;
+        let _ = self.ifft.process_with_scratch(
+            &mut self.spectrum,
+            &mut self.time_buf,
+            &mut self.scratch_inv,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FRAME_SIZE;
+
+    fn noise(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s * s).sum()
+    }
+
+    #[test]
+    fn cancels_delayed_attenuated_echo() {
+        let mut aec = EchoCanceller::new();
+        let mut seed = 1u32;
+        let delay = 2_400; // 50ms speaker → mic
+        let mut far_history = vec![0.0f32; delay];
+        let mut residual = 0.0;
+        let mut echo_total = 0.0;
+
+        for frame in 0..250 {
+            let far: Vec<f32> = (0..FRAME_SIZE).map(|_| noise(&mut seed) * 0.5).collect();
+            far_history.extend_from_slice(&far);
+            let echo: Vec<f32> = far_history[..FRAME_SIZE].iter().map(|s| s * 0.3).collect();
+            far_history.drain(..FRAME_SIZE);
+
+            let mut mic = echo.clone();
+            aec.process(&mut mic, &far);
+
+            // Measure once the filter has had a few seconds to converge.
+            if frame >= 200 {
+                residual += energy(&mic);
+                echo_total += energy(&echo);
+            }
+        }
+
+        let erle_db = 10.0 * (echo_total / residual).log10();
+        assert!(
+            erle_db > 20.0,
+            "echo return loss enhancement {erle_db:.1} dB"
+        );
+    }
+
+    #[test]
+    fn passes_near_end_speech_when_far_end_is_silent() {
+        let mut aec = EchoCanceller::new();
+        let mut seed = 7u32;
+        let far = [0.0f32; FRAME_SIZE];
+
+        for _ in 0..10 {
+            let near: Vec<f32> = (0..FRAME_SIZE).map(|_| noise(&mut seed) * 0.5).collect();
+            let mut mic = near.clone();
+            aec.process(&mut mic, &far);
+            assert_eq!(mic, near);
+        }
+    }
+}
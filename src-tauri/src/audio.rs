@@ -1,56 +1,382 @@
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::SampleRate;
+use cpal::{FromSample, SampleFormat, SizedSample};
 use ringbuf::{
     traits::{Consumer, Observer, Producer, Split},
-    HeapRb,
+    HeapCons, HeapProd, HeapRb,
 };
 
 use tauri::{AppHandle, Emitter};
 
+use crate::aec::EchoCanceller;
+use crate::bitrate::{DEFAULT_EXPECTED_LOSS_PERC, DEFAULT_MAX_BITRATE};
+use crate::devices::{choose_stream_config, describe_config_range};
+use crate::resampler::{downmix_stereo, DriftController, InputConverter, OutputConverter};
+use crate::source::{AudioSource, SourceRead};
 use crate::types::{
-    AudioDevice, EncodedFrame, PcmFrame, AUDIO_CAPTURE_QUEUE_FRAMES, AUDIO_PLAYBACK_QUEUE_FRAMES,
-    EVENT_MIC_TEST_LEVEL, FRAME_SIZE, SAMPLE_RATE,
+    AudioDevice, DeviceConfig, DeviceConfigs, DeviceFormats, DeviceKind, EncodedFrame,
+    MusicModeEvent, PcmFrame, StereoFrame, AUDIO_CAPTURE_QUEUE_FRAMES,
+    AUDIO_ECHO_REFERENCE_QUEUE_FRAMES, AUDIO_PLAYBACK_QUEUE_FRAMES, AUDIO_SHARE_QUEUE_FRAMES,
+    CHANNELS, EVENT_MIC_TEST_LEVEL, FRAME_SIZE, SAMPLE_RATE,
 };
+use crate::vad::{denoise_frame, VoiceDetector};
 
 // ── AudioCapture ──
 
+/// Keep transmitting briefly after the push-to-talk key is released so the
+/// end of the last word isn't clipped.
+const PTT_RELEASE_TAIL_FRAMES: u32 = 15; // 300ms
+
+/// Encoded frames held back during DTX silence and flushed when speech
+/// starts, so the VAD's onset confirmation doesn't clip the first syllable.
+const DTX_PREROLL_FRAMES: usize = 2; // 40ms
+
+/// Shared audio quieter than this peak counts as silence and doesn't keep
+/// the transmission open on its own.
+const SHARED_AUDIO_SILENCE: f32 = 1e-3;
+
+/// Shared audio frames queued before the capture starts taking them, so
+/// jitter between the two devices' callbacks doesn't run the queue dry.
+const SHARED_AUDIO_PREBUFFER_FRAMES: usize = 2;
+/// A longer queue only builds up after a stall (e.g. while muted); it is cut
+/// back to the pre-buffer rather than played late.
+const SHARED_AUDIO_MAX_QUEUE_FRAMES: usize = 4;
+
+/// Time without a captured frame that counts as frames not delivered (the
+/// device was muted or being reopened) rather than callback jitter.
+const CAPTURE_CLOCK_GAP: Duration = Duration::from_millis(100);
+
+/// A clip played into the call, shared between the engine and the capture.
+/// File clips are `Prefetched`, so reading one never waits on the disk.
+pub type PlayingClip = Arc<std::sync::Mutex<Option<Box<dyn AudioSource + Send>>>>;
+
+/// Runtime-tunable capture processing, shared between the engine and the
+/// capture thread so changes apply without restarting the stream.
+#[derive(Clone)]
+pub struct CaptureProcessing {
+    pub noise_suppression: Arc<AtomicBool>,
+    pub echo_cancellation: Arc<AtomicBool>,
+    pub vad_threshold: Arc<AtomicU32>,
+    pub agc_enabled: Arc<AtomicBool>,
+    /// Push-to-talk mode: only transmit while `ptt_pressed` (plus a short tail).
+    pub push_to_talk: Arc<AtomicBool>,
+    pub ptt_pressed: Arc<AtomicBool>,
+    /// Opus target bitrate and expected loss (for FEC), set by the engine's
+    /// bitrate adaptation.
+    pub bitrate: Arc<AtomicU32>,
+    pub expected_loss_perc: Arc<AtomicU32>,
+    /// Music mode: no echo cancellation, noise suppression, AGC or DTX.
+    pub music_mode: Arc<AtomicBool>,
+    /// Encode music mode in stereo, set once every peer has accepted it.
+    pub stereo: Arc<AtomicBool>,
+    /// System audio from `SharedAudio`, mixed in after voice processing. The
+    /// channel outlives captures, so a device switch keeps the share going;
+    /// [`CaptureProcessing::clear_shared_audio`] empties it when sharing stops.
+    pub shared_audio_tx: flume::Sender<StereoFrame>,
+    pub shared_audio_rx: flume::Receiver<StereoFrame>,
+    pub shared_audio_gain: Arc<AtomicU32>,
+    /// Clip being played into the call, read one frame per captured frame.
+    pub clip: PlayingClip,
+    /// Timestamps for captured frames, continued by every capture.
+    pub clock: CaptureClock,
+}
+
+impl Default for CaptureProcessing {
+    fn default() -> Self {
+        let (shared_audio_tx, shared_audio_rx) =
+            flume::bounded::<StereoFrame>(AUDIO_SHARE_QUEUE_FRAMES);
+        Self {
+            noise_suppression: Arc::new(AtomicBool::new(true)),
+            echo_cancellation: Arc::new(AtomicBool::new(true)),
+            vad_threshold: Arc::new(AtomicU32::new(0.01f32.to_bits())),
+            agc_enabled: Arc::new(AtomicBool::new(true)),
+            push_to_talk: Arc::new(AtomicBool::new(false)),
+            ptt_pressed: Arc::new(AtomicBool::new(false)),
+            bitrate: Arc::new(AtomicU32::new(DEFAULT_MAX_BITRATE)),
+            expected_loss_perc: Arc::new(AtomicU32::new(DEFAULT_EXPECTED_LOSS_PERC)),
+            music_mode: Arc::new(AtomicBool::new(false)),
+            stereo: Arc::new(AtomicBool::new(false)),
+            shared_audio_tx,
+            shared_audio_rx,
+            shared_audio_gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            clip: Arc::new(std::sync::Mutex::new(None)),
+            clock: CaptureClock::default(),
+        }
+    }
+}
+
+/// The capture clock behind `EncodedFrame::timestamp`. It outlives a single
+/// capture, so a reopened device or an input file continues it, and it
+/// counts the frames a muted device didn't deliver from the time that passed.
+#[derive(Clone, Default)]
+pub struct CaptureClock {
+    state: Arc<std::sync::Mutex<ClockState>>,
+}
+
+#[derive(Default)]
+struct ClockState {
+    next: u32,
+    last_frame: Option<Instant>,
+}
+
+impl CaptureClock {
+    /// Timestamp for a frame captured now.
+    pub fn stamp(&self) -> u32 {
+        self.stamp_at(Instant::now())
+    }
+
+    fn stamp_at(&self, now: Instant) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        if let Some(last) = state.last_frame {
+            let elapsed = now.saturating_duration_since(last);
+            if elapsed > CAPTURE_CLOCK_GAP {
+                // This frame accounts for the last 20ms of the gap
+                let missed = (elapsed.as_millis() / 20).saturating_sub(1) as u32;
+                state.next = state
+                    .next
+                    .wrapping_add(missed.wrapping_mul(FRAME_SIZE as u32));
+            }
+        }
+        state.last_frame = Some(now);
+        let timestamp = state.next;
+        state.next = timestamp.wrapping_add(FRAME_SIZE as u32);
+        timestamp
+    }
+}
+
+impl CaptureProcessing {
+    /// Drop shared audio still queued, so a later share doesn't start with
+    /// sound from the last one.
+    pub fn clear_shared_audio(&self) {
+        self.shared_audio_rx.drain();
+    }
+
+    pub fn music_mode_state(&self) -> MusicModeEvent {
+        MusicModeEvent {
+            enabled: self.music_mode.load(Ordering::Relaxed),
+            stereo: self.stereo.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Automatic gain control toward a fixed RMS level: fast attack so loud
+/// speech is pulled down quickly, slow release so pauses don't pump noise up.
+struct AutoGain {
+    gain: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+}
+
+impl AutoGain {
+    const TARGET_RMS: f32 = 0.1;
+
+    fn new() -> Self {
+        let frames_per_sec = SAMPLE_RATE as f32 / FRAME_SIZE as f32;
+        Self {
+            gain: 1.0,
+            attack_coeff: 1.0 - (-1.0 / (0.005 * frames_per_sec)).exp(),
+            release_coeff: 1.0 - (-1.0 / (0.200 * frames_per_sec)).exp(),
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        if rms > 1e-6 {
+            let desired = (Self::TARGET_RMS / rms).clamp(0.25, 31.6);
+            let coeff = if desired < self.gain {
+                self.attack_coeff
+            } else {
+                self.release_coeff
+            };
+            self.gain += coeff * (desired - self.gain);
+        }
+        for s in frame.iter_mut() {
+            *s = (*s * self.gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Copy of the processed microphone signal, sent to the engine while the
+/// call is being recorded.
+struct LocalTap {
+    enabled: Arc<AtomicBool>,
+    tx: flume::Sender<PcmFrame>,
+}
+
+/// What a capture reads from.
+enum CaptureInput {
+    /// A device (`None` = system default), opened with the config chosen for it.
+    Device(Option<String>, DeviceConfigs),
+    Source(Box<dyn AudioSource + Send>),
+}
+
+/// An input device, read through the ring buffer its stream fills. Audio is
+/// converted to 48kHz and cut into exact 20ms frames, whatever the device rate
+/// and callback sizes.
+struct DeviceSource {
+    _stream: cpal::Stream,
+    consumer: HeapCons<f32>,
+    converter: InputConverter,
+    device_buf: Vec<f32>,
+    device_rate: u32,
+    device_channels: u16,
+    failed: Arc<AtomicBool>,
+}
+
+impl DeviceSource {
+    fn open(
+        device_name: Option<&str>,
+        device_configs: &DeviceConfigs,
+        muted: Arc<AtomicBool>,
+        failed: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let device = find_input_device(device_name)?;
+        tracing::info!("Using input device: {:?}", device.name());
+
+        // The device's default config, unless another was chosen for it
+        let (config, sample_format) = input_stream_config(&device, device_configs)?;
+        let device_rate = config.sample_rate.0;
+        let device_channels = config.channels;
+
+        tracing::info!(
+            "Input device config: {}Hz, {} channels, {} (target: {}Hz mono)",
+            device_rate,
+            device_channels,
+            sample_format,
+            SAMPLE_RATE
+        );
+
+        // Size ring buffer for the device rate (enough for ~200ms)
+        let ring = HeapRb::<f32>::new(ring_capacity(&config));
+        let (mut producer, consumer) = ring.split();
+
+        let failed_flag = Arc::clone(&failed);
+        let stream = build_input_stream(
+            &device,
+            &config,
+            sample_format,
+            move |data| {
+                if muted.load(Ordering::Relaxed) {
+                    return;
+                }
+                push_whole(&mut producer, data);
+            },
+            move |err| {
+                // Treated as fatal; the engine reopens the capture
+                tracing::error!("Audio input error: {err}");
+                failed_flag.store(true, Ordering::Relaxed);
+            },
+        )?;
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            consumer,
+            converter: InputConverter::new(device_rate, device_channels, 1),
+            device_buf: vec![0.0f32; (device_rate as usize / 50) * device_channels as usize],
+            device_rate,
+            device_channels,
+            failed,
+        })
+    }
+}
+
+impl AudioSource for DeviceSource {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead {
+        let outputs = frame.len() / FRAME_SIZE;
+        if outputs != self.converter.output_channels() {
+            self.converter =
+                InputConverter::new(self.device_rate, self.device_channels, outputs as u16);
+        }
+        loop {
+            if self.converter.pop_frame(frame) {
+                return SourceRead::Frame;
+            }
+            if !read_device_input(
+                &mut self.consumer,
+                &mut self.device_buf,
+                &mut self.converter,
+            ) {
+                // Nothing more will arrive from a failed stream
+                return if self.failed.load(Ordering::Relaxed) {
+                    SourceRead::Ended
+                } else {
+                    SourceRead::Pending
+                };
+            }
+        }
+    }
+}
+
 pub struct AudioCapture {
     muted: Arc<AtomicBool>,
     speaking: Arc<AtomicBool>,
     pub encoded_rx: flume::Receiver<EncodedFrame>,
+    far_end_tx: flume::Sender<PcmFrame>,
+    recording: Arc<AtomicBool>,
+    local_rx: flume::Receiver<PcmFrame>,
+    failed: Arc<AtomicBool>,
 }
 
 impl AudioCapture {
     pub fn new(
         device_name: Option<String>,
-        noise_suppression: Arc<AtomicBool>,
-        vad_threshold: Arc<AtomicU32>,
-        agc_enabled: Arc<AtomicBool>,
+        device_configs: DeviceConfigs,
+        processing: CaptureProcessing,
     ) -> Result<Self> {
+        Self::spawn(
+            CaptureInput::Device(device_name, device_configs),
+            processing,
+        )
+    }
+
+    /// Capture from something other than a device, e.g. a file for a
+    /// repeatable input. It should be paced in real time.
+    pub fn with_source(
+        source: Box<dyn AudioSource + Send>,
+        processing: CaptureProcessing,
+    ) -> Result<Self> {
+        Self::spawn(CaptureInput::Source(source), processing)
+    }
+
+    fn spawn(input: CaptureInput, processing: CaptureProcessing) -> Result<Self> {
         let muted = Arc::new(AtomicBool::new(false));
         let speaking = Arc::new(AtomicBool::new(false));
         let (encoded_tx, encoded_rx) = flume::bounded::<EncodedFrame>(AUDIO_CAPTURE_QUEUE_FRAMES);
+        let (far_end_tx, far_end_rx) =
+            flume::bounded::<PcmFrame>(AUDIO_ECHO_REFERENCE_QUEUE_FRAMES);
+        let recording = Arc::new(AtomicBool::new(false));
+        let (local_tx, local_rx) = flume::bounded::<PcmFrame>(AUDIO_CAPTURE_QUEUE_FRAMES);
         let muted_flag = Arc::clone(&muted);
         let speaking_flag = Arc::clone(&speaking);
+        let local_tap = LocalTap {
+            enabled: Arc::clone(&recording),
+            tx: local_tx,
+        };
+        let failed = Arc::new(AtomicBool::new(false));
+        let failed_flag = Arc::clone(&failed);
 
         std::thread::Builder::new()
             .name("audio-capture".into())
             .spawn(move || {
                 if let Err(e) = run_capture(
-                    device_name,
+                    input,
                     muted_flag,
                     speaking_flag,
                     encoded_tx,
-                    noise_suppression,
-                    vad_threshold,
-                    agc_enabled,
+                    far_end_rx,
+                    local_tap,
+                    processing,
+                    Arc::clone(&failed_flag),
                 ) {
                     tracing::error!("Audio capture thread error: {e}");
+                    failed_flag.store(true, Ordering::Relaxed);
                 }
             })?;
 
@@ -58,283 +384,437 @@ impl AudioCapture {
             muted,
             speaking,
             encoded_rx,
+            far_end_tx,
+            recording,
+            local_rx,
+            failed,
         })
     }
 
-    pub fn set_muted(&self, muted: bool) {
-        self.muted.store(muted, Ordering::Relaxed);
+    /// The input stream errored or couldn't be opened (e.g. the device was
+    /// unplugged). No more frames will come; open a new capture.
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
     }
 
-    pub fn is_speaking(&self) -> bool {
-        self.speaking.load(Ordering::Relaxed)
+    /// Start or stop copying the processed mic signal for a recording.
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Relaxed);
+        if !recording {
+            while self.local_rx.try_recv().is_ok() {}
+        }
     }
-}
 
-fn mono_sample_at(input: &[f32], channels: usize, frame_idx: usize) -> f32 {
-    let start = frame_idx * channels;
-    if channels == 1 {
-        input[start]
-    } else {
-        input[start..start + channels].iter().copied().sum::<f32>() / channels as f32
+    /// The next processed mic frame for the recording, or silence while
+    /// muted or gated by push-to-talk. Call once per mixer tick.
+    pub fn take_local_frame(&self) -> PcmFrame {
+        while self.local_rx.len() > 1 {
+            let _ = self.local_rx.try_recv();
+        }
+        self.local_rx.try_recv().unwrap_or([0.0; FRAME_SIZE])
     }
-}
-
-fn convert_input_to_mono_48k(
-    input: &[f32],
-    input_rate: u32,
-    input_channels: u16,
-    output: &mut [f32],
-) {
-    output.fill(0.0);
 
-    let channels = usize::from(input_channels.max(1));
-    let input_frames = input.len() / channels;
-    if input_frames == 0 {
-        return;
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
     }
 
-    if input_rate == SAMPLE_RATE {
-        let frames = input_frames.min(output.len());
-        if channels == 1 {
-            output[..frames].copy_from_slice(&input[..frames]);
-        } else {
-            for (frame_idx, out_sample) in output.iter_mut().take(frames).enumerate() {
-                *out_sample = mono_sample_at(input, channels, frame_idx);
-            }
-        }
-        return;
+    pub fn is_speaking(&self) -> bool {
+        self.speaking.load(Ordering::Relaxed)
     }
 
-    let ratio = SAMPLE_RATE as f64 / input_rate as f64;
-    for (i, out_sample) in output.iter_mut().enumerate() {
-        let src_pos = i as f64 / ratio;
-        let idx = src_pos as usize;
-        if idx >= input_frames {
-            break;
-        }
-
-        let frac = src_pos - idx as f64;
-        let s0 = mono_sample_at(input, channels, idx);
-        let s1 = if idx + 1 < input_frames {
-            mono_sample_at(input, channels, idx + 1)
-        } else {
-            s0
-        };
-        *out_sample = (s0 as f64 * (1.0 - frac) + s1 as f64 * frac) as f32;
+    /// Feed the mixed remote audio sent to the speakers, used as the echo
+    /// canceller's reference. Call once per mixer tick, silence included.
+    pub fn push_far_end(&self, frame: &PcmFrame) {
+        let _ = self.far_end_tx.try_send(*frame);
     }
 }
 
-fn convert_mono_48k_to_output(
-    input: &[f32],
-    output_rate: u32,
-    output_channels: u16,
-    output: &mut Vec<f32>,
-) {
-    output.clear();
+/// Audio bandwidth worth coding at a bitrate. At low rates, narrowing the
+/// band spends the bits on intelligibility instead of thin highs.
+fn bandwidth_for_bitrate(bitrate: u32) -> opus::Bandwidth {
+    match bitrate {
+        0..14_000 => opus::Bandwidth::Wideband,
+        14_000..24_000 => opus::Bandwidth::Superwideband,
+        _ => opus::Bandwidth::Fullband,
+    }
+}
 
-    let channels = usize::from(output_channels.max(1));
-    let output_frames = if output_rate == SAMPLE_RATE {
-        input.len()
+/// Opus encoder for the call: voice in mono, or music in mono or stereo.
+/// Bitrate, bandwidth and expected loss follow the engine's adaptation and
+/// are applied per frame.
+fn capture_encoder(music: bool, stereo: bool) -> Result<opus::Encoder> {
+    let channels = if stereo {
+        opus::Channels::Stereo
     } else {
-        (input.len() as f64 * output_rate as f64 / SAMPLE_RATE as f64) as usize
+        opus::Channels::Mono
     };
-
-    output.resize(output_frames * channels, 0.0);
-    if input.is_empty() {
-        return;
-    }
-
-    if output_rate == SAMPLE_RATE {
-        if channels == 1 {
-            output[..input.len()].copy_from_slice(input);
-        } else {
-            for (frame_idx, &sample) in input.iter().enumerate() {
-                let start = frame_idx * channels;
-                output[start..start + channels].fill(sample);
-            }
-        }
-        return;
+    let mut encoder = opus::Encoder::new(SAMPLE_RATE, channels, opus::Application::Audio)
+        .map_err(|e| anyhow::anyhow!("Failed to create opus encoder: {e}"))?;
+    let _ = encoder.set_inband_fec(true);
+    let _ = encoder.set_vbr(true);
+    let _ = encoder.set_complexity(10);
+    if music {
+        let _ = encoder.set_signal(opus::Signal::Music);
     }
+    Ok(encoder)
+}
 
-    let ratio = output_rate as f64 / SAMPLE_RATE as f64;
-    for frame_idx in 0..output_frames {
-        let src_pos = frame_idx as f64 / ratio;
-        let idx = src_pos as usize;
-        let frac = src_pos - idx as f64;
-        let s0 = *input.get(idx).unwrap_or(&0.0);
-        let s1 = *input.get(idx + 1).unwrap_or(&s0);
-        let sample = (s0 as f64 * (1.0 - frac) + s1 as f64 * frac) as f32;
-
-        let start = frame_idx * channels;
-        output[start..start + channels].fill(sample);
+fn describe_mode(music: bool, stereo: bool) -> &'static str {
+    match (music, stereo) {
+        (false, _) => "voice",
+        (true, false) => "mono music",
+        (true, true) => "stereo music",
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_capture(
-    device_name: Option<String>,
+    input: CaptureInput,
     muted: Arc<AtomicBool>,
     speaking: Arc<AtomicBool>,
     encoded_tx: flume::Sender<EncodedFrame>,
-    noise_suppression: Arc<AtomicBool>,
-    vad_threshold: Arc<AtomicU32>,
-    agc_enabled: Arc<AtomicBool>,
+    far_end_rx: flume::Receiver<PcmFrame>,
+    local_tap: LocalTap,
+    processing: CaptureProcessing,
+    failed: Arc<AtomicBool>,
 ) -> Result<()> {
-    let host = cpal::default_host();
-    let device = if let Some(ref name) = device_name {
-        host.input_devices()
-            .context("Failed to enumerate input devices")?
-            .find(|d| d.name().ok().as_deref() == Some(name))
-            .with_context(|| format!("Input device '{}' not found, falling back to default", name))
-            .or_else(|e| {
-                tracing::warn!("{e}");
-                host.default_input_device()
-                    .context("No input audio device found")
-            })?
-    } else {
-        host.default_input_device()
-            .context("No input audio device found")?
+    let mut source: Box<dyn AudioSource> = match input {
+        CaptureInput::Device(device_name, device_configs) => Box::new(DeviceSource::open(
+            device_name.as_deref(),
+            &device_configs,
+            Arc::clone(&muted),
+            Arc::clone(&failed),
+        )?),
+        CaptureInput::Source(source) => source,
     };
 
-    tracing::info!("Using input device: {:?}", device.name());
+    // Opus encoder at 48kHz, rebuilt whenever music mode or stereo changes
+    let mut encoder_mode = (false, false);
+    let mut encoder = capture_encoder(false, false)?;
+    let mut applied_bitrate = 0u32;
+    let mut applied_loss_perc = u32::MAX;
 
-    // Query the device's default config instead of hardcoding
-    let default_config = device.default_input_config()?;
-    let device_rate = default_config.sample_rate().0;
-    let device_channels = default_config.channels();
+    // Noise suppression (RNNoise), whose voice probability also drives the VAD
+    let mut denoise = nnnoiseless::DenoiseState::new();
+    let mut vad = VoiceDetector::default();
 
-    tracing::info!(
-        "Input device config: {}Hz, {} channels (target: {}Hz mono)",
-        device_rate,
-        device_channels,
-        SAMPLE_RATE
-    );
+    // Echo cancellation against the far-end signal the engine sends to playback
+    let mut echo_canceller = EchoCanceller::new();
+    let mut far_end: PcmFrame = [0.0; FRAME_SIZE];
 
-    let config = cpal::StreamConfig {
-        channels: device_channels,
-        sample_rate: SampleRate(device_rate),
-        buffer_size: cpal::BufferSize::Default,
-    };
+    // Input comes in 48kHz frames, mono or stereo for stereo music
+    let mut mono_48k_buf: PcmFrame = [0.0; FRAME_SIZE]; // 960 samples = 20ms @ 48kHz
+    let mut stereo_buf: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+    let mut clip_buf: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+    let mut opus_buf = [0u8; 4000];
 
-    // Size ring buffer for the device rate (enough for ~200ms)
-    let ring_size = (device_rate as usize / 5) * device_channels as usize;
-    let ring = HeapRb::<f32>::new(ring_size);
-    let (mut producer, mut consumer) = ring.split();
+    let mut agc = AutoGain::new();
 
-    let muted_flag = Arc::clone(&muted);
+    // Push-to-talk release tail, in frames still to send after the key went up
+    let mut ptt_tail: u32 = 0;
 
-    let stream = device.build_input_stream(
-        &config,
-        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            if muted_flag.load(Ordering::Relaxed) {
-                return;
+    // Shared audio is only taken once its queue holds the pre-buffer
+    let mut shared_primed = false;
+
+    // Discontinuous transmission: nothing is sent while the VAD reports silence.
+    // The capture clock keeps counting so receivers see the gap as silence, not loss.
+    let mut transmitting = false;
+    let mut preroll: VecDeque<EncodedFrame> = VecDeque::with_capacity(DTX_PREROLL_FRAMES + 1);
+
+    loop {
+        let music = processing.music_mode.load(Ordering::Relaxed);
+        let stereo = music && processing.stereo.load(Ordering::Relaxed);
+        if (music, stereo) != encoder_mode {
+            encoder = capture_encoder(music, stereo)?;
+            encoder_mode = (music, stereo);
+            applied_bitrate = 0;
+            applied_loss_perc = u32::MAX;
+            tracing::info!("Capture switched to {}", describe_mode(music, stereo));
+        }
+
+        // Step 1: Take the next 48kHz frame, without per-frame allocation
+        let input_frame: &mut [f32] = if stereo {
+            &mut stereo_buf
+        } else {
+            &mut mono_48k_buf
+        };
+        match source.read_frame(input_frame) {
+            SourceRead::Frame => {}
+            SourceRead::Pending => {
+                // Nobody is listening once the engine replaced this capture
+                if encoded_tx.is_disconnected() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+                continue;
             }
-            let _ = producer.push_slice(data);
-        },
-        move |err| {
-            tracing::error!("Audio input error: {err}");
-        },
-        None,
-    )?;
-    stream.play()?;
+            // Nothing more will come; the engine reopens the capture
+            SourceRead::Ended => {
+                failed.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+        // Muted frames count too. A device stops delivering while muted,
+        // other sources don't
+        let timestamp = processing.clock.stamp();
+        if muted.load(Ordering::Relaxed) {
+            continue;
+        }
+        // Level, voice detection and the recording use the mono mix
+        if stereo {
+            downmix_stereo(&stereo_buf, &mut mono_48k_buf);
+        }
 
-    // Opus encoder - always 48kHz mono
-    let mut encoder =
-        opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Audio)
-            .map_err(|e| anyhow::anyhow!("Failed to create opus encoder: {e}"))?;
+        // Step 1b: Echo cancellation (before noise suppression and AGC alter the echo path).
+        // Drain the reference every frame so it stays aligned even while disabled, and
+        // drop any backlog built up while the mic was muted.
+        while far_end_rx.len() > 2 {
+            let _ = far_end_rx.try_recv();
+        }
+        match far_end_rx.try_recv() {
+            Ok(frame) => far_end = frame,
+            Err(_) => far_end.fill(0.0),
+        }
+        // Voice processing would mangle music, so music mode skips it
+        if processing.echo_cancellation.load(Ordering::Relaxed) && !music {
+            echo_canceller.process(&mut mono_48k_buf, &far_end);
+        }
 
-    let _ = encoder.set_bitrate(opus::Bitrate::Bits(64_000));
-    let _ = encoder.set_inband_fec(true);
-    let _ = encoder.set_packet_loss_perc(10);
-    let _ = encoder.set_bandwidth(opus::Bandwidth::Fullband);
-    let _ = encoder.set_vbr(true);
-    let _ = encoder.set_complexity(10);
+        // Step 2: Noise suppression. RNNoise runs even when suppression is off,
+        // since its voice probability feeds the VAD.
+        let voice_probability = denoise_frame(
+            &mut denoise,
+            &mut mono_48k_buf,
+            processing.noise_suppression.load(Ordering::Relaxed) && !music,
+        );
 
-    // Noise suppression (RNNoise) - works on 480-sample frames in i16 range
-    const DENOISE_FRAME: usize = nnnoiseless::FRAME_SIZE; // 480
-    let mut denoise = nnnoiseless::DenoiseState::new();
-    let mut denoise_in = [0.0f32; DENOISE_FRAME];
-    let mut denoise_out = [0.0f32; DENOISE_FRAME];
+        // Step 3: Automatic Gain Control (after noise suppression, before VAD)
+        if processing.agc_enabled.load(Ordering::Relaxed) && !music {
+            agc.process(&mut mono_48k_buf);
+        }
 
-    // We need to read device frames, convert to mono 48kHz, then encode in 20ms chunks.
-    // Device frame size in samples (interleaved): 20ms worth at device rate * channels
-    let device_frame_samples = (device_rate as usize / 50) * device_channels as usize;
-    let mut device_buf = vec![0.0f32; device_frame_samples];
-    let mut mono_48k_buf: PcmFrame = [0.0; FRAME_SIZE]; // 960 samples = 20ms @ 48kHz
-    let mut opus_buf = [0u8; 4000];
+        // Step 4: Voice activity detection (level gate measured after AGC)
+        let peak = mono_48k_buf.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
+        let threshold = f32::from_bits(processing.vad_threshold.load(Ordering::Relaxed));
+        let is_speech = vad.update(voice_probability, peak, threshold);
+        speaking.store(is_speech, Ordering::Relaxed);
+
+        // Step 5: Shared system audio and clips, mixed in after voice
+        // processing, which would treat them as noise. The shared audio's
+        // thread keeps its queue level against drift between the devices.
+        let shared = next_shared_frame(&processing.shared_audio_rx, &mut shared_primed);
+        let shared_active = shared.as_ref().is_some_and(|frame| {
+            frame.iter().map(|s| s.abs()).fold(0.0f32, f32::max) > SHARED_AUDIO_SILENCE
+        });
+        let clip_playing = next_clip_frame(&processing.clip, &mut clip_buf);
+        let mixing = shared_active || clip_playing;
+
+        // Step 5a: Push-to-talk gate. It closes the mic only; shared audio
+        // and clips still go out while they play.
+        let push_to_talk = processing.push_to_talk.load(Ordering::Relaxed);
+        if push_to_talk {
+            if processing.ptt_pressed.load(Ordering::Relaxed) {
+                ptt_tail = PTT_RELEASE_TAIL_FRAMES;
+            } else if ptt_tail > 0 {
+                ptt_tail -= 1;
+            } else {
+                speaking.store(false, Ordering::Relaxed);
+                if !mixing {
+                    transmitting = false;
+                    preroll.clear();
+                    continue;
+                }
+                mono_48k_buf.fill(0.0);
+                stereo_buf.fill(0.0);
+            }
+        }
 
-    // AGC state
-    let mut agc_gain: f32 = 1.0;
-    let target_rms: f32 = 0.1;
-    let attack_coeff: f32 = 1.0 - (-1.0 / (0.005 * SAMPLE_RATE as f32 / FRAME_SIZE as f32)).exp();
-    let release_coeff: f32 = 1.0 - (-1.0 / (0.200 * SAMPLE_RATE as f32 / FRAME_SIZE as f32)).exp();
+        if let Some(frame) = shared {
+            let gain = f32::from_bits(processing.shared_audio_gain.load(Ordering::Relaxed));
+            mix_into_capture(
+                &frame,
+                gain,
+                &mut mono_48k_buf,
+                stereo.then_some(&mut stereo_buf),
+            );
+        }
+        if clip_playing {
+            mix_into_capture(
+                &clip_buf,
+                1.0,
+                &mut mono_48k_buf,
+                stereo.then_some(&mut stereo_buf),
+            );
+        }
 
-    loop {
-        if consumer.occupied_len() < device_frame_samples {
-            std::thread::sleep(std::time::Duration::from_millis(5));
-            continue;
+        // Step 5b: Copy what the others hear into the call recording
+        if local_tap.enabled.load(Ordering::Relaxed) {
+            let _ = local_tap.tx.try_send(mono_48k_buf);
         }
 
-        consumer.pop_slice(&mut device_buf);
+        // Step 5c: Follow the adaptive bitrate target
+        let bitrate = processing.bitrate.load(Ordering::Relaxed);
+        if bitrate != applied_bitrate {
+            let _ = encoder.set_bitrate(opus::Bitrate::Bits(bitrate as i32));
+            let _ = encoder.set_bandwidth(bandwidth_for_bitrate(bitrate));
+            tracing::debug!("Opus bitrate {} kbps", bitrate / 1000);
+            applied_bitrate = bitrate;
+        }
+        let loss_perc = processing.expected_loss_perc.load(Ordering::Relaxed);
+        if loss_perc != applied_loss_perc {
+            let _ = encoder.set_packet_loss_perc(loss_perc as i32);
+            applied_loss_perc = loss_perc;
+        }
 
-        // Step 1: Convert device input to mono 48kHz without per-frame allocation.
-        convert_input_to_mono_48k(&device_buf, device_rate, device_channels, &mut mono_48k_buf);
+        // Step 6: Opus encode (every frame, so encoder state stays continuous across DTX gaps)
+        let pcm: &[f32] = if stereo { &stereo_buf } else { &mono_48k_buf };
+        let frame = match encoder.encode_float(pcm, &mut opus_buf) {
+            Ok(len) => EncodedFrame {
+                data: Bytes::copy_from_slice(&opus_buf[..len]),
+                timestamp,
+                talkspurt_start: false,
+            },
+            Err(e) => {
+                tracing::warn!("Opus encode error: {e}");
+                continue;
+            }
+        };
 
-        // Step 2: Noise suppression (two 480-sample frames per 960-sample Opus frame)
-        if noise_suppression.load(Ordering::Relaxed) {
-            for chunk_idx in 0..2 {
-                let offset = chunk_idx * DENOISE_FRAME;
-                for i in 0..DENOISE_FRAME {
-                    // nnnoiseless expects i16-range floats [-32768, 32767]
-                    denoise_in[i] = mono_48k_buf[offset + i] * 32767.0;
-                }
-                denoise.process_frame(&mut denoise_out, &denoise_in);
-                for i in 0..DENOISE_FRAME {
-                    // Convert back to [-1.0, 1.0]
-                    mono_48k_buf[offset + i] = denoise_out[i] / 32767.0;
-                }
+        // Step 7: DTX. Push-to-talk transmits everything while open, and music
+        // mode and playing shared audio or clips always; otherwise silence is held in the
+        // pre-roll and only speech goes out. The VAD's hangover sends a little
+        // background noise at the end of each talkspurt, which receivers use to
+        // set their comfort noise level.
+        preroll.push_back(frame);
+        if !is_speech && !push_to_talk && !music && !mixing {
+            transmitting = false;
+            if preroll.len() > DTX_PREROLL_FRAMES {
+                preroll.pop_front();
             }
+            continue;
         }
 
-        // Step 3: Automatic Gain Control (after noise suppression, before VAD)
-        if agc_enabled.load(Ordering::Relaxed) {
-            let rms = (mono_48k_buf.iter().map(|s| s * s).sum::<f32>() / FRAME_SIZE as f32).sqrt();
-            if rms > 1e-6 {
-                let desired = (target_rms / rms).clamp(0.25, 31.6);
-                let coeff = if desired < agc_gain {
-                    attack_coeff
-                } else {
-                    release_coeff
-                };
-                agc_gain += coeff * (desired - agc_gain);
+        if !transmitting {
+            transmitting = true;
+            if let Some(first) = preroll.front_mut() {
+                first.talkspurt_start = true;
             }
-            for s in mono_48k_buf.iter_mut() {
-                *s = (*s * agc_gain).clamp(-1.0, 1.0);
+        }
+        let mut disconnected = false;
+        for frame in preroll.drain(..) {
+            match encoded_tx.try_send(frame) {
+                Ok(()) | Err(flume::TrySendError::Full(_)) => {}
+                Err(flume::TrySendError::Disconnected(_)) => disconnected = true,
             }
         }
+        if disconnected {
+            break;
+        }
+    }
 
-        // Step 4: Voice activity detection (after AGC)
-        let peak = mono_48k_buf.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
-        let threshold = f32::from_bits(vad_threshold.load(Ordering::Relaxed));
-        speaking.store(peak > threshold, Ordering::Relaxed);
-
-        // Step 5: Opus encode
-        match encoder.encode_float(&mono_48k_buf, &mut opus_buf) {
-            Ok(len) => {
-                let frame = EncodedFrame {
-                    data: Bytes::copy_from_slice(&opus_buf[..len]),
-                };
-                match encoded_tx.try_send(frame) {
-                    Ok(()) | Err(flume::TrySendError::Full(_)) => {}
-                    Err(flume::TrySendError::Disconnected(_)) => break,
-                }
+    Ok(())
+}
+
+/// The next frame of shared audio, once the pre-buffer has filled. Running
+/// dry fills the pre-buffer again before more is taken.
+fn next_shared_frame(rx: &flume::Receiver<StereoFrame>, primed: &mut bool) -> Option<StereoFrame> {
+    if rx.len() > SHARED_AUDIO_MAX_QUEUE_FRAMES {
+        while rx.len() > SHARED_AUDIO_PREBUFFER_FRAMES {
+            let _ = rx.try_recv();
+        }
+    }
+    if !*primed {
+        if rx.len() < SHARED_AUDIO_PREBUFFER_FRAMES {
+            return None;
+        }
+        *primed = true;
+    }
+    let frame = rx.try_recv().ok();
+    *primed = frame.is_some();
+    frame
+}
+
+/// The next frame of the clip being played, if any. A clip that ended is
+/// dropped.
+fn next_clip_frame(clip: &PlayingClip, frame: &mut StereoFrame) -> bool {
+    // Never wait for the engine on the capture thread
+    let Ok(mut clip) = clip.try_lock() else {
+        return false;
+    };
+    let Some(source) = clip.as_mut() else {
+        return false;
+    };
+    match source.read_frame(frame) {
+        SourceRead::Frame => true,
+        SourceRead::Pending => false,
+        SourceRead::Ended => {
+            *clip = None;
+            false
+        }
+    }
+}
+
+/// Add a frame of shared audio or a clip to the processed mic signal: into
+/// the stereo frame that's sent when there is one (the mono frame follows as
+/// its mix), otherwise as a mono mix.
+fn mix_into_capture(
+    shared: &StereoFrame,
+    gain: f32,
+    mono: &mut PcmFrame,
+    stereo: Option<&mut StereoFrame>,
+) {
+    match stereo {
+        Some(stereo) => {
+            for (s, shared) in stereo.iter_mut().zip(shared) {
+                *s = (*s + shared * gain).clamp(-1.0, 1.0);
             }
-            Err(e) => {
-                tracing::warn!("Opus encode error: {e}");
+            downmix_stereo(stereo, mono);
+        }
+        None => {
+            for (s, pair) in mono.iter_mut().zip(shared.chunks_exact(2)) {
+                *s = (*s + (pair[0] + pair[1]) * 0.5 * gain).clamp(-1.0, 1.0);
             }
         }
     }
+}
 
-    Ok(())
+/// Samples (interleaved) a stream's ring buffer holds: ~200ms, and at least
+/// two callbacks' worth when a fixed buffer size was chosen.
+fn ring_capacity(config: &cpal::StreamConfig) -> usize {
+    let mut frames = config.sample_rate.0 as usize / 5;
+    if let cpal::BufferSize::Fixed(buffer_frames) = config.buffer_size {
+        frames = frames.max(2 * buffer_frames as usize);
+    }
+    frames * usize::from(config.channels.max(1))
+}
+
+/// Queue samples only if all of them fit, so a full ring buffer drops whole
+/// chunks instead of splitting interleaved frames across channels. Returns
+/// false if the chunk was dropped.
+fn push_whole(producer: &mut HeapProd<f32>, data: &[f32]) -> bool {
+    if producer.vacant_len() < data.len() {
+        return false;
+    }
+    producer.push_slice(data);
+    true
+}
+
+/// Move the interleaved device samples waiting in the ring buffer into the
+/// converter, a whole number of device frames at a time. Returns false if
+/// nothing was waiting.
+fn read_device_input(
+    consumer: &mut HeapCons<f32>,
+    buf: &mut [f32],
+    converter: &mut InputConverter,
+) -> bool {
+    let channels = converter.channels();
+    let available = consumer.occupied_len().min(buf.len()) / channels * channels;
+    if available == 0 {
+        return false;
+    }
+    let read = consumer.pop_slice(&mut buf[..available]);
+    converter.push(&buf[..read]);
+    true
 }
 
 // ── Device enumeration ──
@@ -381,34 +861,439 @@ pub fn list_output_devices() -> Vec<AudioDevice> {
         .collect()
 }
 
+/// Default and supported stream configs of a device, for choosing one.
+pub fn device_formats(kind: DeviceKind, device_name: Option<&str>) -> Result<DeviceFormats> {
+    let (default, supported) = match kind {
+        DeviceKind::Input => {
+            let device = find_input_device(device_name)?;
+            let supported: Vec<_> = device.supported_input_configs()?.collect();
+            (device.default_input_config()?, supported)
+        }
+        DeviceKind::Output => {
+            let device = find_output_device(device_name)?;
+            let supported: Vec<_> = device.supported_output_configs()?.collect();
+            (device.default_output_config()?, supported)
+        }
+    };
+    Ok(DeviceFormats {
+        default_sample_rate: default.sample_rate().0,
+        default_channels: default.channels(),
+        default_sample_format: default.sample_format().to_string(),
+        supported: supported.iter().map(describe_config_range).collect(),
+    })
+}
+
+// ── Device streams ──
+
+/// The named input device, or the default one if it is missing.
+fn find_input_device(device_name: Option<&str>) -> Result<cpal::Device> {
+    let host = cpal::default_host();
+    if let Some(name) = device_name {
+        match host
+            .input_devices()
+            .context("Failed to enumerate input devices")?
+            .find(|d| d.name().ok().as_deref() == Some(name))
+        {
+            Some(device) => return Ok(device),
+            None => tracing::warn!("Input device '{name}' not found, falling back to default"),
+        }
+    }
+    host.default_input_device()
+        .context("No input audio device found")
+}
+
+/// The named output device, or the default one if it is missing.
+fn find_output_device(device_name: Option<&str>) -> Result<cpal::Device> {
+    let host = cpal::default_host();
+    if let Some(name) = device_name {
+        match host
+            .output_devices()
+            .context("Failed to enumerate output devices")?
+            .find(|d| d.name().ok().as_deref() == Some(name))
+        {
+            Some(device) => return Ok(device),
+            None => tracing::warn!("Output device '{name}' not found, falling back to default"),
+        }
+    }
+    host.default_output_device()
+        .context("No output audio device found")
+}
+
+fn input_stream_config(
+    device: &cpal::Device,
+    device_configs: &DeviceConfigs,
+) -> Result<(cpal::StreamConfig, SampleFormat)> {
+    let default = device.default_input_config()?;
+    let supported: Vec<_> = device
+        .supported_input_configs()
+        .map(|configs| configs.collect())
+        .unwrap_or_default();
+    let wanted = configured_for(device, device_configs);
+    Ok(choose_stream_config(&default, &supported, &wanted))
+}
+
+fn output_stream_config(
+    device: &cpal::Device,
+    device_configs: &DeviceConfigs,
+) -> Result<(cpal::StreamConfig, SampleFormat)> {
+    let default = device.default_output_config()?;
+    let supported: Vec<_> = device
+        .supported_output_configs()
+        .map(|configs| configs.collect())
+        .unwrap_or_default();
+    let wanted = configured_for(device, device_configs);
+    Ok(choose_stream_config(&default, &supported, &wanted))
+}
+
+fn configured_for(device: &cpal::Device, device_configs: &DeviceConfigs) -> DeviceConfig {
+    device
+        .name()
+        .ok()
+        .and_then(|name| device_configs.get(&name).cloned())
+        .unwrap_or_default()
+}
+
+/// Open an input stream in the device's sample format, handing `on_data`
+/// the samples converted to f32.
+fn build_input_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: SampleFormat,
+    mut on_data: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream> {
+    fn build<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut on_data: impl FnMut(&[f32]) + Send + 'static,
+        on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        // Sized for typical callbacks so the audio thread rarely allocates
+        let mut converted = Vec::with_capacity(8192);
+        device.build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                converted.clear();
+                converted.extend(data.iter().map(|s| s.to_sample::<f32>()));
+                on_data(&converted);
+            },
+            on_error,
+            None,
+        )
+    }
+
+    let stream = match sample_format {
+        SampleFormat::F32 => device.build_input_stream(
+            config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| on_data(data),
+            on_error,
+            None,
+        ),
+        SampleFormat::F64 => build::<f64>(device, config, on_data, on_error),
+        SampleFormat::I8 => build::<i8>(device, config, on_data, on_error),
+        SampleFormat::I16 => build::<i16>(device, config, on_data, on_error),
+        SampleFormat::I32 => build::<i32>(device, config, on_data, on_error),
+        SampleFormat::I64 => build::<i64>(device, config, on_data, on_error),
+        SampleFormat::U8 => build::<u8>(device, config, on_data, on_error),
+        SampleFormat::U16 => build::<u16>(device, config, on_data, on_error),
+        SampleFormat::U32 => build::<u32>(device, config, on_data, on_error),
+        SampleFormat::U64 => build::<u64>(device, config, on_data, on_error),
+        other => anyhow::bail!("Unsupported input sample format {other}"),
+    }?;
+    Ok(stream)
+}
+
+/// Open an output stream in the device's sample format, filling it with
+/// f32 samples from `next_sample`.
+fn build_output_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: SampleFormat,
+    next_sample: impl FnMut() -> f32 + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream> {
+    fn build<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut next_sample: impl FnMut() -> f32 + Send + 'static,
+        on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                for sample in data.iter_mut() {
+                    *sample = T::from_sample(next_sample());
+                }
+            },
+            on_error,
+            None,
+        )
+    }
+
+    let stream = match sample_format {
+        SampleFormat::F32 => build::<f32>(device, config, next_sample, on_error),
+        SampleFormat::F64 => build::<f64>(device, config, next_sample, on_error),
+        SampleFormat::I8 => build::<i8>(device, config, next_sample, on_error),
+        SampleFormat::I16 => build::<i16>(device, config, next_sample, on_error),
+        SampleFormat::I32 => build::<i32>(device, config, next_sample, on_error),
+        SampleFormat::I64 => build::<i64>(device, config, next_sample, on_error),
+        SampleFormat::U8 => build::<u8>(device, config, next_sample, on_error),
+        SampleFormat::U16 => build::<u16>(device, config, next_sample, on_error),
+        SampleFormat::U32 => build::<u32>(device, config, next_sample, on_error),
+        SampleFormat::U64 => build::<u64>(device, config, next_sample, on_error),
+        other => anyhow::bail!("Unsupported output sample format {other}"),
+    }?;
+    Ok(stream)
+}
+
 // ── AudioPlayback ──
 
+/// A pause between playback frames longer than this means the mixer went
+/// quiet and the device buffer drained.
+const PLAYBACK_GAP: Duration = Duration::from_millis(100);
+
 pub struct AudioPlayback {
-    tx: flume::Sender<PcmFrame>,
+    tx: flume::Sender<StereoFrame>,
+    failed: Arc<AtomicBool>,
 }
 
 impl AudioPlayback {
-    pub fn new(device_name: Option<String>) -> Result<Self> {
-        let (tx, rx) = flume::bounded::<PcmFrame>(AUDIO_PLAYBACK_QUEUE_FRAMES);
+    pub fn new(device_name: Option<String>, device_configs: DeviceConfigs) -> Result<Self> {
+        let (tx, rx) = flume::bounded::<StereoFrame>(AUDIO_PLAYBACK_QUEUE_FRAMES);
+        let failed = Arc::new(AtomicBool::new(false));
+        let failed_flag = Arc::clone(&failed);
 
         std::thread::Builder::new()
             .name("audio-playback".into())
             .spawn(move || {
-                if let Err(e) = run_playback(rx, device_name) {
+                if let Err(e) =
+                    run_playback(rx, device_name, device_configs, Arc::clone(&failed_flag))
+                {
                     tracing::error!("Audio playback thread error: {e}");
+                    failed_flag.store(true, Ordering::Relaxed);
                 }
             })?;
 
-        Ok(Self { tx })
+        Ok(Self { tx, failed })
     }
 
-    pub fn write(&self, samples: PcmFrame) {
+    pub fn write(&self, samples: StereoFrame) {
         let _ = self.tx.try_send(samples);
     }
+
+    /// The output stream errored or couldn't be opened; open a new playback.
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+// ── SharedAudio (system or application sound mixed into the call) ──
+
+/// A second input, typically a loopback or monitor device, whose sound is
+/// mixed into what we send after the mic's voice processing. Frames go to
+/// the capture thread through `CaptureProcessing::shared_audio_rx`.
+///
+/// A monitor of the output device also carries the call itself, so its
+/// sound is echo cancelled against the same far-end reference as the mic.
+/// That removes the others' voices well, but not perfectly: the system
+/// mixer's volume and effects change the signal, and the residue is ducked
+/// rather than removed. Sharing a source that leaves out the call (another
+/// output device, or a single application's monitor) avoids it entirely.
+pub struct SharedAudio {
+    device: String,
+    far_end_tx: flume::Sender<PcmFrame>,
+    stop: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SharedAudio {
+    pub fn new(
+        device_name: String,
+        device_configs: DeviceConfigs,
+        tx: flume::Sender<StereoFrame>,
+    ) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let failed = Arc::new(AtomicBool::new(false));
+        let (far_end_tx, far_end_rx) =
+            flume::bounded::<PcmFrame>(AUDIO_ECHO_REFERENCE_QUEUE_FRAMES);
+        let stop_flag = Arc::clone(&stop);
+        let failed_flag = Arc::clone(&failed);
+        let device = device_name.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("shared-audio".into())
+            .spawn(move || {
+                if let Err(e) = run_shared_audio(
+                    device_name,
+                    device_configs,
+                    tx,
+                    far_end_rx,
+                    stop_flag,
+                    Arc::clone(&failed_flag),
+                ) {
+                    tracing::error!("Shared audio thread error: {e}");
+                    failed_flag.store(true, Ordering::Relaxed);
+                }
+            })?;
+
+        Ok(Self {
+            device,
+            far_end_tx,
+            stop,
+            failed,
+            thread: Some(thread),
+        })
+    }
+
+    /// Feed the mixed remote audio sent to the speakers, like
+    /// [`AudioCapture::push_far_end`].
+    pub fn push_far_end(&self, frame: &PcmFrame) {
+        let _ = self.far_end_tx.try_send(*frame);
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// The input stream errored or couldn't be opened.
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for SharedAudio {
+    /// Waits for the thread, so no frame is queued after sharing stopped.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_shared_audio(
+    device_name: String,
+    device_configs: DeviceConfigs,
+    tx: flume::Sender<StereoFrame>,
+    far_end_rx: flume::Receiver<PcmFrame>,
+    stop: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+) -> Result<()> {
+    let device = find_input_device(Some(&device_name))?;
+    let (config, sample_format) = input_stream_config(&device, &device_configs)?;
+    tracing::info!(
+        "Sharing audio from {device_name}: {}Hz, {} channels, {}",
+        config.sample_rate.0,
+        config.channels,
+        sample_format
+    );
+
+    let ring = HeapRb::<f32>::new(ring_capacity(&config));
+    let (mut producer, mut consumer) = ring.split();
+    let failed_flag = Arc::clone(&failed);
+    let stream = build_input_stream(
+        &device,
+        &config,
+        sample_format,
+        move |data| {
+            push_whole(&mut producer, data);
+        },
+        move |err| {
+            tracing::error!("Shared audio input error: {err}");
+            failed_flag.store(true, Ordering::Relaxed);
+        },
+    )?;
+    stream.play()?;
+
+    // Kept in stereo, so music mode can send it as it is
+    let mut converter = InputConverter::new(config.sample_rate.0, config.channels, CHANNELS);
+    let mut device_buf =
+        vec![0.0f32; (config.sample_rate.0 as usize / 50) * usize::from(config.channels)];
+    let mut frame: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+
+    // The call's own sound, cancelled out of each channel
+    let mut echo_cancellers = [EchoCanceller::new(), EchoCanceller::new()];
+    let mut far_end: PcmFrame = [0.0; FRAME_SIZE];
+    let mut channel: PcmFrame = [0.0; FRAME_SIZE];
+
+    // This device and the mic run on separate clocks; nudge the resampling
+    // ratio to keep the queue to the capture, and so the delay, level
+    let mut drift = DriftController::new(SAMPLE_RATE);
+
+    while !stop.load(Ordering::Relaxed) && !failed.load(Ordering::Relaxed) {
+        if !converter.pop_frame(&mut frame) {
+            if !read_device_input(&mut consumer, &mut device_buf, &mut converter) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            continue;
+        }
+
+        // Same reference handling as the mic's echo cancellation
+        while far_end_rx.len() > 2 {
+            let _ = far_end_rx.try_recv();
+        }
+        match far_end_rx.try_recv() {
+            Ok(reference) => far_end = reference,
+            Err(_) => far_end.fill(0.0),
+        }
+        cancel_shared_echo(&mut echo_cancellers, &mut frame, &far_end, &mut channel);
+
+        match tx.try_send(frame) {
+            Ok(()) => {
+                let queued = tx.len() * FRAME_SIZE + converter.pending_frames();
+                converter.set_drift(drift.update(queued));
+            }
+            // The capture isn't taking frames (e.g. muted); the level to
+            // hold starts over once it does
+            Err(flume::TrySendError::Full(_)) => {
+                drift.reset();
+                converter.set_drift(0.0);
+            }
+            Err(flume::TrySendError::Disconnected(_)) => break,
+        }
+    }
+
+    tracing::info!("Stopped sharing audio from {device_name}");
+    Ok(())
+}
+
+/// Take the call's own sound back out of a frame of shared audio, each
+/// channel against the same far-end reference.
+fn cancel_shared_echo(
+    cancellers: &mut [EchoCanceller; 2],
+    frame: &mut StereoFrame,
+    far_end: &PcmFrame,
+    channel: &mut PcmFrame,
+) {
+    for (c, canceller) in cancellers.iter_mut().enumerate() {
+        for (s, pair) in channel.iter_mut().zip(frame.chunks_exact(2)) {
+            *s = pair[c];
+        }
+        canceller.process(channel, far_end);
+        for (pair, s) in frame.chunks_exact_mut(2).zip(channel.iter()) {
+            pair[c] = *s;
+        }
+    }
 }
 
 // ── MicTest (loopback through opus encode → decode) ──
 
+pub enum MicTestMode {
+    /// Play the processed, Opus round-tripped mic back through the output device.
+    Loopback,
+    /// Stay silent and report each processed frame's peak level instead, for
+    /// measuring the room's noise floor.
+    Calibrate(flume::Sender<f32>),
+}
+
 pub struct MicTest {
     stop: Arc<AtomicBool>,
 }
@@ -417,8 +1302,10 @@ impl MicTest {
     pub fn new(
         device_name: Option<String>,
         output_device_name: Option<String>,
+        device_configs: (DeviceConfigs, DeviceConfigs),
         app: AppHandle,
-        noise_suppression: Arc<AtomicBool>,
+        processing: CaptureProcessing,
+        mode: MicTestMode,
     ) -> Result<Self> {
         let stop = Arc::new(AtomicBool::new(false));
         let stop_flag = Arc::clone(&stop);
@@ -429,9 +1316,11 @@ impl MicTest {
                 if let Err(e) = run_mic_test(
                     device_name,
                     output_device_name,
+                    device_configs,
                     stop_flag,
                     app,
-                    noise_suppression,
+                    processing,
+                    mode,
                 ) {
                     tracing::error!("Mic test error: {e}");
                 }
@@ -451,93 +1340,79 @@ impl Drop for MicTest {
     }
 }
 
+/// Output side of a loopback mic test.
+struct MicTestOutput {
+    _stream: cpal::Stream,
+    producer: HeapProd<f32>,
+    converter: OutputConverter,
+}
+
+fn open_mic_test_output(
+    output_device_name: Option<String>,
+    device_configs: &DeviceConfigs,
+) -> Result<MicTestOutput> {
+    let out_device = find_output_device(output_device_name.as_deref())?;
+    let (out_stream_config, sample_format) = output_stream_config(&out_device, device_configs)?;
+    let out_rate = out_stream_config.sample_rate.0;
+    let out_channels = out_stream_config.channels;
+
+    let out_ring = HeapRb::<f32>::new(ring_capacity(&out_stream_config));
+    let (out_producer, mut out_consumer) = out_ring.split();
+
+    let out_stream = build_output_stream(
+        &out_device,
+        &out_stream_config,
+        sample_format,
+        move || out_consumer.try_pop().unwrap_or(0.0),
+        |err| tracing::error!("Mic test output error: {err}"),
+    )?;
+    out_stream.play()?;
+
+    Ok(MicTestOutput {
+        _stream: out_stream,
+        producer: out_producer,
+        converter: OutputConverter::new(1, out_rate, out_channels),
+    })
+}
+
 fn run_mic_test(
     device_name: Option<String>,
     output_device_name: Option<String>,
+    (input_configs, output_configs): (DeviceConfigs, DeviceConfigs),
     stop: Arc<AtomicBool>,
     app: AppHandle,
-    noise_suppression: Arc<AtomicBool>,
+    processing: CaptureProcessing,
+    mode: MicTestMode,
 ) -> Result<()> {
-    let host = cpal::default_host();
-
     // ── Input device ──
-    let in_device = if let Some(ref name) = device_name {
-        host.input_devices()
-            .context("Failed to enumerate input devices")?
-            .find(|d| d.name().ok().as_deref() == Some(name))
-            .or_else(|| host.default_input_device())
-            .context("No input audio device found")?
-    } else {
-        host.default_input_device()
-            .context("No input audio device found")?
-    };
+    let in_device = find_input_device(device_name.as_deref())?;
     tracing::info!("Mic test input: {:?}", in_device.name());
 
-    // ── Output device ──
-    let out_device = if let Some(ref name) = output_device_name {
-        host.output_devices()
-            .context("Failed to enumerate output devices")?
-            .find(|d| d.name().ok().as_deref() == Some(name))
-            .or_else(|| host.default_output_device())
-            .context("No output audio device found")?
-    } else {
-        host.default_output_device()
-            .context("No output audio device found")?
-    };
-
     // ── Input stream setup ──
-    let in_config = in_device.default_input_config()?;
-    let in_rate = in_config.sample_rate().0;
-    let in_channels = in_config.channels();
-
-    let in_stream_config = cpal::StreamConfig {
-        channels: in_channels,
-        sample_rate: SampleRate(in_rate),
-        buffer_size: cpal::BufferSize::Default,
-    };
+    let (in_stream_config, sample_format) = input_stream_config(&in_device, &input_configs)?;
+    let in_rate = in_stream_config.sample_rate.0;
+    let in_channels = in_stream_config.channels;
 
-    let ring_size = (in_rate as usize / 5) * in_channels as usize;
-    let ring = HeapRb::<f32>::new(ring_size);
+    let ring = HeapRb::<f32>::new(ring_capacity(&in_stream_config));
     let (mut producer, mut consumer) = ring.split();
 
-    let in_stream = in_device.build_input_stream(
+    let in_stream = build_input_stream(
+        &in_device,
         &in_stream_config,
-        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            let _ = producer.push_slice(data);
+        sample_format,
+        move |data| {
+            push_whole(&mut producer, data);
         },
         |err| tracing::error!("Mic test input error: {err}"),
-        None,
     )?;
     in_stream.play()?;
 
-    // ── Output stream setup ──
-    let out_config = out_device.default_output_config()?;
-    let out_rate = out_config.sample_rate().0;
-    let out_channels = out_config.channels();
-
-    let out_stream_config = cpal::StreamConfig {
-        channels: out_channels,
-        sample_rate: SampleRate(out_rate),
-        buffer_size: cpal::BufferSize::Default,
+    // ── Output stream setup (loopback only) ──
+    let mut output = match mode {
+        MicTestMode::Loopback => Some(open_mic_test_output(output_device_name, &output_configs)?),
+        MicTestMode::Calibrate(_) => None,
     };
 
-    let out_ring_size = (out_rate as usize / 5) * out_channels as usize;
-    let out_ring = HeapRb::<f32>::new(out_ring_size);
-    let (out_producer, mut out_consumer) = out_ring.split();
-    let out_producer = Arc::new(std::sync::Mutex::new(out_producer));
-
-    let out_stream = out_device.build_output_stream(
-        &out_stream_config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            for sample in data.iter_mut() {
-                *sample = out_consumer.try_pop().unwrap_or(0.0);
-            }
-        },
-        |err| tracing::error!("Mic test output error: {err}"),
-        None,
-    )?;
-    out_stream.play()?;
-
     // ── Opus encode → decode loopback ──
     let mut encoder =
         opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Audio)
@@ -551,57 +1426,59 @@ fn run_mic_test(
         .map_err(|e| anyhow::anyhow!("opus decoder: {e}"))?;
 
     // Noise suppression
-    const DENOISE_FRAME: usize = nnnoiseless::FRAME_SIZE;
     let mut denoise = nnnoiseless::DenoiseState::new();
-    let mut denoise_in = [0.0f32; DENOISE_FRAME];
-    let mut denoise_out = [0.0f32; DENOISE_FRAME];
 
-    let device_frame_samples = (in_rate as usize / 50) * in_channels as usize;
-    let mut device_buf = vec![0.0f32; device_frame_samples];
+    // Same AGC as the call, so calibration sees the level the VAD will see
+    let mut agc = AutoGain::new();
+
+    let mut converter = InputConverter::new(in_rate, in_channels, 1);
+    let mut device_buf = vec![0.0f32; (in_rate as usize / 50) * in_channels as usize];
     let mut mono_48k: PcmFrame = [0.0; FRAME_SIZE];
     let mut opus_buf = [0u8; 4000];
     let mut decoded_buf: PcmFrame = [0.0; FRAME_SIZE];
-    let output_capacity = ((FRAME_SIZE as f64 * out_rate as f64 / SAMPLE_RATE as f64) as usize + 1)
-        * usize::from(out_channels.max(1));
-    let mut output_buf = Vec::with_capacity(output_capacity);
+    let mut output_buf = Vec::new();
     let mut level_counter: u32 = 0;
 
     while !stop.load(Ordering::Relaxed) {
-        if consumer.occupied_len() < device_frame_samples {
-            std::thread::sleep(std::time::Duration::from_millis(5));
+        if !converter.pop_frame(&mut mono_48k) {
+            if !read_device_input(&mut consumer, &mut device_buf, &mut converter) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
             continue;
         }
 
-        consumer.pop_slice(&mut device_buf);
-
-        convert_input_to_mono_48k(&device_buf, in_rate, in_channels, &mut mono_48k);
+        // Noise suppression and AGC (conditional, and skipped in music mode)
+        let music = processing.music_mode.load(Ordering::Relaxed);
+        if processing.noise_suppression.load(Ordering::Relaxed) && !music {
+            denoise_frame(&mut denoise, &mut mono_48k, true);
+        }
 
-        // Noise suppression (conditional)
-        if noise_suppression.load(Ordering::Relaxed) {
-            for chunk_idx in 0..2 {
-                let offset = chunk_idx * DENOISE_FRAME;
-                for i in 0..DENOISE_FRAME {
-                    denoise_in[i] = mono_48k[offset + i] * 32767.0;
-                }
-                denoise.process_frame(&mut denoise_out, &denoise_in);
-                for i in 0..DENOISE_FRAME {
-                    mono_48k[offset + i] = denoise_out[i] / 32767.0;
-                }
-            }
+        if processing.agc_enabled.load(Ordering::Relaxed) && !music {
+            agc.process(&mut mono_48k);
         }
 
+        let peak = mono_48k
+            .iter()
+            .map(|s| s.abs())
+            .fold(0.0f32, f32::max)
+            .clamp(0.0, 1.0);
+
         // Emit level event (~every 50ms = every 2-3 frames at 20ms/frame)
         level_counter += 1;
         if level_counter >= 3 {
             level_counter = 0;
-            let peak = mono_48k
-                .iter()
-                .map(|s| s.abs())
-                .fold(0.0f32, f32::max)
-                .clamp(0.0, 1.0);
             let _ = app.emit(EVENT_MIC_TEST_LEVEL, peak);
         }
 
+        let Some(output) = output.as_mut() else {
+            if let MicTestMode::Calibrate(ref level_tx) = mode {
+                if level_tx.send(peak).is_err() {
+                    break;
+                }
+            }
+            continue;
+        };
+
         // Opus encode
         let encoded_len = match encoder.encode_float(&mono_48k, &mut opus_buf) {
             Ok(len) => len,
@@ -615,94 +1492,87 @@ fn run_mic_test(
                 Err(_) => continue,
             };
 
-        convert_mono_48k_to_output(
-            &decoded_buf[..decoded_samples],
-            out_rate,
-            out_channels,
-            &mut output_buf,
-        );
+        output
+            .converter
+            .convert(&decoded_buf[..decoded_samples], &mut output_buf);
 
-        if let Ok(mut p) = out_producer.lock() {
-            let _ = p.push_slice(&output_buf);
-        }
+        push_whole(&mut output.producer, &output_buf);
     }
 
     tracing::info!("Mic test stopped");
     Ok(())
 }
 
-fn run_playback(rx: flume::Receiver<PcmFrame>, device_name: Option<String>) -> Result<()> {
-    let host = cpal::default_host();
-    let device = if let Some(ref name) = device_name {
-        host.output_devices()
-            .context("Failed to enumerate output devices")?
-            .find(|d| d.name().ok().as_deref() == Some(name))
-            .with_context(|| {
-                format!(
-                    "Output device '{}' not found, falling back to default",
-                    name
-                )
-            })
-            .or_else(|e| {
-                tracing::warn!("{e}");
-                host.default_output_device()
-                    .context("No output audio device found")
-            })?
-    } else {
-        host.default_output_device()
-            .context("No output audio device found")?
-    };
-
+fn run_playback(
+    rx: flume::Receiver<StereoFrame>,
+    device_name: Option<String>,
+    device_configs: DeviceConfigs,
+    failed: Arc<AtomicBool>,
+) -> Result<()> {
+    let device = find_output_device(device_name.as_deref())?;
     tracing::info!("Using output device: {:?}", device.name());
 
-    // Query the device's default output config
-    let default_config = device.default_output_config()?;
-    let device_rate = default_config.sample_rate().0;
-    let device_channels = default_config.channels();
+    // The device's default config, unless another was chosen for it
+    let (config, sample_format) = output_stream_config(&device, &device_configs)?;
+    let device_rate = config.sample_rate.0;
+    let device_channels = config.channels;
 
     tracing::info!(
-        "Output device config: {}Hz, {} channels (source: {}Hz mono)",
+        "Output device config: {}Hz, {} channels, {} (source: {}Hz stereo)",
         device_rate,
         device_channels,
+        sample_format,
         SAMPLE_RATE
     );
 
-    let config = cpal::StreamConfig {
-        channels: device_channels,
-        sample_rate: SampleRate(device_rate),
-        buffer_size: cpal::BufferSize::Default,
-    };
-
     // Ring buffer sized for the device rate and channels
-    let ring_size = (device_rate as usize / 5) * device_channels as usize;
-    let ring = HeapRb::<f32>::new(ring_size);
+    let ring = HeapRb::<f32>::new(ring_capacity(&config));
     let (producer, mut consumer) = ring.split();
     let producer = Arc::new(std::sync::Mutex::new(producer));
+    let failed_flag = Arc::clone(&failed);
 
-    let stream = device.build_output_stream(
+    let stream = build_output_stream(
+        &device,
         &config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            for sample in data.iter_mut() {
-                *sample = consumer.try_pop().unwrap_or(0.0);
-            }
-        },
+        sample_format,
+        move || consumer.try_pop().unwrap_or(0.0),
         move |err| {
+            // Treated as fatal; the engine reopens the playback
             tracing::error!("Audio output error: {err}");
+            failed_flag.store(true, Ordering::Relaxed);
         },
-        None,
     )?;
     stream.play()?;
 
-    // Read decoded 48kHz mono, resample/upmix to device format, push to ring buffer
+    // Read the 48kHz stereo mix, resample/remap to device format, push to ring buffer.
+    // The converter carries resampler state across frames, so frame edges don't click.
+    let mut converter = OutputConverter::new(CHANNELS, device_rate, device_channels);
     let output_capacity = ((FRAME_SIZE as f64 * device_rate as f64 / SAMPLE_RATE as f64) as usize
-        + 1)
+        + 2)
         * usize::from(device_channels.max(1));
     let mut output_buf = Vec::with_capacity(output_capacity);
+    // The mixer runs on the system clock and the device on its own; nudge the
+    // resampling ratio to keep the ring buffer, and so the latency, level
+    let mut drift = DriftController::new(device_rate);
+    let mut last_frame = Instant::now();
     while let Ok(samples) = rx.recv() {
-        convert_mono_48k_to_output(&samples, device_rate, device_channels, &mut output_buf);
+        if failed.load(Ordering::Relaxed) {
+            break;
+        }
+        // Frames only arrive while there's something to hear; after a gap the
+        // buffer has drained and the level to hold starts over
+        if last_frame.elapsed() > PLAYBACK_GAP {
+            drift.reset();
+        }
+        last_frame = Instant::now();
+        converter.convert(&samples, &mut output_buf);
 
         if let Ok(mut p) = producer.lock() {
-            let _ = p.push_slice(&output_buf);
+            if !push_whole(&mut p, &output_buf) {
+                drift.reset();
+            }
+            let buffered = p.occupied_len() / usize::from(device_channels.max(1));
+            converter.set_drift(drift.update(buffered));
         }
     }
 
@@ -712,47 +1582,102 @@ fn run_playback(rx: flume::Receiver<PcmFrame>, device_name: Option<String>) -> R
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn input_conversion_downmixes_interleaved_stereo_without_changing_rate() {
-        let mut out = [1.0; FRAME_SIZE];
-        let input = [0.2, 0.6, -0.5, 0.5];
-
-        convert_input_to_mono_48k(&input, SAMPLE_RATE, 2, &mut out);
-
-        assert!((out[0] - 0.4).abs() < f32::EPSILON);
-        assert!(out[1].abs() < f32::EPSILON);
-        assert!(out[2..].iter().all(|&sample| sample == 0.0));
+    use crate::source::{Paced, ToneSource};
+
+    /// Share of the signal's energy at `frequency`.
+    fn tone_share(samples: &[f32], frequency: f32) -> f32 {
+        let step = std::f32::consts::TAU * frequency / SAMPLE_RATE as f32;
+        let (mut sin, mut cos, mut energy) = (0.0f32, 0.0f32, 0.0f32);
+        for (n, s) in samples.iter().enumerate() {
+            sin += s * (step * n as f32).sin();
+            cos += s * (step * n as f32).cos();
+            energy += s * s;
+        }
+        2.0 * (sin * sin + cos * cos) / (samples.len() as f32 * energy)
     }
 
     #[test]
-    fn input_conversion_resamples_and_pads_when_source_is_short() {
-        let mut out = [0.0; 4];
-        let input = [0.0, 1.0];
-
-        convert_input_to_mono_48k(&input, 24_000, 1, &mut out);
-
-        assert_eq!(out, [0.0, 0.5, 1.0, 1.0]);
+    fn capture_clock_counts_gaps_and_outlives_captures() {
+        let clock = CaptureClock::default();
+        let start = Instant::now();
+        let frame = FRAME_SIZE as u32;
+        assert_eq!(clock.stamp_at(start), 0);
+        assert_eq!(clock.stamp_at(start + Duration::from_millis(25)), frame);
+        // Callback jitter doesn't move the clock
+        assert_eq!(clock.stamp_at(start + Duration::from_millis(90)), 2 * frame);
+        // Two seconds without frames, e.g. a muted device
+        let resumed = start + Duration::from_millis(2_090);
+        assert_eq!(clock.stamp_at(resumed), 102 * frame);
+        // Another capture sharing the clock continues it
+        assert_eq!(
+            clock.clone().stamp_at(resumed + Duration::from_millis(20)),
+            103 * frame
+        );
     }
 
     #[test]
-    fn output_conversion_upmixes_mono_to_interleaved_channels() {
-        let mut out = Vec::new();
-
-        convert_mono_48k_to_output(&[0.25, -0.5], SAMPLE_RATE, 2, &mut out);
-
-        assert_eq!(out, vec![0.25, 0.25, -0.5, -0.5]);
+    fn muting_keeps_the_clock_running() {
+        let processing = CaptureProcessing::default();
+        processing.music_mode.store(true, Ordering::Relaxed);
+        let tone = ToneSource::new(440.0, 0.5, Duration::from_secs(1));
+        let capture =
+            AudioCapture::with_source(Box::new(Paced::new(tone)), processing.clone()).unwrap();
+
+        std::thread::sleep(Duration::from_millis(300));
+        capture.set_muted(true);
+        std::thread::sleep(Duration::from_millis(400));
+        capture.set_muted(false);
+        let timestamps: Vec<u32> = capture
+            .encoded_rx
+            .iter()
+            .map(|frame| frame.timestamp)
+            .collect();
+
+        // The muted frames are missing, but the clock counted them
+        assert!(timestamps.len() < 40, "{} frames sent", timestamps.len());
+        assert_eq!(*timestamps.last().unwrap(), 49 * FRAME_SIZE as u32);
+        let jumps: Vec<u32> = timestamps
+            .windows(2)
+            .map(|pair| pair[1].wrapping_sub(pair[0]))
+            .filter(|&step| step != FRAME_SIZE as u32)
+            .collect();
+        assert_eq!(jumps.len(), 1, "{jumps:?}");
+        assert_eq!(jumps[0] % FRAME_SIZE as u32, 0);
+
+        // A new capture goes on from there
+        let tone = ToneSource::new(440.0, 0.5, Duration::from_millis(100));
+        let capture = AudioCapture::with_source(Box::new(tone), processing).unwrap();
+        let first = capture.encoded_rx.recv().unwrap();
+        assert_eq!(first.timestamp, 50 * FRAME_SIZE as u32);
     }
 
     #[test]
-    fn output_conversion_reuses_existing_allocation() {
-        let mut out = Vec::with_capacity(16);
-        let capacity = out.capacity();
-
-        convert_mono_48k_to_output(&[0.0; 4], SAMPLE_RATE, 1, &mut out);
-        convert_mono_48k_to_output(&[1.0; 4], SAMPLE_RATE, 1, &mut out);
+    fn tone_comes_through_capture_and_encode() {
+        // Music mode, so voice processing and DTX leave the tone alone
+        let processing = CaptureProcessing::default();
+        processing.music_mode.store(true, Ordering::Relaxed);
+        let tone = ToneSource::new(440.0, 0.5, Duration::from_secs(1));
+        let capture = AudioCapture::with_source(Box::new(Paced::new(tone)), processing).unwrap();
+
+        let mut decoder = opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono).unwrap();
+        let mut pcm: PcmFrame = [0.0; FRAME_SIZE];
+        let mut decoded = Vec::new();
+        let mut timestamps = Vec::new();
+        // The capture ends, closing the queue, when the tone does
+        while let Ok(frame) = capture.encoded_rx.recv_timeout(Duration::from_secs(2)) {
+            timestamps.push(frame.timestamp);
+            let len = decoder.decode_float(&frame.data, &mut pcm, false).unwrap();
+            decoded.extend_from_slice(&pcm[..len]);
+        }
 
-        assert_eq!(out, vec![1.0; 4]);
-        assert_eq!(out.capacity(), capacity);
+        assert_eq!(timestamps.len(), 50, "every frame of the tone is sent");
+        assert!(timestamps
+            .windows(2)
+            .all(|pair| pair[1] == pair[0].wrapping_add(FRAME_SIZE as u32)));
+        // Past the codec's start-up
+        let steady = &decoded[10 * FRAME_SIZE..];
+        let peak = steady.iter().map(|s| s.abs()).fold(0.0f32, f32::max);
+        assert!((peak - 0.5).abs() < 0.05, "peak {peak}");
+        assert!(tone_share(steady, 440.0) > 0.95);
     }
 }
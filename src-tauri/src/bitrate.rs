@@ -0,0 +1,238 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Result};
+
+// ── Tuning ──
+
+pub const DEFAULT_MIN_BITRATE: u32 = 16_000;
+pub const DEFAULT_MAX_BITRATE: u32 = 64_000;
+/// Range the user may configure bounds within. Below 6 kbps Opus stops
+/// being intelligible; above 128 kbps mono voice gains nothing.
+const BITRATE_FLOOR: u32 = 6_000;
+const BITRATE_CEILING: u32 = 128_000;
+/// Smoothed loss above this cuts the bitrate multiplicatively.
+const HIGH_LOSS: f32 = 0.08;
+/// Smoothed loss below this lets the bitrate probe back up.
+const LOW_LOSS: f32 = 0.02;
+const DECREASE_FACTOR: f32 = 0.8;
+/// Additive increase per receiver report (roughly one per second).
+const INCREASE_STEP: u32 = 4_000;
+/// Don't probe upward while the path is this slow; it is likely congested.
+const HIGH_RTT_MS: u32 = 400;
+/// Weight of each new report in the smoothed loss.
+const LOSS_SMOOTHING: f32 = 0.3;
+/// Opus FEC is tuned for this expected loss until the first report arrives.
+pub const DEFAULT_EXPECTED_LOSS_PERC: u32 = 10;
+/// Always keep a little FEC in the stream so a sudden burst is covered.
+const MIN_EXPECTED_LOSS_PERC: u32 = 5;
+const MAX_EXPECTED_LOSS_PERC: u32 = 25;
+
+/// Seconds between the NTP (1900) and Unix (1970) epochs.
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// User-configurable limits for the adaptive Opus bitrate, in bits per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateBounds {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl Default for BitrateBounds {
+    fn default() -> Self {
+        Self {
+            min: DEFAULT_MIN_BITRATE,
+            max: DEFAULT_MAX_BITRATE,
+        }
+    }
+}
+
+impl BitrateBounds {
+    pub fn new(min: u32, max: u32) -> Result<Self> {
+        ensure!(min <= max, "Minimum bitrate must not exceed the maximum");
+        ensure!(
+            min >= BITRATE_FLOOR && max <= BITRATE_CEILING,
+            "Bitrate must be between {} and {} kbps",
+            BITRATE_FLOOR / 1000,
+            BITRATE_CEILING / 1000
+        );
+        Ok(Self { min, max })
+    }
+
+    fn clamp(&self, bitrate: u32) -> u32 {
+        bitrate.clamp(self.min, self.max)
+    }
+}
+
+/// One RTCP reception report block about our outgoing audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkReport {
+    /// Fraction of packets lost since the previous report (0..1).
+    pub fraction_lost: f32,
+    pub rtt_ms: Option<u32>,
+}
+
+impl LinkReport {
+    /// Build from the raw report block fields. `fraction_lost` is the RTCP
+    /// 8-bit fixed point value; `last_sender_report` and `delay` give the
+    /// round trip time per RFC 3550 §6.4.1.
+    pub fn from_rtcp(fraction_lost: u8, last_sender_report: u32, delay: u32) -> Self {
+        Self {
+            fraction_lost: f32::from(fraction_lost) / 256.0,
+            rtt_ms: round_trip_ms(last_sender_report, delay, SystemTime::now()),
+        }
+    }
+}
+
+/// Round trip time from a reception report: arrival time minus the echoed
+/// sender report timestamp minus the receiver's hold time, all in the middle
+/// 32 bits of NTP time (1/65536 s units).
+fn round_trip_ms(last_sender_report: u32, delay: u32, now: SystemTime) -> Option<u32> {
+    if last_sender_report == 0 {
+        return None;
+    }
+    let since_unix = now.duration_since(UNIX_EPOCH).ok()?;
+    let ntp_secs = since_unix.as_secs() + NTP_UNIX_OFFSET_SECS;
+    let ntp_frac = (u64::from(since_unix.subsec_nanos()) << 32) / 1_000_000_000;
+    let now_middle = (((ntp_secs & 0xffff) << 16) | (ntp_frac >> 16)) as u32;
+
+    let rtt = now_middle
+        .wrapping_sub(last_sender_report)
+        .wrapping_sub(delay);
+    // A "negative" result means clock trouble; anything over a minute is bogus.
+    if rtt > 60 << 16 {
+        return None;
+    }
+    Some((u64::from(rtt) * 1000 / 65_536) as u32)
+}
+
+/// Loss-based AIMD bitrate control for the link to one peer: back off
+/// multiplicatively on sustained loss, probe back up additively once the
+/// path is clean, and size Opus FEC to the loss actually observed.
+pub struct BitrateController {
+    bitrate: u32,
+    smoothed_loss: f32,
+    reported: bool,
+}
+
+impl BitrateController {
+    pub fn new(bounds: BitrateBounds) -> Self {
+        Self {
+            bitrate: bounds.max,
+            smoothed_loss: 0.0,
+            reported: false,
+        }
+    }
+
+    pub fn on_report(&mut self, report: LinkReport, bounds: BitrateBounds) {
+        let loss = report.fraction_lost.clamp(0.0, 1.0);
+        self.smoothed_loss = if self.reported {
+            self.smoothed_loss + LOSS_SMOOTHING * (loss - self.smoothed_loss)
+        } else {
+            loss
+        };
+        self.reported = true;
+
+        let congested = report.rtt_ms.is_some_and(|rtt| rtt > HIGH_RTT_MS);
+        self.bitrate = if self.smoothed_loss > HIGH_LOSS {
+            (self.bitrate as f32 * DECREASE_FACTOR) as u32
+        } else if self.smoothed_loss < LOW_LOSS && !congested {
+            self.bitrate.saturating_add(INCREASE_STEP)
+        } else {
+            self.bitrate
+        };
+        self.bitrate = bounds.clamp(self.bitrate);
+    }
+
+    /// Re-apply bounds after the user changed them.
+    pub fn set_bounds(&mut self, bounds: BitrateBounds) {
+        self.bitrate = bounds.clamp(self.bitrate);
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    /// Packet loss percentage to tune Opus in-band FEC for.
+    pub fn expected_loss_perc(&self) -> u32 {
+        if !self.reported {
+            return DEFAULT_EXPECTED_LOSS_PERC;
+        }
+        ((self.smoothed_loss * 100.0).ceil() as u32)
+            .clamp(MIN_EXPECTED_LOSS_PERC, MAX_EXPECTED_LOSS_PERC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn report(loss: f32, rtt_ms: u32) -> LinkReport {
+        LinkReport {
+            fraction_lost: loss,
+            rtt_ms: Some(rtt_ms),
+        }
+    }
+
+    #[test]
+    fn backs_off_under_loss_and_recovers() {
+        let bounds = BitrateBounds::default();
+        let mut ctl = BitrateController::new(bounds);
+        assert_eq!(ctl.bitrate(), DEFAULT_MAX_BITRATE);
+
+        for _ in 0..10 {
+            ctl.on_report(report(0.2, 80), bounds);
+        }
+        assert_eq!(ctl.bitrate(), DEFAULT_MIN_BITRATE);
+        assert_eq!(ctl.expected_loss_perc(), 20);
+
+        for _ in 0..30 {
+            ctl.on_report(report(0.0, 80), bounds);
+        }
+        assert_eq!(ctl.bitrate(), DEFAULT_MAX_BITRATE);
+        assert_eq!(ctl.expected_loss_perc(), MIN_EXPECTED_LOSS_PERC);
+    }
+
+    #[test]
+    fn holds_while_rtt_is_high() {
+        let bounds = BitrateBounds::default();
+        let mut ctl = BitrateController::new(bounds);
+        for _ in 0..10 {
+            ctl.on_report(report(0.3, 80), bounds);
+        }
+        let low = ctl.bitrate();
+        for _ in 0..5 {
+            ctl.on_report(report(0.0, 600), bounds);
+        }
+        assert_eq!(ctl.bitrate(), low);
+    }
+
+    #[test]
+    fn bounds_are_validated_and_applied() {
+        assert!(BitrateBounds::new(32_000, 24_000).is_err());
+        assert!(BitrateBounds::new(1_000, 24_000).is_err());
+        assert!(BitrateBounds::new(16_000, 500_000).is_err());
+
+        let mut ctl = BitrateController::new(BitrateBounds::default());
+        ctl.set_bounds(BitrateBounds::new(12_000, 32_000).unwrap());
+        assert_eq!(ctl.bitrate(), 32_000);
+    }
+
+    #[test]
+    fn round_trip_from_report_fields() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_millis(500);
+        let middle = |t: SystemTime| {
+            let d = t.duration_since(UNIX_EPOCH).unwrap();
+            let secs = d.as_secs() + NTP_UNIX_OFFSET_SECS;
+            let frac = (u64::from(d.subsec_nanos()) << 32) / 1_000_000_000;
+            (((secs & 0xffff) << 16) | (frac >> 16)) as u32
+        };
+        // SR sent 150ms ago, held 50ms at the receiver: 100ms on the wire.
+        let lsr = middle(now - Duration::from_millis(150));
+        let delay = 65_536 / 20;
+        let rtt = round_trip_ms(lsr, delay, now).unwrap();
+        assert!((99..=101).contains(&rtt), "rtt {rtt}");
+
+        assert_eq!(round_trip_ms(0, delay, now), None);
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Label of the per-peer chat data channel.
+pub const CHAT_CHANNEL_LABEL: &str = "chat";
+/// Both sides open the chat channel with this pre-agreed SCTP stream ID, so it
+/// needs no in-band announcement and works whichever side made the offer.
+pub const CHAT_CHANNEL_ID: u16 = 0;
+/// Longest chat message accepted, in characters.
+pub const MAX_CHAT_MESSAGE_CHARS: usize = 2000;
+
+/// What travels over the chat data channel, as JSON text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatPayload {
+    Message {
+        id: String,
+        text: String,
+        /// Sender's wall clock, milliseconds since the Unix epoch.
+        sent_at: u64,
+    },
+    /// Receipt for a `Message`, sent back by each peer that got it.
+    Ack { id: String },
+}
+
+impl ChatPayload {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("chat payload serializes")
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let payload: Self = serde_json::from_slice(data).context("Malformed chat payload")?;
+        if let Self::Message { text, .. } = &payload {
+            validate_text(text)?;
+        }
+        Ok(payload)
+    }
+}
+
+/// Trim a message and check it is worth sending.
+pub fn validate_text(text: &str) -> Result<&str> {
+    let text = text.trim();
+    anyhow::ensure!(!text.is_empty(), "Message is empty");
+    anyhow::ensure!(
+        text.chars().count() <= MAX_CHAT_MESSAGE_CHARS,
+        "Message is longer than {MAX_CHAT_MESSAGE_CHARS} characters"
+    );
+    Ok(text)
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_round_trips_as_tagged_json() {
+        let message = ChatPayload::Message {
+            id: "m1".to_string(),
+            text: "hello".to_string(),
+            sent_at: 1_700_000_000_000,
+        };
+        let encoded = message.encode();
+        assert!(encoded.contains(r#""type":"message""#));
+        assert_eq!(ChatPayload::decode(encoded.as_bytes()).unwrap(), message);
+
+        let ack = ChatPayload::Ack {
+            id: "m1".to_string(),
+        };
+        assert_eq!(ChatPayload::decode(ack.encode().as_bytes()).unwrap(), ack);
+    }
+
+    #[test]
+    fn rejects_empty_oversized_and_malformed_messages() {
+        assert_eq!(validate_text("  hi \n").unwrap(), "hi");
+        assert!(validate_text("   ").is_err());
+        assert!(validate_text(&"a".repeat(MAX_CHAT_MESSAGE_CHARS + 1)).is_err());
+        assert!(validate_text(&"é".repeat(MAX_CHAT_MESSAGE_CHARS)).is_ok());
+
+        assert!(ChatPayload::decode(b"not json").is_err());
+        assert!(
+            ChatPayload::decode(br#"{"type":"message","id":"x","text":"","sent_at":0}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,159 @@
+/// Loudest comfort noise we'll synthesize (≈ -50 dBFS RMS), so a peer who
+/// stopped mid-sentence leaves no more than a faint hiss.
+const MAX_COMFORT_RMS: f32 = 0.003;
+/// Frames of comfort noise at full level after the peer goes quiet: the
+/// pauses of a conversation, not a peer who muted or dropped out.
+const HOLD_FRAMES: u32 = 150; // 3s
+/// Frames over which it then fades to silence.
+const FADE_FRAMES: u32 = 50; // 1s
+/// Per-frame smoothing toward a quieter frame: the estimate drops quickly.
+const FALL_COEFF: f32 = 0.5;
+/// Per-frame smoothing toward a louder frame: speech barely moves it (~10s).
+const RISE_COEFF: f32 = 0.002;
+
+/// Receive-side comfort noise for discontinuous transmission.
+///
+/// While a peer is talking, the decoded frames keep a running estimate of
+/// their background level (a slow-rising minimum tracker). When the sender
+/// stops transmitting, `fill` plays matching low-level noise instead of dead
+/// silence so the call doesn't sound like it dropped. After a few seconds it
+/// fades out, so a peer who muted or stalled goes silent.
+pub struct ComfortNoise {
+    level: Option<f32>,
+    /// Frames filled since the last observed one.
+    gap_frames: u32,
+    seed: u32,
+}
+
+impl Default for ComfortNoise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ComfortNoise {
+    pub fn new() -> Self {
+        Self {
+            level: None,
+            gap_frames: 0,
+            seed: 0x2545_f491,
+        }
+    }
+
+    /// Update the background level estimate from a decoded frame.
+    pub fn observe(&mut self, frame: &[f32]) {
+        if frame.is_empty() {
+            return;
+        }
+        self.gap_frames = 0;
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        self.level = Some(match self.level {
+            None => rms,
+            Some(level) if rms < level => level + FALL_COEFF * (rms - level),
+            Some(level) => level + RISE_COEFF * (rms - level),
+        });
+    }
+
+    /// Fill `out` with noise at the estimated level. Returns `false` (leaving
+    /// `out` untouched) until at least one real frame has been observed, and
+    /// once the noise has faded out after a long gap.
+    pub fn fill(&mut self, out: &mut [f32]) -> bool {
+        let Some(level) = self.level else {
+            return false;
+        };
+        let faded = self.gap_frames.saturating_sub(HOLD_FRAMES);
+        if faded >= FADE_FRAMES {
+            return false;
+        }
+        self.gap_frames += 1;
+        let fade = 1.0 - faded as f32 / FADE_FRAMES as f32;
+        // Uniform noise in [-1, 1] has an RMS of 1/sqrt(3).
+        let scale = level.min(MAX_COMFORT_RMS) * fade * 3.0f32.sqrt();
+        for sample in out.iter_mut() {
+            self.seed = self
+                .seed
+                .wrapping_mul(1_664_525)
+                .wrapping_add(1_013_904_223);
+            let uniform = (self.seed >> 8) as f32 / (1u32 << 23) as f32 - 1.0;
+            *sample = uniform * scale;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FRAME_SIZE;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn sine(amplitude: f32) -> Vec<f32> {
+        (0..FRAME_SIZE)
+            .map(|i| amplitude * (i as f32 * 0.05).sin())
+            .collect()
+    }
+
+    #[test]
+    fn silent_until_audio_observed() {
+        let mut cn = ComfortNoise::new();
+        let mut out = [0.5f32; FRAME_SIZE];
+        assert!(!cn.fill(&mut out));
+        assert!(out.iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn matches_background_level_after_speech() {
+        let mut cn = ComfortNoise::new();
+        // A talkspurt followed by the sender's hangover frames of background noise.
+        for _ in 0..100 {
+            cn.observe(&sine(0.3));
+        }
+        for _ in 0..15 {
+            cn.observe(&sine(0.002));
+        }
+
+        let mut out = [0.0f32; FRAME_SIZE];
+        assert!(cn.fill(&mut out));
+        let expected = rms(&sine(0.002));
+        let actual = rms(&out);
+        assert!(
+            (actual / expected - 1.0).abs() < 0.2,
+            "comfort noise rms {actual}, background {expected}"
+        );
+    }
+
+    #[test]
+    fn fades_out_after_a_long_gap() {
+        let mut cn = ComfortNoise::new();
+        cn.observe(&sine(0.002));
+        let mut out = [0.0f32; FRAME_SIZE];
+
+        let levels: Vec<f32> = (0..HOLD_FRAMES + FADE_FRAMES)
+            .map(|_| {
+                assert!(cn.fill(&mut out));
+                rms(&out)
+            })
+            .collect();
+        assert!(levels[HOLD_FRAMES as usize + FADE_FRAMES as usize / 2] < levels[0] * 0.6);
+        assert!(levels.last().unwrap() < &(levels[0] * 0.1));
+
+        // Silent from then on, until the peer is heard again
+        out.fill(0.5);
+        assert!(!cn.fill(&mut out));
+        assert!(out.iter().all(|&s| s == 0.5));
+        cn.observe(&sine(0.002));
+        assert!(cn.fill(&mut out));
+    }
+
+    #[test]
+    fn level_is_capped() {
+        let mut cn = ComfortNoise::new();
+        cn.observe(&sine(0.5));
+        let mut out = [0.0f32; FRAME_SIZE];
+        cn.fill(&mut out);
+        assert!(rms(&out) <= MAX_COMFORT_RMS * 1.1);
+    }
+}
@@ -0,0 +1,56 @@
+use std::f32::consts::TAU;
+
+use crate::types::SAMPLE_RATE;
+
+const TONE_MS: usize = 60;
+const FADE_MS: usize = 8;
+const CUE_AMPLITUDE: f32 = 0.12;
+
+/// A short locally played sound, mixed into playback by the engine one frame
+/// at a time. Never sent to other participants.
+pub struct Cue {
+    samples: Vec<f32>,
+    pos: usize,
+}
+
+impl Cue {
+    /// Rising two-tone chirp: push-to-talk opened.
+    pub fn ptt_start() -> Self {
+        Self::tones(&[660.0, 880.0])
+    }
+
+    /// Falling two-tone chirp: push-to-talk closed.
+    pub fn ptt_stop() -> Self {
+        Self::tones(&[880.0, 660.0])
+    }
+
+    fn tones(freqs: &[f32]) -> Self {
+        let tone_len = SAMPLE_RATE as usize * TONE_MS / 1000;
+        let fade_len = SAMPLE_RATE as usize * FADE_MS / 1000;
+        let mut samples = Vec::with_capacity(tone_len * freqs.len());
+
+        for &freq in freqs {
+            for i in 0..tone_len {
+                // Short linear fades so the tone edges don't click.
+                let envelope = (i.min(tone_len - 1 - i) as f32 / fade_len as f32).min(1.0);
+                let phase = TAU * freq * i as f32 / SAMPLE_RATE as f32;
+                samples.push(phase.sin() * envelope * CUE_AMPLITUDE);
+            }
+        }
+
+        Self { samples, pos: 0 }
+    }
+
+    /// Add the next chunk of the cue into both sides of interleaved stereo
+    /// `out`. Returns `false` once the cue has finished and can be dropped.
+    pub fn mix_into(&mut self, out: &mut [f32]) -> bool {
+        let remaining = &self.samples[self.pos..];
+        let n = remaining.len().min(out.len() / 2);
+        for (frame, cue) in out.chunks_exact_mut(2).zip(&remaining[..n]) {
+            frame[0] += cue;
+            frame[1] += cue;
+        }
+        self.pos += n;
+        self.pos < self.samples.len()
+    }
+}
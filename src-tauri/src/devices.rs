@@ -0,0 +1,313 @@
+use cpal::{
+    BufferSize, SampleFormat, SampleRate, StreamConfig, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+
+use crate::types::{AudioDevice, DeviceConfig, SupportedDeviceConfig};
+
+/// Sample formats in order of preference when the default one isn't
+/// available at the chosen rate and channel count.
+const FORMAT_PREFERENCE: [SampleFormat; 10] = [
+    SampleFormat::F32,
+    SampleFormat::I16,
+    SampleFormat::I32,
+    SampleFormat::F64,
+    SampleFormat::U16,
+    SampleFormat::I64,
+    SampleFormat::U32,
+    SampleFormat::U64,
+    SampleFormat::I8,
+    SampleFormat::U8,
+];
+
+/// Device a running stream should move to, or `None` to leave it alone.
+/// `Some(None)` means the system default.
+///
+/// The preferred device is used whenever it is plugged in; while it is
+/// missing the default stands in, and the stream moves back once it returns.
+/// A stream on the default follows the default when that changes.
+pub fn failover_target(
+    preferred: Option<&str>,
+    current: Option<&str>,
+    available: &[AudioDevice],
+    stream_failed: bool,
+    default_changed: bool,
+) -> Option<Option<String>> {
+    // An empty list is more likely a failed enumeration than every device
+    // being unplugged; don't move a working stream because of it
+    if available.is_empty() && !stream_failed {
+        return None;
+    }
+
+    let wanted = preferred.filter(|name| available.iter().any(|d| d.name == *name));
+    if stream_failed || wanted != current || (current.is_none() && default_changed) {
+        Some(wanted.map(str::to_string))
+    } else {
+        None
+    }
+}
+
+pub fn default_device(devices: &[AudioDevice]) -> Option<&str> {
+    devices
+        .iter()
+        .find(|d| d.is_default)
+        .map(|d| d.name.as_str())
+}
+
+/// Name fragments of inputs that capture what the system plays rather than a
+/// microphone: PulseAudio/PipeWire monitor sources, Windows "Stereo Mix" and
+/// the usual virtual loopback drivers.
+const LOOPBACK_NAME_HINTS: [&str; 7] = [
+    "monitor",
+    "loopback",
+    "stereo mix",
+    "what u hear",
+    "blackhole",
+    "soundflower",
+    "cable output",
+];
+
+pub fn is_loopback_device(name: &str) -> bool {
+    let name = name.to_lowercase();
+    LOOPBACK_NAME_HINTS.iter().any(|hint| name.contains(hint))
+}
+
+/// Input devices to offer for sharing system audio: likely loopback inputs
+/// first, then the rest in their original order, since any input will do.
+pub fn audio_share_sources(mut devices: Vec<AudioDevice>) -> Vec<AudioDevice> {
+    devices.sort_by_key(|d| !is_loopback_device(&d.name));
+    devices
+}
+
+/// Stream config and sample format to open a device with: the device
+/// default, with the wanted rate, channel count and buffer size applied where
+/// the device supports them.
+pub fn choose_stream_config(
+    default: &SupportedStreamConfig,
+    supported: &[SupportedStreamConfigRange],
+    wanted: &DeviceConfig,
+) -> (StreamConfig, SampleFormat) {
+    let channels = wanted.channels.unwrap_or(default.channels());
+    let rate = wanted.sample_rate.unwrap_or(default.sample_rate().0);
+
+    let (format, buffer_range) =
+        if channels == default.channels() && rate == default.sample_rate().0 {
+            (default.sample_format(), *default.buffer_size())
+        } else {
+            let rank = |format: SampleFormat| {
+                if format == default.sample_format() {
+                    0
+                } else {
+                    FORMAT_PREFERENCE
+                        .iter()
+                        .position(|f| *f == format)
+                        .map_or(usize::MAX, |i| i + 1)
+                }
+            };
+            match supported
+                .iter()
+                .filter(|range| {
+                    range.channels() == channels
+                        && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&rate)
+                })
+                .min_by_key(|range| rank(range.sample_format()))
+            {
+                Some(range) => (range.sample_format(), *range.buffer_size()),
+                None => {
+                    tracing::warn!(
+                    "Device doesn't support {rate}Hz with {channels} channels, using its default"
+                );
+                    return choose_stream_config(
+                        default,
+                        supported,
+                        &DeviceConfig {
+                            buffer_size: wanted.buffer_size,
+                            ..DeviceConfig::default()
+                        },
+                    );
+                }
+            }
+        };
+
+    let buffer_size = match (wanted.buffer_size, buffer_range) {
+        (Some(frames), SupportedBufferSize::Range { min, max })
+            if (min..=max).contains(&frames) =>
+        {
+            BufferSize::Fixed(frames)
+        }
+        // The device can't tell; let opening the stream decide
+        (Some(frames), SupportedBufferSize::Unknown) => BufferSize::Fixed(frames),
+        (Some(frames), _) => {
+            tracing::warn!("Device doesn't support a {frames}-frame buffer, using its default");
+            BufferSize::Default
+        }
+        (None, _) => BufferSize::Default,
+    };
+
+    (
+        StreamConfig {
+            channels,
+            sample_rate: SampleRate(rate),
+            buffer_size,
+        },
+        format,
+    )
+}
+
+pub fn describe_config_range(range: &SupportedStreamConfigRange) -> SupportedDeviceConfig {
+    let (min_buffer_size, max_buffer_size) = match range.buffer_size() {
+        SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+        SupportedBufferSize::Unknown => (None, None),
+    };
+    SupportedDeviceConfig {
+        channels: range.channels(),
+        min_sample_rate: range.min_sample_rate().0,
+        max_sample_rate: range.max_sample_rate().0,
+        sample_format: range.sample_format().to_string(),
+        min_buffer_size,
+        max_buffer_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices(names: &[&str]) -> Vec<AudioDevice> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| AudioDevice {
+                name: name.to_string(),
+                is_default: i == 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn falls_back_to_default_and_returns_to_preferred() {
+        let with_headset = devices(&["Built-in", "USB Headset"]);
+        let without = devices(&["Built-in"]);
+        let headset = Some("USB Headset");
+
+        assert_eq!(
+            failover_target(headset, headset, &with_headset, false, false),
+            None
+        );
+        // Unplugged: move to the default
+        assert_eq!(
+            failover_target(headset, headset, &without, false, false),
+            Some(None)
+        );
+        assert_eq!(failover_target(headset, None, &without, false, false), None);
+        // Plugged back in: move back
+        assert_eq!(
+            failover_target(headset, None, &with_headset, false, false),
+            Some(Some("USB Headset".to_string()))
+        );
+    }
+
+    #[test]
+    fn restarts_failed_streams_and_follows_the_default() {
+        let list = devices(&["Built-in"]);
+
+        assert_eq!(failover_target(None, None, &list, true, false), Some(None));
+        assert_eq!(failover_target(None, None, &list, false, true), Some(None));
+        assert_eq!(failover_target(None, None, &list, false, false), None);
+        // A failed enumeration doesn't move a healthy stream
+        assert_eq!(
+            failover_target(Some("USB Headset"), Some("USB Headset"), &[], false, false),
+            None
+        );
+        assert_eq!(default_device(&list), Some("Built-in"));
+    }
+
+    #[test]
+    fn lists_loopback_inputs_first() {
+        let sources = audio_share_sources(devices(&[
+            "Built-in Microphone",
+            "USB Headset",
+            "Monitor of Built-in Audio Analog Stereo",
+            "BlackHole 2ch",
+        ]));
+        let names: Vec<&str> = sources.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Monitor of Built-in Audio Analog Stereo",
+                "BlackHole 2ch",
+                "Built-in Microphone",
+                "USB Headset"
+            ]
+        );
+        assert!(is_loopback_device("Stereo Mix (Realtek Audio)"));
+        assert!(!is_loopback_device("pulse"));
+    }
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            format,
+        )
+    }
+
+    #[test]
+    fn chooses_a_supported_format_for_the_wanted_config() {
+        // An i16-only USB headset, stereo at 44.1 or 48kHz by default
+        let default = SupportedStreamConfig::new(
+            2,
+            SampleRate(48_000),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            SampleFormat::I16,
+        );
+        let supported = [
+            range(1, 8_000, 48_000, SampleFormat::U16),
+            range(1, 8_000, 48_000, SampleFormat::I16),
+            range(2, 44_100, 48_000, SampleFormat::I16),
+        ];
+
+        let (config, format) = choose_stream_config(&default, &supported, &DeviceConfig::default());
+        assert_eq!((config.channels, config.sample_rate.0), (2, 48_000));
+        assert_eq!(format, SampleFormat::I16);
+        assert_eq!(config.buffer_size, BufferSize::Default);
+
+        let wanted = DeviceConfig {
+            sample_rate: Some(16_000),
+            channels: Some(1),
+            buffer_size: Some(256),
+        };
+        let (config, format) = choose_stream_config(&default, &supported, &wanted);
+        assert_eq!((config.channels, config.sample_rate.0), (1, 16_000));
+        assert_eq!(format, SampleFormat::I16);
+        assert_eq!(config.buffer_size, BufferSize::Fixed(256));
+    }
+
+    #[test]
+    fn unsupported_choices_fall_back_to_the_default() {
+        let default = SupportedStreamConfig::new(
+            2,
+            SampleRate(44_100),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            SampleFormat::F32,
+        );
+        let supported = [range(2, 44_100, 48_000, SampleFormat::F32)];
+
+        let wanted = DeviceConfig {
+            sample_rate: Some(96_000),
+            channels: Some(6),
+            buffer_size: Some(8192),
+        };
+        let (config, format) = choose_stream_config(&default, &supported, &wanted);
+        assert_eq!((config.channels, config.sample_rate.0), (2, 44_100));
+        assert_eq!(format, SampleFormat::F32);
+        assert_eq!(config.buffer_size, BufferSize::Default);
+    }
+}
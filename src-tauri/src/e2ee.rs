@@ -0,0 +1,292 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Result;
+use bytes::Bytes;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::types::E2eeState;
+
+/// Random bytes in a room secret (32 hex characters in the call code).
+const ROOM_SECRET_LEN: usize = 16;
+const KEY_INFO: &[u8] = b"entavi e2ee v1 audio";
+/// Random stream prefix (8 bytes) + frame counter (4 bytes, big endian),
+/// which together form the nonce, ahead of each encrypted frame.
+const FRAME_HEADER_LEN: usize = 12;
+const NONCE_PREFIX_LEN: usize = 8;
+/// Consecutive frames that fail to decrypt before a peer is reported as
+/// holding a different key, rather than just a corrupted packet.
+const MISMATCH_FRAMES: u32 = 25; // 500ms
+
+/// Secret shared through the invite (`<room id>#<secret>`). The server only
+/// ever sees the room ID before the `#`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RoomSecret([u8; ROOM_SECRET_LEN]);
+
+impl RoomSecret {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        anyhow::ensure!(
+            hex.len() == ROOM_SECRET_LEN * 2 && hex.is_ascii(),
+            "Invalid room key in call code"
+        );
+        let mut secret = [0u8; ROOM_SECRET_LEN];
+        for (i, byte) in secret.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| anyhow::anyhow!("Invalid room key in call code"))?;
+        }
+        Ok(Self(secret))
+    }
+}
+
+/// Split a call code into the room ID and the optional end-to-end secret.
+pub fn split_call_code(code: &str) -> Result<(String, Option<RoomSecret>)> {
+    match code.split_once('#') {
+        Some((room_id, secret)) => Ok((room_id.to_string(), Some(RoomSecret::from_hex(secret)?))),
+        None => Ok((code.to_string(), None)),
+    }
+}
+
+pub fn call_code(room_id: &str, secret: Option<&RoomSecret>) -> String {
+    match secret {
+        Some(secret) => format!("{room_id}#{}", secret.to_hex()),
+        None => room_id.to_string(),
+    }
+}
+
+/// AES-256-GCM over Opus payloads, with the key derived from the room secret.
+/// Every participant and every connection shares the key, so each sending
+/// stream draws a random 8-byte prefix and counts its frames; the nonce is
+/// the prefix plus the count, both sent ahead of the ciphertext. Unrelated to
+/// the RTP SSRC, so a clash there can't repeat a nonce. The RTP timestamp and sequence number
+/// are authenticated but not part of the nonce, since they repeat when the
+/// capture restarts. A frame grows by 28 bytes (header and tag).
+#[derive(Clone)]
+pub struct FrameCipher {
+    cipher: Aes256Gcm,
+}
+
+impl FrameCipher {
+    pub fn new(secret: &RoomSecret, room_id: &str) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(room_id.as_bytes()), &secret.0);
+        let mut key = [0u8; 32];
+        hkdf.expand(KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            cipher: Aes256Gcm::new(&key.into()),
+        }
+    }
+
+    pub fn decrypt(&self, timestamp: u32, seq: u16, frame: &[u8]) -> Result<Bytes> {
+        anyhow::ensure!(
+            frame.len() > FRAME_HEADER_LEN,
+            "Encrypted audio frame too short"
+        );
+        let (nonce, sealed) = frame.split_at(FRAME_HEADER_LEN);
+        let opened = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &rtp_aad(timestamp, seq),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Audio frame failed to decrypt"))?;
+        Ok(Bytes::from(opened))
+    }
+}
+
+/// Encrypts one sending stream (one peer connection's audio track).
+pub struct FrameSealer {
+    cipher: FrameCipher,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    /// Frames sealed so far; never reset, so no nonce is used twice.
+    counter: AtomicU32,
+}
+
+impl FrameSealer {
+    pub fn new(cipher: FrameCipher) -> Self {
+        Self {
+            cipher,
+            prefix: rand::random(),
+            counter: AtomicU32::new(0),
+        }
+    }
+
+    /// Fails once the counter is used up (after about two years of audio)
+    /// rather than wrap round to a nonce already used.
+    pub fn seal(&self, timestamp: u32, seq: u16, payload: &[u8]) -> Result<Bytes> {
+        let count = self
+            .counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_add(1))
+            .map_err(|_| anyhow::anyhow!("Audio frame counter exhausted"))?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len() + 16);
+        frame.extend_from_slice(&self.prefix);
+        frame.extend_from_slice(&count.to_be_bytes());
+        let sealed = self
+            .cipher
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&frame),
+                Payload {
+                    msg: payload,
+                    aad: &rtp_aad(timestamp, seq),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt audio frame"))?;
+        frame.extend_from_slice(&sealed);
+        Ok(Bytes::from(frame))
+    }
+}
+
+fn rtp_aad(timestamp: u32, seq: u16) -> [u8; 6] {
+    let mut aad = [0u8; 6];
+    aad[..4].copy_from_slice(&timestamp.to_be_bytes());
+    aad[4..].copy_from_slice(&seq.to_be_bytes());
+    aad
+}
+
+/// Tracks whether a peer's frames decrypt with our key.
+pub struct KeyCheck {
+    state: E2eeState,
+    failures: u32,
+}
+
+impl KeyCheck {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            state: if enabled {
+                E2eeState::Pending
+            } else {
+                E2eeState::Disabled
+            },
+            failures: 0,
+        }
+    }
+
+    pub fn record(&mut self, decrypted: bool) {
+        if decrypted {
+            self.failures = 0;
+            self.state = E2eeState::Verified;
+        } else {
+            self.failures += 1;
+            if self.failures >= MISMATCH_FRAMES {
+                self.state = E2eeState::Mismatch;
+            }
+        }
+    }
+
+    pub fn state(&self) -> E2eeState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_code_round_trips_and_keeps_secret_out_of_room_id() {
+        let secret = RoomSecret::generate();
+        let code = call_code("abc123", Some(&secret));
+
+        let (room_id, parsed) = split_call_code(&code).unwrap();
+
+        assert_eq!(room_id, "abc123");
+        assert!(parsed == Some(secret));
+        assert!(split_call_code("abc123").unwrap().1.is_none());
+        assert!(split_call_code("abc123#zz").is_err());
+    }
+
+    #[test]
+    fn only_the_same_room_key_decrypts() {
+        let secret = RoomSecret::generate();
+        let sender = FrameSealer::new(FrameCipher::new(&secret, "abc123"));
+        let receiver = FrameCipher::new(&secret, "abc123");
+        let frame = b"opus frame";
+
+        let sealed = sender.seal(960, 1, frame).unwrap();
+        assert_eq!(sealed.len(), frame.len() + 28);
+        assert_eq!(&receiver.decrypt(960, 1, &sealed).unwrap()[..], frame);
+
+        // Wrong header fields, wrong secret or wrong room all fail
+        assert!(receiver.decrypt(1920, 1, &sealed).is_err());
+        assert!(receiver.decrypt(960, 2, &sealed).is_err());
+        let stranger = FrameCipher::new(&RoomSecret::generate(), "abc123");
+        assert!(stranger.decrypt(960, 1, &sealed).is_err());
+        let other_room = FrameCipher::new(&secret, "def456");
+        assert!(other_room.decrypt(960, 1, &sealed).is_err());
+        assert!(receiver.decrypt(960, 1, b"abc").is_err());
+    }
+
+    #[test]
+    fn nonces_never_repeat_when_the_capture_restarts() {
+        let secret = RoomSecret::generate();
+        let sender = FrameSealer::new(FrameCipher::new(&secret, "abc123"));
+        let receiver = FrameCipher::new(&secret, "abc123");
+        let mut nonces = std::collections::HashSet::new();
+        let mut seq = 0u16;
+
+        // Each capture restart starts the timestamps over; with DTX gaps the
+        // sequence number comes back round to the same values too
+        for _restart in 0..3 {
+            for frame in 0..70_000u32 {
+                let timestamp = frame * 960;
+                let sealed = sender.seal(timestamp, seq, b"opus").unwrap();
+                let nonce: [u8; FRAME_HEADER_LEN] = sealed[..FRAME_HEADER_LEN].try_into().unwrap();
+                assert!(nonces.insert(nonce), "nonce reused at frame {frame}");
+                if frame % 10_000 == 0 {
+                    assert_eq!(
+                        &receiver.decrypt(timestamp, seq, &sealed).unwrap()[..],
+                        b"opus"
+                    );
+                }
+                seq = seq.wrapping_add(1);
+            }
+        }
+    }
+
+    #[test]
+    fn streams_use_their_own_nonce_prefix() {
+        let cipher = FrameCipher::new(&RoomSecret::generate(), "abc123");
+        let first = FrameSealer::new(cipher.clone())
+            .seal(0, 0, b"opus")
+            .unwrap();
+        let second = FrameSealer::new(cipher.clone())
+            .seal(0, 0, b"opus")
+            .unwrap();
+        assert_ne!(first[..FRAME_HEADER_LEN], second[..FRAME_HEADER_LEN]);
+
+        let spent = FrameSealer::new(cipher);
+        spent.counter.store(u32::MAX - 1, Ordering::Relaxed);
+        assert!(spent.seal(0, 0, b"opus").is_ok());
+        assert!(spent.seal(0, 0, b"opus").is_err());
+    }
+
+    #[test]
+    fn key_check_needs_a_run_of_failures_to_report_mismatch() {
+        let mut check = KeyCheck::new(true);
+        assert_eq!(check.state(), E2eeState::Pending);
+
+        check.record(true);
+        assert_eq!(check.state(), E2eeState::Verified);
+        for _ in 0..MISMATCH_FRAMES - 1 {
+            check.record(false);
+        }
+        assert_eq!(check.state(), E2eeState::Verified);
+        check.record(false);
+        assert_eq!(check.state(), E2eeState::Mismatch);
+
+        assert_eq!(KeyCheck::new(false).state(), E2eeState::Disabled);
+    }
+}
@@ -1,22 +1,62 @@
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::Mutex;
 
 use crate::types::AudioDevice;
 
-use crate::audio::{AudioCapture, AudioPlayback, MicTest};
-use crate::peer::PeerConn;
+use crate::audio::{
+    AudioCapture, AudioPlayback, CaptureProcessing, MicTest, MicTestMode, SharedAudio,
+};
+use crate::bitrate::{BitrateBounds, BitrateController, LinkReport, DEFAULT_EXPECTED_LOSS_PERC};
+use crate::chat::{self, ChatPayload};
+use crate::cue::Cue;
+use crate::devices;
+use crate::e2ee::{self, FrameCipher, RoomSecret};
+use crate::file_transfer::{
+    self, FileChannelEvent, FilePayload, FileTransfers, IncomingFile, IncomingState, OutgoingFile,
+    FILE_CHUNK_SIZE,
+};
+use crate::music;
+use crate::peer::{PeerConn, PeerEvents};
+use crate::recording::{RecordedTrack, Recording, RecordingFrame};
+use crate::resampler::downmix_stereo;
+use crate::settings::{self, Settings, SettingsWriter, MAX_SHARED_AUDIO_GAIN};
 use crate::signaling;
+use crate::source::{AudioSource, FileSource, Paced, Prefetched, ToneSource};
 use crate::types::*;
+use crate::vad::{NoiseFloor, MAX_VAD_THRESHOLD, MIN_VAD_THRESHOLD};
 
 const DEFAULT_SIGNALING_URL: &str = "wss://entavi-signaling.avdo.workers.dev/ws";
+/// Loudest a single participant can be boosted in the local mix.
+const MAX_PEER_VOLUME: f32 = 2.0;
+const DEFAULT_PTT_SHORTCUT: &str = "CommandOrControl+Shift+Space";
+/// Test tone played into a call: an A at -12 dBFS.
+const TEST_TONE_FREQUENCY: f32 = 440.0;
+const TEST_TONE_AMPLITUDE: f32 = 0.25;
+const TEST_TONE_DURATION: Duration = Duration::from_secs(2);
+/// How long the room noise floor is measured for VAD calibration.
+const VAD_CALIBRATION_DURATION: Duration = Duration::from_secs(3);
 const ICE_QUEUE_MESSAGES: usize = 256;
 const CONN_STATE_QUEUE_MESSAGES: usize = 32;
+const LINK_REPORT_QUEUE_MESSAGES: usize = 64;
+const CHAT_QUEUE_MESSAGES: usize = 64;
+const FILE_EVENT_QUEUE_MESSAGES: usize = 256;
+/// Minimum gap between `file-progress` events for one transfer.
+const FILE_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the `call-stats` event is emitted during a call.
+const CALL_STATS_INTERVAL: Duration = Duration::from_secs(2);
+/// How often audio devices are enumerated to notice them being plugged in
+/// or removed.
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 fn queue_signal(tx: &flume::Sender<SignalMessage>, msg: SignalMessage) -> Result<()> {
     match tx.try_send(msg) {
@@ -26,18 +66,37 @@ fn queue_signal(tx: &flume::Sender<SignalMessage>, msg: SignalMessage) -> Result
     }
 }
 
+/// Parse a push-to-talk accelerator such as "CommandOrControl+Shift+Space";
+/// `None` gives the default.
+fn parse_ptt_shortcut(shortcut: Option<&str>) -> Result<Shortcut> {
+    let shortcut = shortcut.unwrap_or(DEFAULT_PTT_SHORTCUT);
+    shortcut
+        .parse::<Shortcut>()
+        .map_err(|e| anyhow::anyhow!("Invalid shortcut '{shortcut}': {e}"))
+}
+
 /// Engine is the central orchestrator stored in Tauri managed state.
 pub struct Engine {
     inner: Arc<Mutex<Option<EngineInner>>>,
     app: AppHandle,
+    /// Saves settings off the caller's thread; `None` if the file couldn't
+    /// be read, so a file we don't understand is never overwritten.
+    settings_writer: Option<SettingsWriter>,
     /// Persists across sessions - not inside EngineInner.
     selected_input_device: std::sync::Mutex<Option<String>>,
     selected_output_device: std::sync::Mutex<Option<String>>,
+    /// Stream settings chosen per device, by device name.
+    input_configs: std::sync::Mutex<DeviceConfigs>,
+    output_configs: std::sync::Mutex<DeviceConfigs>,
     signaling_url: std::sync::Mutex<Option<String>>,
     mic_test: std::sync::Mutex<Option<MicTest>>,
-    noise_suppression: Arc<AtomicBool>,
-    vad_threshold: Arc<AtomicU32>,
-    agc_enabled: Arc<AtomicBool>,
+    processing: CaptureProcessing,
+    /// Local playback gain per participant display name (0 = locally muted).
+    /// Keyed by name so it survives peers rejoining with a new peer ID.
+    peer_volumes: Arc<std::sync::Mutex<HashMap<String, f32>>>,
+    /// Global shortcut currently bound to push-to-talk, if that mode is active.
+    ptt_shortcut: std::sync::Mutex<Option<String>>,
+    bitrate_bounds: std::sync::Mutex<BitrateBounds>,
 }
 
 struct EngineInner {
@@ -45,10 +104,30 @@ struct EngineInner {
     peer_name: String,
     room_id: String,
     room_name: String,
+    /// Room password, kept so reconnects can rejoin a protected room.
+    password: Option<String>,
+    /// Frame cipher when the call code carries an end-to-end key.
+    cipher: Option<FrameCipher>,
     signal_tx: flume::Sender<SignalMessage>,
     peers: HashMap<String, Arc<PeerConn>>,
+    /// Display names of remote peers, by peer ID
+    peer_names: HashMap<String, String>,
+    peer_volumes: Arc<std::sync::Mutex<HashMap<String, f32>>>,
     capture: AudioCapture,
     playback: AudioPlayback,
+    /// Devices the capture and playback were opened on (`None` = system
+    /// default). They differ from the preferred ones while those are unplugged.
+    capture_device: Option<String>,
+    playback_device: Option<String>,
+    /// System audio mixed into what we send, while it's being shared.
+    shared_audio: Option<SharedAudio>,
+    processing: CaptureProcessing,
+    /// Mute toggled by the user (UI/tray), independent of push-to-talk.
+    muted: bool,
+    /// Local sound cue being mixed into playback, if any.
+    cue: Option<Cue>,
+    /// Call recording in progress, fed from the mixer tick.
+    recording: Option<Recording>,
     /// Channel for ICE candidates generated by any PeerConn
     ice_tx: flume::Sender<(String, SignalPayload)>,
     ice_rx: flume::Receiver<(String, SignalPayload)>,
@@ -61,6 +140,18 @@ struct EngineInner {
         String,
         webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState,
     )>,
+    /// RTCP reception reports about our outgoing audio, from any PeerConn
+    link_report_tx: flume::Sender<(String, LinkReport)>,
+    link_report_rx: flume::Receiver<(String, LinkReport)>,
+    /// Chat payloads received on any PeerConn's data channel
+    chat_tx: flume::Sender<(String, ChatPayload)>,
+    chat_rx: flume::Receiver<(String, ChatPayload)>,
+    /// File channel traffic from any PeerConn, handled by `file_transfer_loop`
+    file_tx: flume::Sender<(String, FileChannelEvent)>,
+    file_transfers: Arc<std::sync::Mutex<FileTransfers>>,
+    /// Per-peer bitrate adaptation; the encoder follows the worst link.
+    bitrate_controllers: HashMap<String, BitrateController>,
+    bitrate_bounds: BitrateBounds,
     /// RTT measurements from signaling ping/pong
     rtt_rx: flume::Receiver<u64>,
     /// Signaling connection status
@@ -73,40 +164,177 @@ struct EngineInner {
 
 impl Engine {
     pub fn new(app: AppHandle) -> Self {
-        Self {
+        let (settings, settings_writer) = match app.path().app_config_dir() {
+            Ok(dir) => {
+                let path = dir.join(settings::SETTINGS_FILE);
+                match Settings::load(&path) {
+                    Ok(settings) => {
+                        let writer = SettingsWriter::spawn(path)
+                            .inspect_err(|e| tracing::warn!("Settings won't be saved: {e:#}"))
+                            .ok();
+                        (settings, writer)
+                    }
+                    Err(e) => {
+                        tracing::warn!("Not using saved settings: {e:#}");
+                        (Settings::default(), None)
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("No config folder for settings: {e}");
+                (Settings::default(), None)
+            }
+        };
+
+        let engine = Self {
             inner: Arc::new(Mutex::new(None)),
             app,
-            selected_input_device: std::sync::Mutex::new(None),
-            selected_output_device: std::sync::Mutex::new(None),
-            signaling_url: std::sync::Mutex::new(None),
+            settings_writer,
+            selected_input_device: std::sync::Mutex::new(settings.input_device.clone()),
+            selected_output_device: std::sync::Mutex::new(settings.output_device.clone()),
+            input_configs: std::sync::Mutex::new(settings.input_configs.clone()),
+            output_configs: std::sync::Mutex::new(settings.output_configs.clone()),
+            signaling_url: std::sync::Mutex::new(settings.signaling_url.clone()),
             mic_test: std::sync::Mutex::new(None),
-            noise_suppression: Arc::new(AtomicBool::new(true)),
-            vad_threshold: Arc::new(AtomicU32::new(0.01f32.to_bits())),
-            agc_enabled: Arc::new(AtomicBool::new(true)),
+            processing: CaptureProcessing::default(),
+            peer_volumes: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            ptt_shortcut: std::sync::Mutex::new(None),
+            bitrate_bounds: std::sync::Mutex::new(settings.bitrate_bounds()),
+        };
+        engine.apply_processing_settings(&settings);
+        engine
+    }
+
+    fn apply_processing_settings(&self, settings: &Settings) {
+        let processing = &self.processing;
+        processing
+            .noise_suppression
+            .store(settings.noise_suppression, Ordering::Relaxed);
+        processing
+            .echo_cancellation
+            .store(settings.echo_cancellation, Ordering::Relaxed);
+        processing
+            .vad_threshold
+            .store(settings.vad_threshold.to_bits(), Ordering::Relaxed);
+        processing
+            .agc_enabled
+            .store(settings.agc_enabled, Ordering::Relaxed);
+        processing
+            .shared_audio_gain
+            .store(settings.shared_audio_gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Current preferences, as they would be saved.
+    pub fn settings(&self) -> Settings {
+        let processing = &self.processing;
+        let bounds = *self.bitrate_bounds.lock().unwrap();
+        Settings {
+            version: settings::SETTINGS_VERSION,
+            input_device: self.selected_input_device.lock().unwrap().clone(),
+            output_device: self.selected_output_device.lock().unwrap().clone(),
+            input_configs: self.input_configs(),
+            output_configs: self.output_configs(),
+            signaling_url: self.signaling_url.lock().unwrap().clone(),
+            noise_suppression: processing.noise_suppression.load(Ordering::Relaxed),
+            echo_cancellation: processing.echo_cancellation.load(Ordering::Relaxed),
+            vad_threshold: f32::from_bits(processing.vad_threshold.load(Ordering::Relaxed)),
+            agc_enabled: processing.agc_enabled.load(Ordering::Relaxed),
+            min_bitrate: bounds.min,
+            max_bitrate: bounds.max,
+            shared_audio_gain: f32::from_bits(processing.shared_audio_gain.load(Ordering::Relaxed)),
         }
     }
 
-    pub fn set_signaling_url(&self, url: Option<String>) {
+    /// Apply a full set of preferences and save them. Values out of range
+    /// are repaired; the settings in effect are returned.
+    pub async fn update_settings(&self, settings: Settings) -> Result<Settings> {
+        let settings = settings.validated();
+        let applied = self.apply_settings(&settings).await;
+        // Whatever applied before a failure is saved too, in one write
+        self.save_settings();
+        applied.map(|()| self.settings())
+    }
+
+    async fn apply_settings(&self, settings: &Settings) -> Result<()> {
+        let current = self.settings();
+
+        if settings.input_device != current.input_device {
+            self.apply_input_device(settings.input_device.clone())
+                .await?;
+        }
+        if settings.output_device != current.output_device {
+            self.apply_output_device(settings.output_device.clone())
+                .await?;
+        }
+        if settings.input_configs != current.input_configs {
+            *self.input_configs.lock().unwrap() = settings.input_configs.clone();
+            self.reopen_capture().await?;
+        }
+        if settings.output_configs != current.output_configs {
+            *self.output_configs.lock().unwrap() = settings.output_configs.clone();
+            self.reopen_playback().await?;
+        }
+        if settings.bitrate_bounds() != current.bitrate_bounds() {
+            self.apply_bitrate_bounds(settings.min_bitrate, settings.max_bitrate)
+                .await?;
+        }
+        *self.signaling_url.lock().unwrap() = settings.signaling_url.clone();
+        self.apply_processing_settings(settings);
+        Ok(())
+    }
+
+    /// Queue the current preferences to be written to disk. Failures are
+    /// logged, not returned: the setting still applies for this session.
+    fn save_settings(&self) {
+        if let Some(writer) = &self.settings_writer {
+            writer.save(self.settings());
+        }
+    }
+
+    /// Use a custom signaling server from the next call on; blank goes back
+    /// to the default one.
+    pub fn set_signaling_url(&self, url: Option<String>) -> Result<()> {
+        let url = settings::validate_signaling_url(url)?;
         *self.signaling_url.lock().unwrap() = url;
+        self.save_settings();
+        Ok(())
     }
 
-    /// Create a new room. Returns the room_id (6-char code).
-    pub async fn create_room(&self, room_name: String, name: String) -> Result<String> {
+    /// Create a new room. Returns the call code: the room_id (6-char code),
+    /// followed by `#<room key>` when `e2ee` is set. With a password, everyone
+    /// joining later has to present the same one.
+    pub async fn create_room(
+        &self,
+        room_name: String,
+        name: String,
+        password: Option<String>,
+        e2ee: bool,
+    ) -> Result<String> {
         let room_id = uuid::Uuid::new_v4().to_string()[..6].to_string();
-        self.join_room_inner(room_id.clone(), room_name, name, None, true)
-            .await?;
-        Ok(room_id)
+        let secret = e2ee.then(RoomSecret::generate);
+        self.join_room_inner(
+            room_id.clone(),
+            room_name,
+            name,
+            password,
+            secret.clone(),
+            true,
+        )
+        .await?;
+        Ok(e2ee::call_code(&room_id, secret.as_ref()))
     }
 
-    /// Join an existing room by room_id.
+    /// Join an existing room by call code. The room key after `#`, if any,
+    /// stays on this device; only the room_id goes to the server.
     pub async fn join_room(
         &self,
-        room_id: String,
+        code: String,
         name: String,
         password: Option<String>,
     ) -> Result<()> {
+        let (room_id, secret) = e2ee::split_call_code(&code)?;
         let room_name = format!("Room {room_id}");
-        self.join_room_inner(room_id, room_name, name, password, false)
+        self.join_room_inner(room_id, room_name, name, password, secret, false)
             .await
     }
 
@@ -116,6 +344,7 @@ impl Engine {
         room_name: String,
         name: String,
         password: Option<String>,
+        secret: Option<RoomSecret>,
         create: bool,
     ) -> Result<()> {
         // Stop mic test if running
@@ -125,6 +354,8 @@ impl Engine {
         self.leave_room().await?;
 
         let peer_id = uuid::Uuid::new_v4().to_string();
+        // An empty password field means an open room
+        let password = password.filter(|p| !p.is_empty());
 
         self.emit_state(CallState::Connecting);
 
@@ -132,14 +363,13 @@ impl Engine {
         let device_name = self.selected_input_device.lock().unwrap().clone();
         let output_device_name = self.selected_output_device.lock().unwrap().clone();
         let capture = AudioCapture::new(
-            device_name,
-            Arc::clone(&self.noise_suppression),
-            Arc::clone(&self.vad_threshold),
-            Arc::clone(&self.agc_enabled),
+            device_name.clone(),
+            self.input_configs(),
+            self.processing.clone(),
         )
         .context("Failed to start audio capture")?;
-        let playback =
-            AudioPlayback::new(output_device_name).context("Failed to start audio playback")?;
+        let playback = AudioPlayback::new(output_device_name.clone(), self.output_configs())
+            .context("Failed to start audio playback")?;
 
         // Connect to signaling server (room_id is part of the URL path)
         let base_url = self
@@ -160,7 +390,7 @@ impl Engine {
                 room_id: room_id.clone(),
                 peer_id: peer_id.clone(),
                 name: name.clone(),
-                password,
+                password: password.clone(),
                 create,
             },
         )
@@ -172,19 +402,49 @@ impl Engine {
         // Peer connection state changes
         let (conn_state_tx, conn_state_rx) = flume::bounded(CONN_STATE_QUEUE_MESSAGES);
 
+        // RTCP reception reports that drive the adaptive bitrate
+        let (link_report_tx, link_report_rx) = flume::bounded(LINK_REPORT_QUEUE_MESSAGES);
+
+        // Chat messages and receipts from the peers' data channels
+        let (chat_tx, chat_rx) = flume::bounded(CHAT_QUEUE_MESSAGES);
+
+        // File transfer control messages and chunks
+        let (file_tx, file_rx) = flume::bounded(FILE_EVENT_QUEUE_MESSAGES);
+        let file_transfers = Arc::new(std::sync::Mutex::new(FileTransfers::default()));
+
+        let session_peer_id = peer_id.clone();
         let inner = EngineInner {
             peer_id,
             peer_name: name,
             room_id: room_id.clone(),
             room_name: room_name.clone(),
+            password,
+            cipher: secret.map(|secret| FrameCipher::new(&secret, &room_id)),
             signal_tx: signal_tx.clone(),
             peers: HashMap::new(),
+            peer_names: HashMap::new(),
+            peer_volumes: Arc::clone(&self.peer_volumes),
             capture,
             playback,
+            capture_device: device_name,
+            playback_device: output_device_name,
+            shared_audio: None,
+            processing: self.processing.clone(),
+            muted: false,
+            cue: None,
+            recording: None,
             ice_tx,
             ice_rx,
             conn_state_tx,
             conn_state_rx,
+            link_report_tx,
+            link_report_rx,
+            chat_tx,
+            chat_rx,
+            file_tx,
+            file_transfers: Arc::clone(&file_transfers),
+            bitrate_controllers: HashMap::new(),
+            bitrate_bounds: *self.bitrate_bounds.lock().unwrap(),
             rtt_rx,
             signaling_status_rx,
             turn_servers: Vec::new(),
@@ -192,8 +452,25 @@ impl Engine {
             room_locked: false,
         };
 
+        inner.update_stereo();
         *self.inner.lock().await = Some(inner);
 
+        // Periodic media stats, separate from the engine loop so slow stats
+        // collection never delays mixing
+        tokio::spawn(call_stats_loop(
+            Arc::clone(&self.inner),
+            self.app.clone(),
+            session_peer_id,
+        ));
+
+        // File transfers write to disk, so they get their own task too
+        tokio::spawn(file_transfer_loop(
+            Arc::clone(&self.inner),
+            self.app.clone(),
+            file_rx,
+            file_transfers,
+        ));
+
         // Start the engine event loop
         let engine_inner = Arc::clone(&self.inner);
         let app = self.app.clone();
@@ -225,7 +502,15 @@ impl Engine {
             guard.take()
         };
 
-        if let Some(inner) = inner {
+        if let Some(mut inner) = inner {
+            if let Some(recording) = inner.recording.take() {
+                if let Err(e) = finish_recording(recording, &self.app).await {
+                    tracing::error!("Failed to finish recording: {e}");
+                }
+            }
+            inner.processing.clip.lock().unwrap().take();
+            inner.stop_audio_share();
+
             // Send leave signal
             let _ = queue_signal(
                 &inner.signal_tx,
@@ -246,17 +531,221 @@ impl Engine {
     }
 
     pub async fn set_muted(&self, muted: bool) -> Result<()> {
+        let mut guard = self.inner.lock().await;
+        if let Some(inner) = guard.as_mut() {
+            inner.muted = muted;
+            inner.capture.set_muted(muted);
+            inner.broadcast_mute_state();
+        }
+        Ok(())
+    }
+
+    /// Switch between open-mic (voice activity) and push-to-talk. In push-to-talk
+    /// mode the mic only transmits while `shortcut` (a global accelerator such as
+    /// `"CommandOrControl+Shift+Space"`) is held.
+    pub async fn set_input_mode(&self, mode: InputMode, shortcut: Option<String>) -> Result<()> {
+        let global_shortcut = self.app.global_shortcut();
+        // The new key is bound before the old one is released, so a shortcut
+        // that can't be used leaves the current mode working
+        let previous = self.ptt_shortcut.lock().unwrap().clone();
+        let previous =
+            previous.and_then(|name| Some((parse_ptt_shortcut(Some(&name)).ok()?, name)));
+
+        match mode {
+            InputMode::VoiceActivity => {
+                self.processing.push_to_talk.store(false, Ordering::Relaxed);
+                if let Some((key, _)) = previous {
+                    let _ = global_shortcut.unregister(key);
+                }
+                *self.ptt_shortcut.lock().unwrap() = None;
+            }
+            InputMode::PushToTalk => {
+                let name = shortcut.unwrap_or_else(|| DEFAULT_PTT_SHORTCUT.to_string());
+                let key = parse_ptt_shortcut(Some(&name))?;
+                let rebinding = previous.as_ref().is_none_or(|(old, _)| *old != key);
+                if rebinding {
+                    global_shortcut
+                        .on_shortcut(key, |app, _, event| {
+                            let pressed = event.state() == ShortcutState::Pressed;
+                            let app = app.clone();
+                            tauri::async_runtime::spawn(async move {
+                                app.state::<Engine>().set_ptt_pressed(pressed).await;
+                            });
+                        })
+                        .with_context(|| format!("Failed to register shortcut '{name}'"))?;
+                    if let Some((old, _)) = previous {
+                        let _ = global_shortcut.unregister(old);
+                    }
+                }
+                tracing::info!("Push-to-talk bound to {name}");
+                *self.ptt_shortcut.lock().unwrap() = Some(name);
+                self.processing.ptt_pressed.store(false, Ordering::Relaxed);
+                self.processing.push_to_talk.store(true, Ordering::Relaxed);
+            }
+        }
+
         let guard = self.inner.lock().await;
         if let Some(inner) = guard.as_ref() {
-            inner.capture.set_muted(muted);
-            // Broadcast mute state to all peers via signaling
-            let _ = queue_signal(&inner.signal_tx, SignalMessage::MuteState { muted });
+            inner.broadcast_mute_state();
         }
         Ok(())
     }
 
+    /// Share music: voice processing and DTX are bypassed and the bitrate
+    /// raised, in stereo once every peer has accepted stereo. Lasts for the
+    /// session.
+    pub async fn set_music_mode(&self, enabled: bool) {
+        self.processing.music_mode.store(enabled, Ordering::Relaxed);
+        let guard = self.inner.lock().await;
+        match guard.as_ref() {
+            Some(inner) => {
+                inner.update_stereo();
+            }
+            None => self.processing.stereo.store(false, Ordering::Relaxed),
+        }
+        let _ = self
+            .app
+            .emit(EVENT_MUSIC_MODE, self.processing.music_mode_state());
+    }
+
+    /// Mix an input device's sound into what we send, typically a monitor or
+    /// loopback of the system output, or stop sharing with `None`. Lasts
+    /// until the call ends.
+    pub async fn set_audio_share(&self, device: Option<String>) -> Result<()> {
+        let shared = device
+            .map(|name| {
+                SharedAudio::new(
+                    name,
+                    self.input_configs(),
+                    self.processing.shared_audio_tx.clone(),
+                )
+            })
+            .transpose()
+            .context("Failed to share audio")?;
+
+        let mut guard = self.inner.lock().await;
+        let inner = guard.as_mut().context("Not in a call")?;
+        let event = AudioShareEvent {
+            device: shared.as_ref().map(|s| s.device().to_string()),
+        };
+        inner.stop_audio_share();
+        inner.shared_audio = shared;
+        let _ = self.app.emit(EVENT_AUDIO_SHARE, event);
+        Ok(())
+    }
+
+    /// Gain for shared audio, relative to the processed mic.
+    pub fn set_shared_audio_gain(&self, gain: f32) {
+        let gain = if gain.is_finite() {
+            gain.clamp(0.0, MAX_SHARED_AUDIO_GAIN)
+        } else {
+            1.0
+        };
+        self.processing
+            .shared_audio_gain
+            .store(gain.to_bits(), Ordering::Relaxed);
+        self.save_settings();
+    }
+
+    /// Play a WAV or Ogg Opus file into the call, mixed with the mic. It
+    /// replaces any clip still playing, and pauses while muted.
+    pub async fn play_audio_clip(&self, path: PathBuf) -> Result<()> {
+        let clip = tokio::task::spawn_blocking(move || FileSource::open(&path, false)).await??;
+        self.play_clip(Box::new(Prefetched::spawn(clip)?)).await
+    }
+
+    /// Play a short tone into the call, to check the others can hear us.
+    pub async fn play_test_tone(&self) -> Result<()> {
+        let tone = ToneSource::new(TEST_TONE_FREQUENCY, TEST_TONE_AMPLITUDE, TEST_TONE_DURATION);
+        self.play_clip(Box::new(tone)).await
+    }
+
+    async fn play_clip(&self, clip: Box<dyn AudioSource + Send>) -> Result<()> {
+        anyhow::ensure!(self.inner.lock().await.is_some(), "Not in a call");
+        *self.processing.clip.lock().unwrap() = Some(clip);
+        Ok(())
+    }
+
+    pub fn stop_audio_clip(&self) {
+        self.processing.clip.lock().unwrap().take();
+    }
+
+    /// Capture from a WAV or Ogg Opus file, looped, instead of the input
+    /// device, or go back to the device with `None`. Gives every run the same
+    /// input when testing the whole pipeline. Lasts until the input device
+    /// changes or the call ends.
+    pub async fn set_input_file(&self, path: Option<PathBuf>) -> Result<()> {
+        let Some(path) = path else {
+            return self.reopen_capture().await;
+        };
+        let open_path = path.clone();
+        let file =
+            tokio::task::spawn_blocking(move || FileSource::open(&open_path, true)).await??;
+        let source = Paced::new(Prefetched::spawn(file)?);
+        let capture = AudioCapture::with_source(Box::new(source), self.processing.clone())
+            .context("Failed to capture from file")?;
+
+        let mut guard = self.inner.lock().await;
+        let inner = guard.as_mut().context("Not in a call")?;
+        let device = inner.capture_device.clone();
+        inner.replace_capture(capture, device);
+        tracing::info!("Capturing from {}", path.display());
+        Ok(())
+    }
+
+    /// Push-to-talk key went down or up.
+    async fn set_ptt_pressed(&self, pressed: bool) {
+        if !self.processing.push_to_talk.load(Ordering::Relaxed) {
+            return;
+        }
+        // Ignore key auto-repeat.
+        if self.processing.ptt_pressed.swap(pressed, Ordering::Relaxed) == pressed {
+            return;
+        }
+
+        let _ = self.app.emit(EVENT_PTT_STATE, pressed);
+
+        let mut guard = self.inner.lock().await;
+        if let Some(inner) = guard.as_mut() {
+            inner.cue = Some(if pressed {
+                Cue::ptt_start()
+            } else {
+                Cue::ptt_stop()
+            });
+            inner.broadcast_mute_state();
+        }
+    }
+
+    /// Set the local playback gain for one participant (0 = locally muted).
+    /// The gain is remembered by display name for the rest of the session.
+    pub async fn set_peer_volume(&self, peer_id: String, gain: f32) -> Result<()> {
+        if !gain.is_finite() {
+            anyhow::bail!("Invalid volume {gain}");
+        }
+        let gain = gain.clamp(0.0, MAX_PEER_VOLUME);
+
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a call")?;
+        let name = inner
+            .peer_names
+            .get(&peer_id)
+            .with_context(|| format!("Unknown peer {peer_id}"))?;
+        self.peer_volumes.lock().unwrap().insert(name.clone(), gain);
+
+        let _ = self
+            .app
+            .emit(EVENT_PEER_VOLUME_CHANGED, PeerVolumeEvent { peer_id, gain });
+        Ok(())
+    }
+
     /// Store the preferred input device. If currently in a call, restart capture.
     pub async fn set_input_device(&self, name: Option<String>) -> Result<()> {
+        let applied = self.apply_input_device(name).await;
+        self.save_settings();
+        applied
+    }
+
+    async fn apply_input_device(&self, name: Option<String>) -> Result<()> {
         {
             let mut dev = self.selected_input_device.lock().unwrap();
             *dev = name.clone();
@@ -265,19 +754,13 @@ impl Engine {
         // If we're in a call, restart the capture thread with the new device.
         let in_call = self.inner.lock().await.is_some();
         if in_call {
-            let new_capture = AudioCapture::new(
-                name,
-                Arc::clone(&self.noise_suppression),
-                Arc::clone(&self.vad_threshold),
-                Arc::clone(&self.agc_enabled),
-            )
-            .context("Failed to restart audio capture")?;
+            let new_capture =
+                AudioCapture::new(name.clone(), self.input_configs(), self.processing.clone())
+                    .context("Failed to restart audio capture")?;
 
             let mut guard = self.inner.lock().await;
             if let Some(inner) = guard.as_mut() {
-                // Replace capture - old one is dropped, its thread exits when
-                // the encoded_tx sender side is dropped (receiver gone).
-                inner.capture = new_capture;
+                inner.replace_capture(new_capture, name);
                 tracing::info!("Restarted audio capture with new input device");
             }
         }
@@ -289,6 +772,11 @@ impl Engine {
         crate::audio::list_input_devices()
     }
 
+    /// Inputs that can be shared into a call, likely loopback devices first.
+    pub fn list_audio_share_sources(&self) -> Vec<AudioDevice> {
+        devices::audio_share_sources(crate::audio::list_input_devices())
+    }
+
     pub fn start_mic_test(&self) -> Result<()> {
         // Stop any existing test first
         self.stop_mic_test();
@@ -297,15 +785,79 @@ impl Engine {
         let test = MicTest::new(
             device_name,
             output_device_name,
+            (self.input_configs(), self.output_configs()),
             self.app.clone(),
-            Arc::clone(&self.noise_suppression),
+            self.processing.clone(),
+            MicTestMode::Loopback,
         )?;
         *self.mic_test.lock().unwrap() = Some(test);
         Ok(())
     }
 
+    /// Measure the room's noise floor through the selected mic for a few
+    /// seconds and propose a VAD threshold just above it. The user should stay
+    /// quiet meanwhile; the proposal is returned, not applied.
+    pub async fn calibrate_vad(&self) -> Result<f32> {
+        self.stop_mic_test();
+        let device_name = self.selected_input_device.lock().unwrap().clone();
+        let (level_tx, level_rx) = flume::unbounded();
+        let test = MicTest::new(
+            device_name,
+            None,
+            (self.input_configs(), self.output_configs()),
+            self.app.clone(),
+            self.processing.clone(),
+            MicTestMode::Calibrate(level_tx),
+        )?;
+        *self.mic_test.lock().unwrap() = Some(test);
+
+        let mut noise_floor = NoiseFloor::default();
+        let deadline = tokio::time::Instant::now() + VAD_CALIBRATION_DURATION;
+        while let Ok(Ok(level)) = tokio::time::timeout_at(deadline, level_rx.recv_async()).await {
+            noise_floor.push(level);
+        }
+        self.stop_mic_test();
+
+        let threshold = noise_floor
+            .proposed_threshold()
+            .context("No audio was captured during calibration")?;
+        tracing::info!("VAD calibration proposed threshold {threshold:.4}");
+        Ok(threshold)
+    }
+
+    /// Peak level (0..1, after noise suppression and AGC) the mic must exceed
+    /// before detected voice counts as speaking.
+    pub fn set_vad_threshold(&self, threshold: f32) {
+        let threshold = if threshold.is_finite() {
+            threshold.clamp(MIN_VAD_THRESHOLD, MAX_VAD_THRESHOLD)
+        } else {
+            MIN_VAD_THRESHOLD
+        };
+        self.processing
+            .vad_threshold
+            .store(threshold.to_bits(), Ordering::Relaxed);
+        self.save_settings();
+    }
+
+    pub fn set_agc_enabled(&self, enabled: bool) {
+        self.processing
+            .agc_enabled
+            .store(enabled, Ordering::Relaxed);
+        self.save_settings();
+    }
+
     pub fn set_noise_suppression(&self, enabled: bool) {
-        self.noise_suppression.store(enabled, Ordering::Relaxed);
+        self.processing
+            .noise_suppression
+            .store(enabled, Ordering::Relaxed);
+        self.save_settings();
+    }
+
+    pub fn set_echo_cancellation(&self, enabled: bool) {
+        self.processing
+            .echo_cancellation
+            .store(enabled, Ordering::Relaxed);
+        self.save_settings();
     }
 
     pub fn list_output_devices(&self) -> Vec<AudioDevice> {
@@ -313,6 +865,12 @@ impl Engine {
     }
 
     pub async fn set_output_device(&self, name: Option<String>) -> Result<()> {
+        let applied = self.apply_output_device(name).await;
+        self.save_settings();
+        applied
+    }
+
+    async fn apply_output_device(&self, name: Option<String>) -> Result<()> {
         {
             let mut dev = self.selected_output_device.lock().unwrap();
             *dev = name.clone();
@@ -321,14 +879,15 @@ impl Engine {
         // If we're in a call, restart the playback thread with the new device.
         let in_call = self.inner.lock().await.is_some();
         if in_call {
-            let new_playback =
-                AudioPlayback::new(name).context("Failed to restart audio playback")?;
+            let new_playback = AudioPlayback::new(name.clone(), self.output_configs())
+                .context("Failed to restart audio playback")?;
 
             let mut guard = self.inner.lock().await;
             if let Some(inner) = guard.as_mut() {
                 // Replace playback - old one is dropped, its thread exits when
                 // the tx sender side is dropped (receiver gone).
                 inner.playback = new_playback;
+                inner.playback_device = name;
                 tracing::info!("Restarted audio playback with new output device");
             }
         }
@@ -336,6 +895,620 @@ impl Engine {
         Ok(())
     }
 
+    fn input_configs(&self) -> DeviceConfigs {
+        self.input_configs.lock().unwrap().clone()
+    }
+
+    fn output_configs(&self) -> DeviceConfigs {
+        self.output_configs.lock().unwrap().clone()
+    }
+
+    /// Default and supported stream configs of a device (`None` = the
+    /// system default), to choose from in the settings.
+    pub fn device_formats(
+        &self,
+        kind: DeviceKind,
+        device_name: Option<String>,
+    ) -> Result<DeviceFormats> {
+        crate::audio::device_formats(kind, device_name.as_deref())
+    }
+
+    /// Use another sample rate, channel count or buffer size for a device;
+    /// an all-default config goes back to the device's own. Reopens the
+    /// call's audio so it applies right away.
+    pub async fn set_device_config(
+        &self,
+        kind: DeviceKind,
+        device_name: String,
+        config: DeviceConfig,
+    ) -> Result<()> {
+        let config = config.validated()?;
+        let configs = match kind {
+            DeviceKind::Input => &self.input_configs,
+            DeviceKind::Output => &self.output_configs,
+        };
+        {
+            let mut configs = configs.lock().unwrap();
+            if config.is_default() {
+                configs.remove(&device_name);
+            } else {
+                configs.insert(device_name, config);
+            }
+        }
+        self.save_settings();
+
+        match kind {
+            DeviceKind::Input => self.reopen_capture().await,
+            DeviceKind::Output => self.reopen_playback().await,
+        }
+    }
+
+    /// Reopen the call's capture on the device it is using, picking up a
+    /// changed stream config.
+    async fn reopen_capture(&self) -> Result<()> {
+        let Some(device) = self
+            .inner
+            .lock()
+            .await
+            .as_ref()
+            .map(|inner| inner.capture_device.clone())
+        else {
+            return Ok(());
+        };
+        let capture = AudioCapture::new(
+            device.clone(),
+            self.input_configs(),
+            self.processing.clone(),
+        )
+        .context("Failed to restart audio capture")?;
+        if let Some(inner) = self.inner.lock().await.as_mut() {
+            inner.replace_capture(capture, device);
+        }
+        Ok(())
+    }
+
+    async fn reopen_playback(&self) -> Result<()> {
+        let Some(device) = self
+            .inner
+            .lock()
+            .await
+            .as_ref()
+            .map(|inner| inner.playback_device.clone())
+        else {
+            return Ok(());
+        };
+        let playback = AudioPlayback::new(device.clone(), self.output_configs())
+            .context("Failed to restart audio playback")?;
+        if let Some(inner) = self.inner.lock().await.as_mut() {
+            inner.playback = playback;
+            inner.playback_device = device;
+        }
+        Ok(())
+    }
+
+    /// Poll the audio devices for as long as the app runs. Emits
+    /// `devices-changed` when the lists change, and during a call moves
+    /// capture and playback off devices that vanished or failed (onto the
+    /// system default) and back to the preferred ones when they return.
+    pub async fn watch_devices(&self) {
+        let mut interval = tokio::time::interval(DEVICE_POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut last: Option<(Vec<AudioDevice>, Vec<AudioDevice>)> = None;
+
+        loop {
+            interval.tick().await;
+            // Enumeration can block for a while on some hosts
+            let Ok((inputs, outputs)) = tokio::task::spawn_blocking(|| {
+                (
+                    crate::audio::list_input_devices(),
+                    crate::audio::list_output_devices(),
+                )
+            })
+            .await
+            else {
+                continue;
+            };
+
+            let mut default_changed = (false, false);
+            if let Some((last_inputs, last_outputs)) = &last {
+                if *last_inputs != inputs || *last_outputs != outputs {
+                    let _ = self.app.emit(
+                        EVENT_DEVICES_CHANGED,
+                        DevicesChangedEvent {
+                            inputs: inputs.clone(),
+                            outputs: outputs.clone(),
+                        },
+                    );
+                }
+                default_changed = (
+                    devices::default_device(last_inputs) != devices::default_device(&inputs),
+                    devices::default_device(last_outputs) != devices::default_device(&outputs),
+                );
+            }
+
+            if let Err(e) = self
+                .fail_over_devices(&inputs, &outputs, default_changed)
+                .await
+            {
+                tracing::warn!("Audio device failover failed: {e:#}");
+            }
+            last = Some((inputs, outputs));
+        }
+    }
+
+    /// Reopen the call's capture and playback where `devices::failover_target`
+    /// says they belong.
+    async fn fail_over_devices(
+        &self,
+        inputs: &[AudioDevice],
+        outputs: &[AudioDevice],
+        (input_default_changed, output_default_changed): (bool, bool),
+    ) -> Result<()> {
+        let preferred_input = self.selected_input_device.lock().unwrap().clone();
+        let preferred_output = self.selected_output_device.lock().unwrap().clone();
+        let (input_target, output_target) = {
+            let mut guard = self.inner.lock().await;
+            let Some(inner) = guard.as_mut() else {
+                return Ok(());
+            };
+            // A shared device that went away isn't replaced by another one
+            if inner
+                .shared_audio
+                .as_ref()
+                .is_some_and(SharedAudio::has_failed)
+            {
+                tracing::warn!("Shared audio device stopped, no longer sharing");
+                inner.stop_audio_share();
+                let _ = self.app.emit(EVENT_AUDIO_SHARE, AudioShareEvent::default());
+            }
+            let input_target = devices::failover_target(
+                preferred_input.as_deref(),
+                inner.capture_device.as_deref(),
+                inputs,
+                inner.capture.has_failed(),
+                input_default_changed,
+            );
+            let output_target = devices::failover_target(
+                preferred_output.as_deref(),
+                inner.playback_device.as_deref(),
+                outputs,
+                inner.playback.has_failed(),
+                output_default_changed,
+            );
+            (
+                input_target.map(|device| (device, inner.capture_device.clone())),
+                output_target.map(|device| (device, inner.playback_device.clone())),
+            )
+        };
+
+        if let Some((device, previous)) = input_target {
+            // Opening happens on the capture thread; if the device still
+            // doesn't work, the next poll sees the new capture failed
+            let capture = AudioCapture::new(
+                device.clone(),
+                self.input_configs(),
+                self.processing.clone(),
+            )
+            .context("Failed to reopen audio capture")?;
+            let mut guard = self.inner.lock().await;
+            let Some(inner) = guard.as_mut() else {
+                return Ok(());
+            };
+            // The user may have picked another device meanwhile
+            if inner.capture_device == previous {
+                tracing::info!("Moving audio capture to {device:?}");
+                inner.replace_capture(capture, device.clone());
+                if device != previous || input_default_changed {
+                    self.emit_device_switched(DeviceKind::Input, device, &preferred_input);
+                }
+            }
+        }
+
+        if let Some((device, previous)) = output_target {
+            let playback = AudioPlayback::new(device.clone(), self.output_configs())
+                .context("Failed to reopen audio playback")?;
+            let mut guard = self.inner.lock().await;
+            let Some(inner) = guard.as_mut() else {
+                return Ok(());
+            };
+            if inner.playback_device == previous {
+                tracing::info!("Moving audio playback to {device:?}");
+                inner.playback = playback;
+                inner.playback_device = device.clone();
+                if device != previous || output_default_changed {
+                    self.emit_device_switched(DeviceKind::Output, device, &preferred_output);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_device_switched(
+        &self,
+        kind: DeviceKind,
+        device: Option<String>,
+        preferred: &Option<String>,
+    ) {
+        let fallback = preferred.is_some() && device.is_none();
+        let _ = self.app.emit(
+            EVENT_DEVICE_SWITCHED,
+            DeviceSwitchedEvent {
+                kind,
+                device,
+                fallback,
+            },
+        );
+    }
+
+    /// Limits (bits per second) for the adaptive Opus bitrate. Applies to the
+    /// current call immediately and to later calls.
+    pub async fn set_bitrate_bounds(&self, min_bitrate: u32, max_bitrate: u32) -> Result<()> {
+        self.apply_bitrate_bounds(min_bitrate, max_bitrate).await?;
+        self.save_settings();
+        Ok(())
+    }
+
+    async fn apply_bitrate_bounds(&self, min_bitrate: u32, max_bitrate: u32) -> Result<()> {
+        let bounds = BitrateBounds::new(min_bitrate, max_bitrate)?;
+        *self.bitrate_bounds.lock().unwrap() = bounds;
+
+        let mut guard = self.inner.lock().await;
+        if let Some(inner) = guard.as_mut() {
+            inner.bitrate_bounds = bounds;
+            for controller in inner.bitrate_controllers.values_mut() {
+                controller.set_bounds(bounds);
+            }
+            inner.update_encoder_target();
+        }
+        Ok(())
+    }
+
+    /// Host only: stop (or allow again) new participants joining the room.
+    pub async fn set_room_locked(&self, locked: bool) -> Result<()> {
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a room")?;
+        anyhow::ensure!(inner.is_host, "Only the host can lock the room");
+        queue_signal(&inner.signal_tx, SignalMessage::LockRoom { locked })
+    }
+
+    /// Host only: remove a participant from the room. The server keeps them
+    /// out until the room is recreated.
+    pub async fn kick_peer(&self, peer_id: String) -> Result<()> {
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a room")?;
+        anyhow::ensure!(inner.is_host, "Only the host can remove participants");
+        anyhow::ensure!(
+            inner.peer_names.contains_key(&peer_id),
+            "Unknown participant {peer_id}"
+        );
+        queue_signal(&inner.signal_tx, SignalMessage::KickPeer { peer_id })
+    }
+
+    /// Host only: ask a participant to mute. Their engine mutes itself; they
+    /// can unmute again afterwards.
+    pub async fn request_peer_mute(&self, peer_id: String) -> Result<()> {
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a room")?;
+        anyhow::ensure!(inner.is_host, "Only the host can mute participants");
+        anyhow::ensure!(
+            inner.peer_names.contains_key(&peer_id),
+            "Unknown participant {peer_id}"
+        );
+        queue_signal(&inner.signal_tx, SignalMessage::RequestMute { peer_id })
+    }
+
+    /// Start recording the call into a new folder under the user's music
+    /// folder. Everyone in the call is told that we are recording. Returns
+    /// the folder.
+    pub async fn start_recording(
+        &self,
+        format: RecordingFormat,
+        layout: RecordingLayout,
+    ) -> Result<PathBuf> {
+        let (dir, local_name) = {
+            let guard = self.inner.lock().await;
+            let inner = guard.as_ref().context("Not in a room")?;
+            anyhow::ensure!(inner.recording.is_none(), "Already recording");
+
+            let base = self
+                .app
+                .path()
+                .audio_dir()
+                .or_else(|_| self.app.path().app_data_dir())
+                .context("No folder to save recordings in")?;
+            let dir = base.join("Entavi").join(format!(
+                "Call {} {}",
+                inner.room_id,
+                chat::now_millis() / 1000
+            ));
+            (dir, inner.peer_name.clone())
+        };
+
+        // Creating the files blocks; the call goes on meanwhile
+        let start_dir = dir.clone();
+        let recording = tokio::task::spawn_blocking(move || {
+            Recording::start(start_dir, format, layout, local_name)
+        })
+        .await??;
+
+        let mut guard = self.inner.lock().await;
+        // The call ended or another recording started while the files were created
+        let Some(inner) = guard.as_mut().filter(|inner| inner.recording.is_none()) else {
+            let in_call = guard.is_some();
+            drop(guard);
+            // A recording started in the same second shares the folder
+            discard_recording(recording, (!in_call).then_some(dir)).await;
+            anyhow::ensure!(!in_call, "Already recording");
+            anyhow::bail!("Not in a room");
+        };
+        inner.recording = Some(recording);
+        inner.capture.set_recording(true);
+        inner.broadcast_recording_state();
+        tracing::info!("Recording call to {}", dir.display());
+
+        let _ = self.app.emit(
+            EVENT_RECORDING_STATE,
+            RecordingStateEvent {
+                recording: true,
+                dir: Some(dir.display().to_string()),
+                files: Vec::new(),
+            },
+        );
+        Ok(dir)
+    }
+
+    /// Stop recording and finalize the files. Returns the files written.
+    pub async fn stop_recording(&self) -> Result<Vec<PathBuf>> {
+        let recording = {
+            let mut guard = self.inner.lock().await;
+            let inner = guard.as_mut().context("Not in a room")?;
+            let recording = inner.recording.take().context("Not recording")?;
+            inner.capture.set_recording(false);
+            inner.broadcast_recording_state();
+            recording
+        };
+        finish_recording(recording, &self.app).await
+    }
+
+    /// Short authentication string for the connection to a peer, to read
+    /// aloud and compare with what the peer sees.
+    pub async fn peer_sas(&self, peer_id: String) -> Result<Vec<SasSymbol>> {
+        let peer = {
+            let guard = self.inner.lock().await;
+            let inner = guard.as_ref().context("Not in a room")?;
+            inner
+                .peers
+                .get(&peer_id)
+                .cloned()
+                .with_context(|| format!("Unknown participant {peer_id}"))?
+        };
+        peer.short_auth_string()
+            .await
+            .context("Connection to this participant is not set up yet")
+    }
+
+    /// Send a chat message to everyone in the call over the peer data
+    /// channels. Returns the message as sent, for the local chat log; each
+    /// recipient's receipt arrives later as a `chat-delivered` event.
+    pub async fn send_chat_message(&self, text: String) -> Result<ChatMessageEvent> {
+        let text = chat::validate_text(&text)?.to_string();
+        let (message, peers) = {
+            let guard = self.inner.lock().await;
+            let inner = guard.as_ref().context("Not in a room")?;
+            let message = ChatMessageEvent {
+                id: uuid::Uuid::new_v4().to_string(),
+                peer_id: inner.peer_id.clone(),
+                name: inner.peer_name.clone(),
+                text,
+                sent_at: chat::now_millis(),
+            };
+            let peers: Vec<_> = inner.peers.values().cloned().collect();
+            (message, peers)
+        };
+        anyhow::ensure!(!peers.is_empty(), "Nobody else is in the call");
+
+        let payload = ChatPayload::Message {
+            id: message.id.clone(),
+            text: message.text.clone(),
+            sent_at: message.sent_at,
+        };
+        for peer in peers {
+            if let Err(e) = peer.send_chat(&payload).await {
+                tracing::warn!("Chat message not sent to {}: {e}", peer.peer_id);
+            }
+        }
+        Ok(message)
+    }
+
+    /// Offer a file to one participant. Returns the transfer ID; data only
+    /// flows once they accept.
+    pub async fn send_file(&self, peer_id: String, path: PathBuf) -> Result<String> {
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        anyhow::ensure!(metadata.is_file(), "{} is not a file", path.display());
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+        let hash_path = path.clone();
+        let sha256 =
+            tokio::task::spawn_blocking(move || file_transfer::sha256_file(&hash_path)).await??;
+
+        let (peer, transfers) = self.file_transfer_peer(&peer_id).await?;
+        let id = uuid::Uuid::new_v4().to_string();
+        transfers.lock().unwrap().outgoing.insert(
+            id.clone(),
+            OutgoingFile {
+                peer_id,
+                path,
+                size: metadata.len(),
+                task: None,
+            },
+        );
+        let offer = FilePayload::Offer {
+            id: id.clone(),
+            name,
+            size: metadata.len(),
+            sha256,
+        };
+        if let Err(e) = peer.send_file_control(&offer).await {
+            transfers.lock().unwrap().outgoing.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Accept a file a peer offered; it downloads to a temp file until saved
+    /// with `save_received_file`.
+    pub async fn accept_file_transfer(&self, id: String) -> Result<()> {
+        let transfers = self.file_transfers().await?;
+        let temp_path = {
+            let transfers = transfers.lock().unwrap();
+            let incoming = transfers
+                .incoming
+                .get(&id)
+                .context("Unknown file transfer")?;
+            anyhow::ensure!(
+                incoming.state == IncomingState::Offered,
+                "File transfer was already accepted"
+            );
+            incoming.temp_path().to_path_buf()
+        };
+        let create_path = temp_path.clone();
+        let file =
+            tokio::task::spawn_blocking(move || file_transfer::create_temp_file(&create_path))
+                .await??;
+        let peer_id = {
+            let mut transfers = transfers.lock().unwrap();
+            let incoming = transfers
+                .incoming
+                .get_mut(&id)
+                .filter(|t| t.state == IncomingState::Offered);
+            match incoming {
+                Some(incoming) => {
+                    incoming.start(file);
+                    Some(incoming.peer_id.clone())
+                }
+                None => None,
+            }
+        };
+        let Some(peer_id) = peer_id else {
+            // Cancelled while the temp file was being created
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            anyhow::bail!("File transfer was cancelled");
+        };
+        let (peer, _) = self.file_transfer_peer(&peer_id).await?;
+        peer.send_file_control(&FilePayload::Accept {
+            id: id.clone(),
+            offset: 0,
+        })
+        .await?;
+
+        // An empty file has no chunks to wait for
+        let complete = transfers
+            .lock()
+            .unwrap()
+            .incoming
+            .get(&id)
+            .is_some_and(IncomingFile::is_complete);
+        if complete {
+            finish_incoming_file(&peer, &self.app, &transfers, &id).await;
+        }
+        Ok(())
+    }
+
+    pub async fn decline_file_transfer(&self, id: String) -> Result<()> {
+        let transfers = self.file_transfers().await?;
+        let incoming = transfers
+            .lock()
+            .unwrap()
+            .incoming
+            .remove(&id)
+            .context("Unknown file transfer")?;
+        let (peer, _) = self.file_transfer_peer(&incoming.peer_id).await?;
+        peer.send_file_control(&FilePayload::Decline { id }).await
+    }
+
+    /// Stop a transfer in either direction and discard any partial data.
+    pub async fn cancel_file_transfer(&self, id: String) -> Result<()> {
+        let transfers = self.file_transfers().await?;
+        let peer_id = {
+            let mut transfers = transfers.lock().unwrap();
+            if let Some(incoming) = transfers.incoming.remove(&id) {
+                incoming.peer_id.clone()
+            } else {
+                let outgoing = transfers
+                    .outgoing
+                    .remove(&id)
+                    .context("Unknown file transfer")?;
+                outgoing.peer_id.clone()
+            }
+        };
+        emit_file_state(&self.app, &id, &peer_id, FileTransferState::Cancelled, None);
+        let (peer, _) = self.file_transfer_peer(&peer_id).await?;
+        peer.send_file_control(&FilePayload::Cancel { id }).await
+    }
+
+    /// Move a completed, checksum-verified download to `path`.
+    pub async fn save_received_file(&self, id: String, path: PathBuf) -> Result<()> {
+        let transfers = self.file_transfers().await?;
+        let temp_path = transfers
+            .lock()
+            .unwrap()
+            .incoming
+            .get(&id)
+            .context("Unknown file transfer")?
+            .verified_path()?
+            .to_path_buf();
+        tokio::task::spawn_blocking(move || file_transfer::move_file(&temp_path, &path)).await??;
+        transfers.lock().unwrap().incoming.remove(&id);
+        Ok(())
+    }
+
+    async fn file_transfers(&self) -> Result<Arc<std::sync::Mutex<FileTransfers>>> {
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a room")?;
+        Ok(Arc::clone(&inner.file_transfers))
+    }
+
+    async fn file_transfer_peer(
+        &self,
+        peer_id: &str,
+    ) -> Result<(Arc<PeerConn>, Arc<std::sync::Mutex<FileTransfers>>)> {
+        let guard = self.inner.lock().await;
+        let inner = guard.as_ref().context("Not in a room")?;
+        let peer = inner
+            .peers
+            .get(peer_id)
+            .cloned()
+            .with_context(|| format!("Unknown participant {peer_id}"))?;
+        Ok((peer, Arc::clone(&inner.file_transfers)))
+    }
+
+    /// Current media stats for every connected peer, with the bitrates the
+    /// stats loop last measured.
+    pub async fn call_stats(&self) -> Vec<PeerCallStats> {
+        collect_call_stats(&self.inner, false).await
+    }
+
+    /// Current jitter buffer state for every connected peer.
+    pub async fn jitter_stats(&self) -> Vec<PeerJitterStats> {
+        let guard = self.inner.lock().await;
+        let Some(inner) = guard.as_ref() else {
+            return Vec::new();
+        };
+        inner
+            .peers
+            .iter()
+            .map(|(peer_id, peer)| PeerJitterStats {
+                peer_id: peer_id.clone(),
+                stats: peer.jitter_stats(),
+            })
+            .collect()
+    }
+
     pub fn stop_mic_test(&self) {
         if let Some(test) = self.mic_test.lock().unwrap().take() {
             test.stop();
@@ -347,6 +1520,106 @@ impl Engine {
     }
 }
 
+impl EngineInner {
+    /// Stop sharing audio, if we are, and drop what it left queued.
+    fn stop_audio_share(&mut self) {
+        self.shared_audio = None;
+        self.processing.clear_shared_audio();
+    }
+
+    /// Swap in a capture opened on another device, carrying over the mute
+    /// and recording state. The old capture thread exits once it notices its
+    /// receiver is gone.
+    fn replace_capture(&mut self, capture: AudioCapture, device: Option<String>) {
+        capture.set_muted(self.muted);
+        capture.set_recording(self.recording.is_some());
+        self.capture = capture;
+        self.capture_device = device;
+    }
+
+    /// Point the encoder at the worst link: lowest bitrate and highest expected
+    /// loss over all peers. With nobody reporting yet, use the upper bound.
+    fn update_encoder_target(&self) {
+        let bitrate = self
+            .bitrate_controllers
+            .values()
+            .map(BitrateController::bitrate)
+            .min()
+            .unwrap_or(self.bitrate_bounds.max);
+        let loss_perc = self
+            .bitrate_controllers
+            .values()
+            .map(BitrateController::expected_loss_perc)
+            .max()
+            .unwrap_or(DEFAULT_EXPECTED_LOSS_PERC);
+        let bitrate = if self.processing.music_mode.load(Ordering::Relaxed) {
+            let stereo = self.processing.stereo.load(Ordering::Relaxed);
+            music::music_bitrate(bitrate, self.bitrate_bounds, stereo)
+        } else {
+            bitrate
+        };
+        self.processing.bitrate.store(bitrate, Ordering::Relaxed);
+        self.processing
+            .expected_loss_perc
+            .store(loss_perc, Ordering::Relaxed);
+    }
+
+    /// Music mode is sent in stereo only while every peer's SDP accepted
+    /// stereo, and mono otherwise. Returns the new state if that changed.
+    fn update_stereo(&self) -> Option<MusicModeEvent> {
+        let stereo = self.processing.music_mode.load(Ordering::Relaxed)
+            && !self.peers.is_empty()
+            && self.peers.values().all(|peer| peer.accepts_stereo());
+        let changed = self.processing.stereo.swap(stereo, Ordering::Relaxed) != stereo;
+        self.update_encoder_target();
+        changed.then(|| self.processing.music_mode_state())
+    }
+
+    /// Whether peers should see us as muted: muted by the user, or in
+    /// push-to-talk mode with the key released.
+    fn transmit_muted(&self) -> bool {
+        self.muted
+            || (self.processing.push_to_talk.load(Ordering::Relaxed)
+                && !self.processing.ptt_pressed.load(Ordering::Relaxed))
+    }
+
+    /// Broadcast our effective mute state to all peers via signaling.
+    fn broadcast_mute_state(&self) {
+        let muted = self.transmit_muted();
+        let _ = queue_signal(&self.signal_tx, SignalMessage::MuteState { muted });
+    }
+
+    fn broadcast_recording_state(&self) {
+        let recording = self.recording.is_some();
+        let _ = queue_signal(&self.signal_tx, SignalMessage::RecordingState { recording });
+    }
+
+    /// Local playback gain for a remote peer, looked up by display name.
+    fn peer_volume(&self, peer_id: &str) -> f32 {
+        self.peer_names
+            .get(peer_id)
+            .and_then(|name| self.peer_volumes.lock().ok()?.get(name).copied())
+            .unwrap_or(1.0)
+    }
+
+    /// Record a peer's display name and re-apply any volume set for that name
+    /// earlier in the session.
+    fn remember_peer_name(&mut self, app: &AppHandle, peer_id: &str, name: &str) {
+        self.peer_names
+            .insert(peer_id.to_string(), name.to_string());
+        let gain = self.peer_volume(peer_id);
+        if gain != 1.0 {
+            let _ = app.emit(
+                EVENT_PEER_VOLUME_CHANGED,
+                PeerVolumeEvent {
+                    peer_id: peer_id.to_string(),
+                    gain,
+                },
+            );
+        }
+    }
+}
+
 /// Main engine event loop. Runs as a tokio task.
 async fn engine_loop(
     engine: Arc<Mutex<Option<EngineInner>>>,
@@ -363,14 +1636,26 @@ async fn engine_loop(
     voice_activity_interval.tick().await;
 
     let mut audio_peers = Vec::new();
-    let mut mixed = [0.0f32; FRAME_SIZE];
+    let mut mixed: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+    let mut peer_frame: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+    // Mono mix for the echo canceller's reference and the recording
+    let mut mixed_mono: PcmFrame = [0.0; FRAME_SIZE];
     let mut speaking_peers = Vec::new();
 
     loop {
+        let mut mix_tick = false;
         let mut emit_voice_activity = false;
 
         // Get channels from inner (if still active)
-        let (encoded_rx, ice_rx, conn_state_rx, rtt_rx, signaling_status_rx) = {
+        let (
+            encoded_rx,
+            ice_rx,
+            conn_state_rx,
+            link_report_rx,
+            chat_rx,
+            rtt_rx,
+            signaling_status_rx,
+        ) = {
             let guard = engine.lock().await;
             let Some(inner) = guard.as_ref() else {
                 break; // Engine shut down
@@ -379,14 +1664,18 @@ async fn engine_loop(
                 inner.capture.encoded_rx.clone(),
                 inner.ice_rx.clone(),
                 inner.conn_state_rx.clone(),
+                inner.link_report_rx.clone(),
+                inner.chat_rx.clone(),
                 inner.rtt_rx.clone(),
                 inner.signaling_status_rx.clone(),
             )
         };
 
         tokio::select! {
-            // ── Mixer tick keeps remote playback draining even when local capture is muted ──
-            _ = mix_interval.tick() => {}
+            // ── Mixer tick pulls one frame per peer from the jitter buffers ──
+            _ = mix_interval.tick() => {
+                mix_tick = true;
+            }
 
             // ── Voice activity tick ──
             _ = voice_activity_interval.tick() => {
@@ -408,8 +1697,10 @@ async fn engine_loop(
             }
 
             // ── Encoded audio from microphone ──
-            frame = encoded_rx.recv_async() => {
-                let Ok(frame) = frame else { break };
+            // A capture whose device failed stops sending; the call carries
+            // on until the device watcher opens a new one
+            frame = encoded_rx.recv_async(), if !encoded_rx.is_disconnected() => {
+                let Ok(frame) = frame else { continue };
                 audio_peers.clear();
                 {
                     let guard = engine.lock().await;
@@ -443,7 +1734,20 @@ async fn engine_loop(
             conn_state = conn_state_rx.recv_async() => {
                 if let Ok((peer_id, state)) = conn_state {
                     use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
-                    if state == RTCPeerConnectionState::Failed {
+                    if state == RTCPeerConnectionState::Connected {
+                        // DTLS is up: publish the string to compare out loud
+                        let peer = {
+                            let guard = engine.lock().await;
+                            guard
+                                .as_ref()
+                                .and_then(|inner| inner.peers.get(&peer_id).cloned())
+                        };
+                        if let Some(peer) = peer {
+                            if let Some(symbols) = peer.short_auth_string().await {
+                                let _ = app.emit(EVENT_PEER_SAS, &PeerSasEvent { peer_id, symbols });
+                            }
+                        }
+                    } else if state == RTCPeerConnectionState::Failed {
                         tracing::warn!("Peer {peer_id} connection failed, attempting ICE restart");
                         let peer = {
                             let guard = engine.lock().await;
@@ -472,6 +1776,31 @@ async fn engine_loop(
                 }
             }
 
+            // ── RTCP reception report about our audio at one peer ──
+            report = link_report_rx.recv_async() => {
+                if let Ok((peer_id, report)) = report {
+                    let mut guard = engine.lock().await;
+                    if let Some(inner) = guard.as_mut() {
+                        if inner.peers.contains_key(&peer_id) {
+                            let bounds = inner.bitrate_bounds;
+                            inner
+                                .bitrate_controllers
+                                .entry(peer_id)
+                                .or_insert_with(|| BitrateController::new(bounds))
+                                .on_report(report, bounds);
+                            inner.update_encoder_target();
+                        }
+                    }
+                }
+            }
+
+            // ── Chat message or receipt from a peer's data channel ──
+            chat = chat_rx.recv_async() => {
+                if let Ok((peer_id, payload)) = chat {
+                    handle_chat_payload(&engine, &app, peer_id, payload).await;
+                }
+            }
+
             // ── Signaling connection status ──
             status = signaling_status_rx.recv_async() => {
                 if let Ok(status) = status {
@@ -486,7 +1815,7 @@ async fn engine_loop(
                                         room_id: inner.room_id.clone(),
                                         peer_id: inner.peer_id.clone(),
                                         name: inner.peer_name.clone(),
-                                        password: None,
+                                        password: inner.password.clone(),
                                         create: false,
                                     },
                                 );
@@ -534,38 +1863,80 @@ async fn engine_loop(
         }
 
         // ── Mix decoded audio from all remote peers into playback ──
-        {
-            let guard = engine.lock().await;
-            if let Some(inner) = guard.as_ref() {
-                mixed.fill(0.0);
-                let mut has_audio = false;
-                speaking_peers.clear();
-
-                for (peer_id, peer) in &inner.peers {
-                    let mut peer_peak: f32 = 0.0;
-                    let mut peer_has_audio = false;
+        if mix_tick || emit_voice_activity {
+            let mut guard = engine.lock().await;
+            if let Some(inner) = guard.as_mut() {
+                if mix_tick {
+                    mixed.fill(0.0);
+                    let mut has_audio = false;
+                    let record_tracks = inner
+                        .recording
+                        .as_ref()
+                        .is_some_and(|recording| recording.per_participant());
+                    let mut recorded_tracks = Vec::new();
+
+                    for (peer_id, peer) in &inner.peers {
+                        let pulled = peer.pull_audio(&mut peer_frame);
+
+                        // The peer's VAD runs on the unscaled signal, so a
+                        // locally muted participant still lights up.
+                        if peer.is_speaking() && !speaking_peers.contains(peer_id) {
+                            speaking_peers.push(peer_id.clone());
+                        }
 
-                    while let Ok(frame) = peer.decoded_rx.try_recv() {
+                        let Some(len) = pulled else {
+                            continue;
+                        };
                         has_audio = true;
-                        peer_has_audio = true;
-                        let len = frame.len.min(frame.samples.len());
-                        for (i, sample) in frame.samples[..len].iter().enumerate() {
-                            peer_peak = peer_peak.max(sample.abs());
-                            mixed[i] += *sample;
+
+                        // Tracks are recorded at the level the peer sent them
+                        let samples = &peer_frame[..len * CHANNELS as usize];
+                        if record_tracks {
+                            let mut frame = [0.0; FRAME_SIZE];
+                            downmix_stereo(samples, &mut frame[..len]);
+                            recorded_tracks.push(RecordedTrack {
+                                peer_id: peer_id.clone(),
+                                name: inner.peer_names.get(peer_id).cloned().unwrap_or_default(),
+                                frame,
+                            });
+                        }
+
+                        let gain = inner.peer_volume(peer_id);
+                        for (out, sample) in mixed.iter_mut().zip(samples) {
+                            *out += *sample * gain;
                         }
                     }
 
-                    if peer_has_audio && peer_peak > 0.01 {
-                        speaking_peers.push(peer_id.clone());
+                    // Record the call before local sound cues are mixed in
+                    downmix_stereo(&mixed, &mut mixed_mono);
+                    if let Some(recording) = inner.recording.as_ref() {
+                        recording.push(RecordingFrame {
+                            local: inner.capture.take_local_frame(),
+                            remote: mixed_mono,
+                            tracks: recorded_tracks,
+                        });
+                    }
+
+                    if let Some(cue) = inner.cue.as_mut() {
+                        has_audio = true;
+                        if !cue.mix_into(&mut mixed) {
+                            inner.cue = None;
+                        }
                     }
-                }
 
-                if has_audio {
-                    // Clamp to [-1.0, 1.0]
-                    for s in &mut mixed {
-                        *s = s.clamp(-1.0, 1.0);
+                    if has_audio {
+                        // Clamp to [-1.0, 1.0]
+                        for s in &mut mixed {
+                            *s = s.clamp(-1.0, 1.0);
+                        }
+                        inner.playback.write(mixed);
+                    }
+                    // The echo canceller needs the far-end signal every tick, silence included.
+                    downmix_stereo(&mixed, &mut mixed_mono);
+                    inner.capture.push_far_end(&mixed_mono);
+                    if let Some(shared) = &inner.shared_audio {
+                        shared.push_far_end(&mixed_mono);
                     }
-                    inner.playback.write(mixed);
                 }
 
                 if emit_voice_activity {
@@ -573,7 +1944,7 @@ async fn engine_loop(
                     let _ = app.emit(
                         EVENT_VOICE_ACTIVITY,
                         VoiceActivityEvent {
-                            speaking: speaking_peers.clone(),
+                            speaking: std::mem::take(&mut speaking_peers),
                             self_speaking,
                         },
                     );
@@ -585,6 +1956,18 @@ async fn engine_loop(
     Ok(())
 }
 
+/// Re-check stereo music mode once a peer's SDP has arrived.
+async fn update_music_stereo(engine: &Arc<Mutex<Option<EngineInner>>>, app: &AppHandle) {
+    let music_mode = engine
+        .lock()
+        .await
+        .as_ref()
+        .and_then(EngineInner::update_stereo);
+    if let Some(music_mode) = music_mode {
+        let _ = app.emit(EVENT_MUSIC_MODE, music_mode);
+    }
+}
+
 async fn handle_signal_message(
     engine: &Arc<Mutex<Option<EngineInner>>>,
     signal_tx: &flume::Sender<SignalMessage>,
@@ -608,6 +1991,13 @@ async fn handle_signal_message(
                     inner.is_host = is_host;
                     inner.room_locked = locked;
                     inner.turn_servers = turn_servers;
+                    // Mute and recording state aren't replayed by the server, so announce them on (re)join.
+                    if inner.transmit_muted() {
+                        inner.broadcast_mute_state();
+                    }
+                    if inner.recording.is_some() {
+                        inner.broadcast_recording_state();
+                    }
                 }
             }
 
@@ -615,6 +2005,14 @@ async fn handle_signal_message(
             for peer_info in &peers {
                 let _ = app.emit(EVENT_PEER_JOINED, peer_info);
             }
+            {
+                let mut guard = engine.lock().await;
+                if let Some(inner) = guard.as_mut() {
+                    for peer_info in &peers {
+                        inner.remember_peer_name(app, &peer_info.peer_id, &peer_info.name);
+                    }
+                }
+            }
 
             // We are the new joiner - send offers to all existing peers
             for peer_info in &peers {
@@ -655,13 +2053,20 @@ async fn handle_signal_message(
                 EVENT_PEER_JOINED,
                 PeerInfo {
                     peer_id: peer_id.clone(),
-                    name,
+                    name: name.clone(),
                     is_host,
                 },
             );
 
-            let guard = engine.lock().await;
-            if let Some(inner) = guard.as_ref() {
+            let mut guard = engine.lock().await;
+            if let Some(inner) = guard.as_mut() {
+                inner.remember_peer_name(app, &peer_id, &name);
+                if inner.transmit_muted() {
+                    inner.broadcast_mute_state();
+                }
+                if inner.recording.is_some() {
+                    inner.broadcast_recording_state();
+                }
                 let _ = app.emit(
                     EVENT_STATE_CHANGED,
                     CallState::InRoom {
@@ -682,6 +2087,12 @@ async fn handle_signal_message(
                 let mut guard = engine.lock().await;
                 if let Some(inner) = guard.as_mut() {
                     let peer = inner.peers.remove(&peer_id);
+                    inner.peer_names.remove(&peer_id);
+                    inner.file_transfers.lock().unwrap().remove_peer(&peer_id);
+                    inner.bitrate_controllers.remove(&peer_id);
+                    if let Some(music_mode) = inner.update_stereo() {
+                        let _ = app.emit(EVENT_MUSIC_MODE, music_mode);
+                    }
                     let state = Some(CallState::InRoom {
                         room_id: inner.room_id.clone(),
                         room_name: inner.room_name.clone(),
@@ -705,23 +2116,70 @@ async fn handle_signal_message(
 
         SignalMessage::RoomNotFound => {
             tracing::info!("Room does not exist");
+            end_call(engine, app, "Room not found").await;
+        }
 
-            let inner = {
-                let mut guard = engine.lock().await;
-                guard.take()
-            };
-            if let Some(inner) = inner {
-                for (_, peer) in inner.peers {
-                    peer.close().await;
-                }
+        SignalMessage::WrongPassword => {
+            tracing::info!("Room password rejected");
+            end_call(engine, app, "Wrong password").await;
+        }
+
+        SignalMessage::RoomLocked => {
+            tracing::info!("Room is locked");
+            end_call(engine, app, "This room is locked").await;
+        }
+
+        SignalMessage::Kicked => {
+            tracing::info!("Removed from the room by the host");
+            let _ = app.emit(EVENT_KICKED, ());
+            end_call(engine, app, "You were removed from the room by the host").await;
+        }
+
+        SignalMessage::RoomLockChanged { locked } => {
+            tracing::info!("Room lock changed: {locked}");
+            let mut guard = engine.lock().await;
+            if let Some(inner) = guard.as_mut() {
+                inner.room_locked = locked;
+                let _ = app.emit(
+                    EVENT_STATE_CHANGED,
+                    CallState::InRoom {
+                        room_id: inner.room_id.clone(),
+                        room_name: inner.room_name.clone(),
+                        is_host: inner.is_host,
+                        locked: inner.room_locked,
+                    },
+                );
             }
+        }
 
-            let _ = app.emit(
-                EVENT_STATE_CHANGED,
-                CallState::Error {
-                    message: "Room not found".to_string(),
-                },
-            );
+        SignalMessage::MuteRequested { by } => {
+            tracing::info!("Host {by} asked us to mute");
+            let mut guard = engine.lock().await;
+            if let Some(inner) = guard.as_mut() {
+                inner.muted = true;
+                inner.capture.set_muted(true);
+                inner.broadcast_mute_state();
+                let _ = app.emit(EVENT_MUTE_REQUESTED, &by);
+            }
+        }
+
+        SignalMessage::HostChanged { peer_id } => {
+            tracing::info!("Peer {peer_id} is now the host");
+            let _ = app.emit(EVENT_HOST_CHANGED, &peer_id);
+
+            let mut guard = engine.lock().await;
+            if let Some(inner) = guard.as_mut() {
+                inner.is_host = peer_id == inner.peer_id;
+                let _ = app.emit(
+                    EVENT_STATE_CHANGED,
+                    CallState::InRoom {
+                        room_id: inner.room_id.clone(),
+                        room_name: inner.room_name.clone(),
+                        is_host: inner.is_host,
+                        locked: inner.room_locked,
+                    },
+                );
+            }
         }
 
         SignalMessage::PeerMuteState { peer_id, muted } => {
@@ -729,12 +2187,20 @@ async fn handle_signal_message(
             let _ = app.emit(EVENT_PEER_MUTE_CHANGED, PeerMuteEvent { peer_id, muted });
         }
 
+        SignalMessage::PeerRecordingState { peer_id, recording } => {
+            tracing::info!("Peer {peer_id} recording: {recording}");
+            let _ = app.emit(
+                EVENT_PEER_RECORDING,
+                PeerRecordingEvent { peer_id, recording },
+            );
+        }
+
         SignalMessage::Signal {
             from: Some(from),
             payload,
             ..
         } => {
-            handle_signal_payload(engine, signal_tx, &from, payload).await?;
+            handle_signal_payload(engine, signal_tx, app, &from, payload).await?;
         }
 
         // Client-originating messages or unrecognized
@@ -744,9 +2210,110 @@ async fn handle_signal_message(
     Ok(())
 }
 
+async fn handle_chat_payload(
+    engine: &Arc<Mutex<Option<EngineInner>>>,
+    app: &AppHandle,
+    peer_id: String,
+    payload: ChatPayload,
+) {
+    match payload {
+        ChatPayload::Message { id, text, sent_at } => {
+            let (peer, name) = {
+                let guard = engine.lock().await;
+                let Some(inner) = guard.as_ref() else {
+                    return;
+                };
+                (
+                    inner.peers.get(&peer_id).cloned(),
+                    inner.peer_names.get(&peer_id).cloned().unwrap_or_default(),
+                )
+            };
+            let Some(peer) = peer else {
+                return;
+            };
+
+            let ack = ChatPayload::Ack { id: id.clone() };
+            if let Err(e) = peer.send_chat(&ack).await {
+                tracing::warn!("Failed to acknowledge chat message from {peer_id}: {e}");
+            }
+            let _ = app.emit(
+                EVENT_CHAT_MESSAGE,
+                ChatMessageEvent {
+                    id,
+                    peer_id,
+                    name,
+                    text,
+                    sent_at,
+                },
+            );
+        }
+        ChatPayload::Ack { id } => {
+            let _ = app.emit(EVENT_CHAT_DELIVERED, ChatDeliveredEvent { id, peer_id });
+        }
+    }
+}
+
+/// Finalize a recording and tell the UI where its files are. Finalizing
+/// waits for the recording thread to drain its queue, so it runs off the
+/// async runtime.
+async fn finish_recording(recording: Recording, app: &AppHandle) -> Result<Vec<PathBuf>> {
+    let dir = recording.dir.display().to_string();
+    let files = tokio::task::spawn_blocking(move || recording.stop()).await?;
+    tracing::info!("Recording saved to {dir} ({} files)", files.len());
+    let _ = app.emit(
+        EVENT_RECORDING_STATE,
+        RecordingStateEvent {
+            recording: false,
+            dir: Some(dir),
+            files: files.iter().map(|f| f.display().to_string()).collect(),
+        },
+    );
+    Ok(files)
+}
+
+/// Stop a recording that was never used, removing its folder if given.
+async fn discard_recording(recording: Recording, dir: Option<PathBuf>) {
+    let removed = tokio::task::spawn_blocking(move || {
+        recording.stop();
+        dir.map_or(Ok(()), std::fs::remove_dir_all)
+    })
+    .await;
+    if let Ok(Err(e)) = removed {
+        tracing::warn!("Failed to remove unused recording: {e}");
+    }
+}
+
+/// Tear down the call after the server refused or ended our membership.
+async fn end_call(engine: &Arc<Mutex<Option<EngineInner>>>, app: &AppHandle, message: &str) {
+    let inner = {
+        let mut guard = engine.lock().await;
+        guard.take()
+    };
+    if let Some(mut inner) = inner {
+        if let Some(recording) = inner.recording.take() {
+            if let Err(e) = finish_recording(recording, app).await {
+                tracing::error!("Failed to finish recording: {e}");
+            }
+        }
+        inner.processing.clip.lock().unwrap().take();
+        inner.stop_audio_share();
+        for (_, peer) in inner.peers {
+            peer.close().await;
+        }
+    }
+
+    let _ = app.emit(
+        EVENT_STATE_CHANGED,
+        CallState::Error {
+            message: message.to_string(),
+        },
+    );
+}
+
 async fn handle_signal_payload(
     engine: &Arc<Mutex<Option<EngineInner>>>,
     signal_tx: &flume::Sender<SignalMessage>,
+    app: &AppHandle,
     from: &str,
     payload: SignalPayload,
 ) -> Result<()> {
@@ -768,6 +2335,7 @@ async fn handle_signal_payload(
             };
 
             let answer_sdp = peer.handle_offer(&sdp).await?;
+            update_music_stereo(engine, app).await;
 
             queue_signal(
                 signal_tx,
@@ -788,6 +2356,7 @@ async fn handle_signal_payload(
             };
             if let Some(peer) = peer {
                 peer.handle_answer(&sdp).await?;
+                update_music_stereo(engine, app).await;
             }
         }
 
@@ -811,24 +2380,444 @@ async fn handle_signal_payload(
     Ok(())
 }
 
+async fn collect_call_stats(
+    engine: &Arc<Mutex<Option<EngineInner>>>,
+    advance_bitrate: bool,
+) -> Vec<PeerCallStats> {
+    // Snapshot the peers so stats collection doesn't hold the engine lock.
+    let peers: Vec<Arc<PeerConn>> = {
+        let guard = engine.lock().await;
+        match guard.as_ref() {
+            Some(inner) => inner.peers.values().cloned().collect(),
+            None => return Vec::new(),
+        }
+    };
+
+    let mut stats = Vec::with_capacity(peers.len());
+    for peer in peers {
+        stats.push(peer.call_stats(advance_bitrate).await);
+    }
+    stats
+}
+
+/// Emit `call-stats` periodically until the call identified by `peer_id` ends,
+/// and `e2ee-state` whenever a peer's key check changes.
+async fn call_stats_loop(engine: Arc<Mutex<Option<EngineInner>>>, app: AppHandle, peer_id: String) {
+    let mut interval = tokio::time::interval(CALL_STATS_INTERVAL);
+    interval.tick().await;
+    let mut e2ee_states: HashMap<String, E2eeState> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+        let same_call = engine
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|inner| inner.peer_id == peer_id);
+        if !same_call {
+            break;
+        }
+        let stats = collect_call_stats(&engine, true).await;
+        let _ = app.emit(EVENT_CALL_STATS, &stats);
+
+        e2ee_states.retain(|id, _| stats.iter().any(|p| &p.peer_id == id));
+        for peer in &stats {
+            if e2ee_states.insert(peer.peer_id.clone(), peer.e2ee) != Some(peer.e2ee) {
+                let _ = app.emit(
+                    EVENT_E2EE_STATE,
+                    &E2eeStateEvent {
+                        peer_id: peer.peer_id.clone(),
+                        state: peer.e2ee,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Handle file channel traffic for the current call. Ends when every sender
+/// (the engine and its peer connections) is gone.
+async fn file_transfer_loop(
+    engine: Arc<Mutex<Option<EngineInner>>>,
+    app: AppHandle,
+    file_rx: flume::Receiver<(String, FileChannelEvent)>,
+    transfers: Arc<std::sync::Mutex<FileTransfers>>,
+) {
+    let mut last_progress: HashMap<String, Instant> = HashMap::new();
+
+    while let Ok((peer_id, event)) = file_rx.recv_async().await {
+        let peer = {
+            let guard = engine.lock().await;
+            guard
+                .as_ref()
+                .and_then(|inner| inner.peers.get(&peer_id).cloned())
+        };
+        let Some(peer) = peer else {
+            continue;
+        };
+
+        match event {
+            FileChannelEvent::Resume => {
+                // A new or restored connection to this peer: resume our
+                // unfinished downloads from the bytes we hold
+                let resume = transfers.lock().unwrap().resumable(&peer_id);
+                for (id, offset) in resume {
+                    tracing::info!("Resuming file transfer {id} from {peer_id} at byte {offset}");
+                    let _ = peer
+                        .send_file_control(&FilePayload::Accept { id, offset })
+                        .await;
+                }
+            }
+
+            FileChannelEvent::Control(payload) => {
+                handle_file_control(&peer, &app, &transfers, payload).await;
+            }
+
+            FileChannelEvent::Chunk(chunk) => {
+                let (id, offset, data) = match file_transfer::decode_chunk(&chunk) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        tracing::warn!("Dropping file chunk from {peer_id}: {e}");
+                        continue;
+                    }
+                };
+                let target = {
+                    let transfers = transfers.lock().unwrap();
+                    let Some(incoming) = transfers.incoming.get(&id) else {
+                        continue;
+                    };
+                    if incoming.peer_id != peer_id {
+                        continue;
+                    }
+                    incoming.chunk_target(offset, data.len())
+                };
+                // The write runs off the runtime and without the lock; chunks
+                // are handled one at a time, so the offset still holds after
+                let appended = match target {
+                    Ok(Some(file)) => {
+                        let chunk = chunk.slice_ref(data);
+                        let append = tokio::task::spawn_blocking(move || {
+                            file_transfer::append_chunk(&file, &chunk).map(|()| Some(chunk))
+                        });
+                        match append.await {
+                            Ok(appended) => appended,
+                            Err(e) => Err(e.into()),
+                        }
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                let written = {
+                    let mut transfers = transfers.lock().unwrap();
+                    let Some(incoming) = transfers.incoming.get_mut(&id) else {
+                        continue;
+                    };
+                    match appended {
+                        Ok(chunk) => {
+                            if let Some(chunk) = chunk {
+                                incoming.chunk_written(&chunk);
+                            }
+                            Ok((incoming.received, incoming.size, incoming.is_complete()))
+                        }
+                        Err(e) => {
+                            transfers.incoming.remove(&id);
+                            Err(e)
+                        }
+                    }
+                };
+                match written {
+                    Ok((received, size, complete)) => {
+                        let due = last_progress
+                            .get(&id)
+                            .is_none_or(|at| at.elapsed() >= FILE_PROGRESS_INTERVAL);
+                        if due || complete {
+                            last_progress.insert(id.clone(), Instant::now());
+                            let _ = app.emit(
+                                EVENT_FILE_PROGRESS,
+                                FileProgressEvent {
+                                    id: id.clone(),
+                                    peer_id: peer_id.clone(),
+                                    transferred: received,
+                                    size,
+                                },
+                            );
+                        }
+                        if complete {
+                            last_progress.remove(&id);
+                            finish_incoming_file(&peer, &app, &transfers, &id).await;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("File transfer {id} from {peer_id} failed: {e}");
+                        let _ = peer
+                            .send_file_control(&FilePayload::Cancel { id: id.clone() })
+                            .await;
+                        emit_file_state(
+                            &app,
+                            &id,
+                            &peer_id,
+                            FileTransferState::Failed,
+                            Some(e.to_string()),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_file_control(
+    peer: &Arc<PeerConn>,
+    app: &AppHandle,
+    transfers: &Arc<std::sync::Mutex<FileTransfers>>,
+    payload: FilePayload,
+) {
+    let peer_id = peer.peer_id.as_str();
+    match payload {
+        FilePayload::Offer {
+            id,
+            name,
+            size,
+            sha256,
+        } => {
+            // The ID names our temp file, so only accept a well-formed UUID
+            let Ok(id) = uuid::Uuid::parse_str(&id).map(|id| id.to_string()) else {
+                tracing::warn!("Ignoring file offer with invalid ID from {peer_id}");
+                return;
+            };
+            let temp_path = std::env::temp_dir().join(format!("entavi-{id}.part"));
+            let incoming = IncomingFile::new(peer_id.to_string(), &name, size, sha256, temp_path);
+            let offer = FileOfferEvent {
+                id: id.clone(),
+                peer_id: peer_id.to_string(),
+                name: incoming.name.clone(),
+                size,
+            };
+            transfers.lock().unwrap().incoming.insert(id, incoming);
+            let _ = app.emit(EVENT_FILE_OFFER, offer);
+        }
+
+        FilePayload::Accept { id, offset } => {
+            let mut transfers = transfers.lock().unwrap();
+            let Some(outgoing) = transfers.outgoing.get_mut(&id) else {
+                return;
+            };
+            if outgoing.peer_id != peer_id {
+                return;
+            }
+            if let Some(previous) = outgoing.task.take() {
+                previous.abort();
+            }
+            if offset == 0 {
+                emit_file_state(app, &id, peer_id, FileTransferState::Accepted, None);
+            } else {
+                tracing::info!("Peer {peer_id} resumed file transfer {id} at byte {offset}");
+            }
+            let send = send_file_data(
+                Arc::clone(peer),
+                app.clone(),
+                id.clone(),
+                outgoing.path.clone(),
+                offset,
+                outgoing.size,
+            );
+            outgoing.task = Some(tokio::spawn(async move {
+                if let Err(e) = send.await {
+                    // The receiver asks to resume once it reconnects
+                    tracing::warn!("File transfer {id} paused: {e}");
+                }
+            }));
+        }
+
+        FilePayload::Decline { id } => {
+            let removed = remove_outgoing(transfers, &id, peer_id);
+            if removed {
+                emit_file_state(app, &id, peer_id, FileTransferState::Declined, None);
+            }
+        }
+
+        FilePayload::Cancel { id } => {
+            let removed = {
+                let mut transfers = transfers.lock().unwrap();
+                let incoming = transfers
+                    .incoming
+                    .get(&id)
+                    .is_some_and(|t| t.peer_id == peer_id);
+                if incoming {
+                    transfers.incoming.remove(&id);
+                }
+                incoming
+            } || remove_outgoing(transfers, &id, peer_id);
+            if removed {
+                emit_file_state(app, &id, peer_id, FileTransferState::Cancelled, None);
+            }
+        }
+
+        FilePayload::Received { id, ok } => {
+            if remove_outgoing(transfers, &id, peer_id) {
+                if ok {
+                    emit_file_state(app, &id, peer_id, FileTransferState::Completed, None);
+                } else {
+                    emit_file_state(
+                        app,
+                        &id,
+                        peer_id,
+                        FileTransferState::Failed,
+                        Some("Checksum mismatch at the receiver".to_string()),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn remove_outgoing(
+    transfers: &Arc<std::sync::Mutex<FileTransfers>>,
+    id: &str,
+    peer_id: &str,
+) -> bool {
+    let mut transfers = transfers.lock().unwrap();
+    let matches = transfers
+        .outgoing
+        .get(id)
+        .is_some_and(|t| t.peer_id == peer_id);
+    if matches {
+        transfers.outgoing.remove(id);
+    }
+    matches
+}
+
+/// Verify a fully received file and tell the sender how it went.
+async fn finish_incoming_file(
+    peer: &PeerConn,
+    app: &AppHandle,
+    transfers: &Arc<std::sync::Mutex<FileTransfers>>,
+    id: &str,
+) {
+    let checked = {
+        let mut transfers = transfers.lock().unwrap();
+        let Some(incoming) = transfers.incoming.get_mut(id) else {
+            return;
+        };
+        incoming.verify()
+    };
+    let verified = match checked {
+        Ok((file, ok)) => match tokio::task::spawn_blocking(move || file.sync_all()).await {
+            Ok(Ok(())) => Ok(ok),
+            Ok(Err(e)) => Err(e.into()),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e),
+    };
+    if !matches!(verified, Ok(true)) {
+        transfers.lock().unwrap().incoming.remove(id);
+    }
+
+    let ok = matches!(verified, Ok(true));
+    let _ = peer
+        .send_file_control(&FilePayload::Received {
+            id: id.to_string(),
+            ok,
+        })
+        .await;
+    match verified {
+        Ok(true) => emit_file_state(app, id, &peer.peer_id, FileTransferState::Completed, None),
+        Ok(false) => emit_file_state(
+            app,
+            id,
+            &peer.peer_id,
+            FileTransferState::Failed,
+            Some("Checksum mismatch".to_string()),
+        ),
+        Err(e) => emit_file_state(
+            app,
+            id,
+            &peer.peer_id,
+            FileTransferState::Failed,
+            Some(e.to_string()),
+        ),
+    }
+}
+
+/// Stream a file to a peer from `offset`, with progress events.
+async fn send_file_data(
+    peer: Arc<PeerConn>,
+    app: AppHandle,
+    id: String,
+    path: PathBuf,
+    offset: u64,
+    size: u64,
+) -> Result<()> {
+    let uuid = uuid::Uuid::parse_str(&id)?;
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+    let mut sent = offset;
+    let mut last_progress = Instant::now();
+    while sent < size {
+        let len = FILE_CHUNK_SIZE.min((size - sent) as usize);
+        file.read_exact(&mut buf[..len]).await?;
+        peer.send_file_chunk(&file_transfer::encode_chunk(&uuid, sent, &buf[..len]))
+            .await?;
+        sent += len as u64;
+
+        if sent == size || last_progress.elapsed() >= FILE_PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let _ = app.emit(
+                EVENT_FILE_PROGRESS,
+                FileProgressEvent {
+                    id: id.clone(),
+                    peer_id: peer.peer_id.clone(),
+                    transferred: sent,
+                    size,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn emit_file_state(
+    app: &AppHandle,
+    id: &str,
+    peer_id: &str,
+    state: FileTransferState,
+    error: Option<String>,
+) {
+    let _ = app.emit(
+        EVENT_FILE_TRANSFER_STATE,
+        FileTransferStateEvent {
+            id: id.to_string(),
+            peer_id: peer_id.to_string(),
+            state,
+            error,
+        },
+    );
+}
+
 /// Create a new PeerConn, store it in the engine, and return a reference.
 async fn create_peer_conn(
     engine: &Arc<Mutex<Option<EngineInner>>>,
     remote_peer_id: String,
 ) -> Result<Arc<PeerConn>> {
-    let (ice_tx, conn_state_tx, turn_servers) = {
+    let (events, turn_servers, cipher) = {
         let guard = engine.lock().await;
         let inner = guard.as_ref().context("Engine not active")?;
-        (
-            inner.ice_tx.clone(),
-            inner.conn_state_tx.clone(),
-            inner.turn_servers.clone(),
-        )
+        let events = PeerEvents {
+            ice_candidate: inner.ice_tx.clone(),
+            conn_state: inner.conn_state_tx.clone(),
+            link_report: inner.link_report_tx.clone(),
+            chat: inner.chat_tx.clone(),
+            file: inner.file_tx.clone(),
+        };
+        (events, inner.turn_servers.clone(), inner.cipher.clone())
     };
 
-    let peer = Arc::new(
-        PeerConn::new(remote_peer_id.clone(), ice_tx, &turn_servers, conn_state_tx).await?,
-    );
+    let peer =
+        Arc::new(PeerConn::new(remote_peer_id.clone(), &turn_servers, events, cipher).await?);
 
     {
         let mut guard = engine.lock().await;
@@ -861,4 +2850,38 @@ mod tests {
 
         assert!(err.to_string().contains("closed"));
     }
+
+    #[test]
+    fn push_to_talk_shortcuts_are_checked_before_binding() {
+        assert!(parse_ptt_shortcut(None).is_ok());
+        assert!(parse_ptt_shortcut(Some("Alt+F9")).is_ok());
+        let err = parse_ptt_shortcut(Some("Ctrl+Shift+NotAKey")).unwrap_err();
+        assert!(err.to_string().contains("Invalid shortcut"));
+        assert!(parse_ptt_shortcut(Some("")).is_err());
+    }
+
+    #[test]
+    fn moderation_messages_match_server_wire_format() {
+        let kick = serde_json::to_value(SignalMessage::KickPeer {
+            peer_id: "p1".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            kick,
+            serde_json::json!({"type": "kick_peer", "peer_id": "p1"})
+        );
+
+        let lock = serde_json::to_value(SignalMessage::LockRoom { locked: true }).unwrap();
+        assert_eq!(
+            lock,
+            serde_json::json!({"type": "lock_room", "locked": true})
+        );
+
+        let kicked: SignalMessage = serde_json::from_str(r#"{"type":"kicked"}"#).unwrap();
+        assert!(matches!(kicked, SignalMessage::Kicked));
+
+        let muted: SignalMessage =
+            serde_json::from_str(r#"{"type":"mute_requested","by":"host"}"#).unwrap();
+        assert!(matches!(muted, SignalMessage::MuteRequested { by } if by == "host"));
+    }
 }
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Label of the per-peer file transfer data channel.
+pub const FILE_CHANNEL_LABEL: &str = "file";
+/// Pre-agreed SCTP stream ID for the file channel (chat uses 0).
+pub const FILE_CHANNEL_ID: u16 = 1;
+/// File bytes per data channel message, well under every SCTP message limit.
+pub const FILE_CHUNK_SIZE: usize = 16 * 1024;
+/// Stop queueing chunks while this much is waiting in the channel's send
+/// buffer, and resume once it drains below the low-water mark.
+pub const FILE_BUFFER_HIGH_WATER: usize = 1024 * 1024;
+pub const FILE_BUFFER_LOW_WATER: usize = 256 * 1024;
+
+/// Transfer ID (16 bytes) + offset (8 bytes, big endian) ahead of each chunk.
+const CHUNK_HEADER_LEN: usize = 24;
+
+/// Control messages on the file channel, sent as JSON text. File data goes
+/// as binary messages framed by [`encode_chunk`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilePayload {
+    Offer {
+        id: String,
+        name: String,
+        size: u64,
+        /// Hex SHA-256 of the whole file, checked by the receiver at the end.
+        sha256: String,
+    },
+    /// Start (or, after a reconnect, resume) sending from `offset`.
+    Accept {
+        id: String,
+        offset: u64,
+    },
+    Decline {
+        id: String,
+    },
+    /// Either side gave up on the transfer.
+    Cancel {
+        id: String,
+    },
+    /// Receiver got every byte; `ok` says whether the checksum matched.
+    Received {
+        id: String,
+        ok: bool,
+    },
+}
+
+impl FilePayload {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("file payload serializes")
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(data).context("Malformed file transfer payload")
+    }
+}
+
+/// What a peer's file channel reports to the engine.
+#[derive(Debug)]
+pub enum FileChannelEvent {
+    /// The channel opened, or the connection came back after dropping:
+    /// incomplete downloads from this peer can resume.
+    Resume,
+    Control(FilePayload),
+    Chunk(Bytes),
+}
+
+pub fn encode_chunk(id: &uuid::Uuid, offset: u64, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(CHUNK_HEADER_LEN + data.len());
+    buf.put_slice(id.as_bytes());
+    buf.put_u64(offset);
+    buf.put_slice(data);
+    buf.freeze()
+}
+
+pub fn decode_chunk(chunk: &[u8]) -> Result<(String, u64, &[u8])> {
+    anyhow::ensure!(chunk.len() >= CHUNK_HEADER_LEN, "File chunk too short");
+    let id = uuid::Uuid::from_slice(&chunk[..16])?;
+    let offset = u64::from_be_bytes(chunk[16..CHUNK_HEADER_LEN].try_into()?);
+    Ok((id.to_string(), offset, &chunk[CHUNK_HEADER_LEN..]))
+}
+
+/// Spots a peer connection coming back after it dropped. An ICE restart
+/// keeps the same data channel, so the channel's open event doesn't fire again.
+#[derive(Default)]
+pub struct ReconnectWatch {
+    dropped: AtomicBool,
+}
+
+impl ReconnectWatch {
+    /// The connection went down (disconnected or failed).
+    pub fn dropped(&self) {
+        self.dropped.store(true, Ordering::Relaxed);
+    }
+
+    /// The connection is up; true if it had dropped since it was last up.
+    pub fn connected(&self) -> bool {
+        self.dropped.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Hex SHA-256 of a file on disk. Blocking; run it off the async runtime.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex_digest(hasher))
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Keep only the final path component of a name chosen by the remote peer.
+pub fn sanitize_file_name(name: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .filter(|n| !n.is_empty())
+        .unwrap_or("file")
+        .to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncomingState {
+    /// Offered by the peer, waiting for the user to accept.
+    Offered,
+    Receiving,
+    /// All bytes arrived and the checksum matched; ready to save.
+    Verified,
+}
+
+/// A file being received. Bytes are written to a temp file as they arrive
+/// and hashed incrementally, so a resumed transfer picks up where it stopped.
+/// The file I/O itself is left to the caller, to run off the async runtime
+/// without holding the transfers lock.
+pub struct IncomingFile {
+    pub peer_id: String,
+    pub name: String,
+    pub size: u64,
+    sha256: String,
+    pub state: IncomingState,
+    temp_path: PathBuf,
+    file: Option<Arc<File>>,
+    hasher: Sha256,
+    pub received: u64,
+}
+
+impl IncomingFile {
+    pub fn new(peer_id: String, name: &str, size: u64, sha256: String, temp_path: PathBuf) -> Self {
+        Self {
+            peer_id,
+            name: sanitize_file_name(name),
+            size,
+            sha256,
+            state: IncomingState::Offered,
+            temp_path,
+            file: None,
+            hasher: Sha256::new(),
+            received: 0,
+        }
+    }
+
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Start receiving into `file`, the newly created temp file (see
+    /// [`create_temp_file`]).
+    pub fn start(&mut self, file: File) {
+        self.file = Some(Arc::new(file));
+        self.hasher = Sha256::new();
+        self.received = 0;
+        self.state = IncomingState::Receiving;
+    }
+
+    /// Where a chunk goes. `Ok(None)` for a chunk we already have, resent
+    /// after a resume; otherwise the temp file to append it to with
+    /// [`append_chunk`], followed by [`IncomingFile::chunk_written`].
+    pub fn chunk_target(&self, offset: u64, len: usize) -> Result<Option<Arc<File>>> {
+        anyhow::ensure!(
+            self.state == IncomingState::Receiving,
+            "Transfer is not receiving"
+        );
+        if offset + len as u64 <= self.received {
+            return Ok(None);
+        }
+        anyhow::ensure!(offset == self.received, "File chunk out of order");
+        anyhow::ensure!(
+            self.received + len as u64 <= self.size,
+            "Peer sent more data than offered"
+        );
+        let file = self.file.as_ref().context("Transfer not started")?;
+        Ok(Some(Arc::clone(file)))
+    }
+
+    /// Account for a chunk appended at the current offset.
+    pub fn chunk_written(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.received += data.len() as u64;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == IncomingState::Receiving && self.received == self.size
+    }
+
+    /// Compare checksums and hand back the temp file, for the caller to
+    /// flush to disk with `sync_all`.
+    pub fn verify(&mut self) -> Result<(Arc<File>, bool)> {
+        let file = self.file.take().context("Transfer not started")?;
+        let ok = hex_digest(self.hasher.clone()).eq_ignore_ascii_case(&self.sha256);
+        if ok {
+            self.state = IncomingState::Verified;
+        }
+        Ok((file, ok))
+    }
+
+    /// The verified download, ready to move into place with [`move_file`].
+    pub fn verified_path(&self) -> Result<&Path> {
+        anyhow::ensure!(
+            self.state == IncomingState::Verified,
+            "File has not been received yet"
+        );
+        Ok(&self.temp_path)
+    }
+}
+
+/// Create a download's temp file. Fails if it already exists, so accepting
+/// twice can't truncate a download in progress. Blocking; run it off the
+/// async runtime.
+pub fn create_temp_file(path: &Path) -> Result<File> {
+    File::create_new(path).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Append a chunk to a download's temp file. Blocking; run it off the async runtime.
+pub fn append_chunk(file: &File, data: &[u8]) -> Result<()> {
+    let mut file = file;
+    file.write_all(data).context("Failed to write file chunk")
+}
+
+/// Move a saved download from its temp file to `dest`. Blocking; run it off
+/// the async runtime.
+pub fn move_file(from: &Path, dest: &Path) -> Result<()> {
+    if std::fs::rename(from, dest).is_err() {
+        // Different filesystem: copy, then drop the temp file
+        std::fs::copy(from, dest)
+            .with_context(|| format!("Failed to save to {}", dest.display()))?;
+        let _ = std::fs::remove_file(from);
+    }
+    Ok(())
+}
+
+impl Drop for IncomingFile {
+    fn drop(&mut self) {
+        // Already moved away if the file was saved
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
+
+/// A file we offered to a peer.
+pub struct OutgoingFile {
+    pub peer_id: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Task streaming chunks, while a send is in progress.
+    pub task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Drop for OutgoingFile {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// All transfers of the current call, by transfer ID.
+#[derive(Default)]
+pub struct FileTransfers {
+    pub incoming: HashMap<String, IncomingFile>,
+    pub outgoing: HashMap<String, OutgoingFile>,
+}
+
+impl FileTransfers {
+    /// Unfinished downloads from a peer and the offset each should resume at.
+    pub fn resumable(&self, peer_id: &str) -> Vec<(String, u64)> {
+        self.incoming
+            .iter()
+            .filter(|(_, t)| t.peer_id == peer_id && t.state == IncomingState::Receiving)
+            .map(|(id, t)| (id.clone(), t.received))
+            .collect()
+    }
+
+    /// Drop every transfer involving a peer that left.
+    pub fn remove_peer(&mut self, peer_id: &str) {
+        self.incoming.retain(|_, t| t.peer_id != peer_id);
+        self.outgoing.retain(|_, t| t.peer_id != peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(tag: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("entavi-test-{tag}-{}.part", uuid::Uuid::new_v4()))
+    }
+
+    fn digest(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hex_digest(hasher)
+    }
+
+    fn start(incoming: &mut IncomingFile) {
+        let file = create_temp_file(incoming.temp_path()).unwrap();
+        incoming.start(file);
+    }
+
+    /// Append a chunk the way the engine does; returns whether it was new.
+    fn write_chunk(incoming: &mut IncomingFile, offset: u64, data: &[u8]) -> Result<bool> {
+        let Some(file) = incoming.chunk_target(offset, data.len())? else {
+            return Ok(false);
+        };
+        append_chunk(&file, data)?;
+        incoming.chunk_written(data);
+        Ok(true)
+    }
+
+    fn verify(incoming: &mut IncomingFile) -> bool {
+        let (file, ok) = incoming.verify().unwrap();
+        file.sync_all().unwrap();
+        ok
+    }
+
+    #[test]
+    fn chunk_framing_round_trips() {
+        let id = uuid::Uuid::new_v4();
+        let chunk = encode_chunk(&id, 32_768, b"abc");
+
+        let (decoded_id, offset, data) = decode_chunk(&chunk).unwrap();
+
+        assert_eq!(decoded_id, id.to_string());
+        assert_eq!(offset, 32_768);
+        assert_eq!(data, b"abc");
+        assert!(decode_chunk(&chunk[..10]).is_err());
+    }
+
+    #[test]
+    fn incoming_file_assembles_verifies_and_saves() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let mut incoming = IncomingFile::new(
+            "peer".to_string(),
+            "../../etc/notes.txt",
+            data.len() as u64,
+            digest(&data),
+            temp_path("assemble"),
+        );
+        assert_eq!(incoming.name, "notes.txt");
+        start(&mut incoming);
+        assert_eq!(incoming.received, 0);
+
+        for (i, chunk) in data.chunks(FILE_CHUNK_SIZE).enumerate() {
+            let offset = (i * FILE_CHUNK_SIZE) as u64;
+            assert!(write_chunk(&mut incoming, offset, chunk).unwrap());
+        }
+        assert!(incoming.is_complete());
+        assert!(verify(&mut incoming));
+
+        let dest = temp_path("saved");
+        move_file(incoming.verified_path().unwrap(), &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        std::fs::remove_file(dest).unwrap();
+    }
+
+    #[test]
+    fn resumed_transfer_skips_duplicates_and_rejects_gaps() {
+        let data = vec![7u8; 3 * FILE_CHUNK_SIZE];
+        let mut incoming = IncomingFile::new(
+            "peer".to_string(),
+            "a.bin",
+            data.len() as u64,
+            digest(&data),
+            temp_path("resume"),
+        );
+        start(&mut incoming);
+        let chunks: Vec<_> = data.chunks(FILE_CHUNK_SIZE).collect();
+        write_chunk(&mut incoming, 0, chunks[0]).unwrap();
+
+        // After a reconnect we ask to resume at the bytes we hold
+        assert_eq!(incoming.received, FILE_CHUNK_SIZE as u64);
+        // A late duplicate of what we have is ignored, a gap is an error
+        assert!(!write_chunk(&mut incoming, 0, chunks[0]).unwrap());
+        assert!(write_chunk(&mut incoming, 2 * FILE_CHUNK_SIZE as u64, chunks[2]).is_err());
+
+        write_chunk(&mut incoming, FILE_CHUNK_SIZE as u64, chunks[1]).unwrap();
+        write_chunk(&mut incoming, 2 * FILE_CHUNK_SIZE as u64, chunks[2]).unwrap();
+        assert!(verify(&mut incoming));
+    }
+
+    #[test]
+    fn transfer_resumes_after_ice_restart_from_confirmed_offset() {
+        let data: Vec<u8> = (0..4 * FILE_CHUNK_SIZE as u32)
+            .map(|i| (i % 13) as u8)
+            .collect();
+        let chunks: Vec<_> = data.chunks(FILE_CHUNK_SIZE).collect();
+        let mut transfers = FileTransfers::default();
+        let mut incoming = IncomingFile::new(
+            "peer".to_string(),
+            "a.bin",
+            data.len() as u64,
+            digest(&data),
+            temp_path("ice-restart"),
+        );
+        start(&mut incoming);
+        transfers.incoming.insert("t".to_string(), incoming);
+        let watch = ReconnectWatch::default();
+
+        // The first connect isn't a reconnect; the channel's open event covers it
+        assert!(!watch.connected());
+        let incoming = transfers.incoming.get_mut("t").unwrap();
+        write_chunk(incoming, 0, chunks[0]).unwrap();
+        write_chunk(incoming, FILE_CHUNK_SIZE as u64, chunks[1]).unwrap();
+
+        // The link fails mid-transfer and ICE restarts on the same connection
+        watch.dropped();
+        assert!(watch.connected(), "coming back after the restart resumes");
+        assert!(!watch.connected(), "one resume per reconnect");
+        assert_eq!(
+            transfers.resumable("peer"),
+            vec![("t".to_string(), 2 * FILE_CHUNK_SIZE as u64)]
+        );
+        assert!(transfers.resumable("other").is_empty());
+
+        // The sender restarts at that offset; chunks still in flight from
+        // before the outage arrive first and are skipped
+        let incoming = transfers.incoming.get_mut("t").unwrap();
+        assert!(!write_chunk(incoming, FILE_CHUNK_SIZE as u64, chunks[1]).unwrap());
+        for (i, chunk) in chunks.iter().enumerate().skip(2) {
+            assert!(write_chunk(incoming, (i * FILE_CHUNK_SIZE) as u64, chunk).unwrap());
+        }
+        assert!(incoming.is_complete());
+        assert!(verify(incoming));
+    }
+
+    #[test]
+    fn checksum_mismatch_is_not_saved() {
+        let mut incoming = IncomingFile::new(
+            "peer".to_string(),
+            "a.bin",
+            4,
+            digest(b"good"),
+            temp_path("mismatch"),
+        );
+        start(&mut incoming);
+        write_chunk(&mut incoming, 0, b"evil").unwrap();
+        assert!(write_chunk(&mut incoming, 4, b"x").is_err());
+
+        assert!(!verify(&mut incoming));
+        assert!(incoming.verified_path().is_err());
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use crate::types::{JitterStats, FRAME_SIZE, SAMPLE_RATE};
+
+// ── Tuning ──
+
+/// Lower/upper bounds for the adaptive playout delay, in 20ms frames.
+const MIN_TARGET_FRAMES: usize = 2; // 40ms
+const MAX_TARGET_FRAMES: usize = 12; // 240ms
+/// Hard cap on queued packets. Anything beyond this is stale and gets dropped.
+const MAX_BUFFERED_FRAMES: usize = 50; // 1s
+/// Target delay covers this many multiples of the measured jitter.
+const JITTER_MARGIN: f64 = 3.0;
+/// Consecutive packets with lower measured jitter before the target shrinks a frame.
+const SHRINK_AFTER_PACKETS: u32 = 150; // ~3s
+/// Consecutive pulls with the queue over target before a frame is dropped to cut latency.
+const EXCESS_PULLS_BEFORE_DROP: u32 = 25; // ~500ms
+/// A sequence jump this large means the sender restarted its stream.
+const SEQ_RESET_DISTANCE: u64 = 1_000;
+/// Arrival and timestamp gaps this far apart (in samples, 1s) aren't network
+/// jitter but a sender that paused or restarted its clock.
+const CLOCK_MISMATCH: f64 = SAMPLE_RATE as f64;
+
+const FRAME_MS: u32 = (FRAME_SIZE as u32 * 1000) / SAMPLE_RATE;
+
+/// What the mixer should play for a peer on this tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// The next packet in sequence.
+    Packet(Bytes),
+    /// The next packet never arrived but later ones did; playout skipped past it.
+    Lost,
+    /// Nothing to play: still buffering up to the target delay, or the queue ran dry.
+    Empty,
+}
+
+/// Adaptive jitter buffer for one remote peer's RTP audio stream.
+///
+/// Packets are keyed on their extended RTP sequence number so late and
+/// reordered arrivals slot back into place. Interarrival jitter is measured
+/// from RTP timestamps (RFC 3550 §6.4.1) and drives the playout delay: it
+/// grows as soon as jitter rises and shrinks slowly once the network settles.
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Bytes>,
+    /// Highest extended sequence number seen so far.
+    highest_seq: Option<u64>,
+    /// Next sequence number to hand to the mixer.
+    next_seq: Option<u64>,
+    /// True while (re)filling up to the target delay before playout starts.
+    buffering: bool,
+    /// Smoothed interarrival jitter, in samples.
+    jitter: f64,
+    last_arrival: Option<(Instant, u32)>,
+    target_frames: usize,
+    calm_packets: u32,
+    excess_pulls: u32,
+    late_packets: u64,
+    lost_packets: u64,
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JitterBuffer {
+    pub fn new() -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            highest_seq: None,
+            next_seq: None,
+            buffering: true,
+            jitter: 0.0,
+            last_arrival: None,
+            target_frames: MIN_TARGET_FRAMES,
+            calm_packets: 0,
+            excess_pulls: 0,
+            late_packets: 0,
+            lost_packets: 0,
+        }
+    }
+
+    /// Queue an incoming RTP packet. `marker` is the RTP marker bit, set on
+    /// the first packet after a transmission gap.
+    pub fn push(
+        &mut self,
+        seq: u16,
+        timestamp: u32,
+        marker: bool,
+        payload: Bytes,
+        arrival: Instant,
+    ) {
+        let ext_seq = self.extend_seq(seq);
+
+        if let Some(highest) = self.highest_seq {
+            if ext_seq.abs_diff(highest) > SEQ_RESET_DISTANCE {
+                tracing::debug!(
+                    "RTP sequence jumped from {highest} to {ext_seq}, resetting jitter buffer"
+                );
+                self.reset();
+                return self.push(seq, timestamp, marker, payload, arrival);
+            }
+        }
+
+        if self.next_seq.is_some_and(|next| ext_seq < next) {
+            // Its playout slot has already passed.
+            self.late_packets += 1;
+            return;
+        }
+
+        if self.highest_seq.is_none_or(|highest| ext_seq > highest) {
+            self.update_jitter(timestamp, marker, arrival);
+            self.highest_seq = Some(ext_seq);
+        }
+
+        self.packets.entry(ext_seq).or_insert(payload);
+
+        while self.packets.len() > MAX_BUFFERED_FRAMES {
+            if let Some((seq, _)) = self.packets.pop_first() {
+                self.next_seq = Some(seq + 1);
+            }
+        }
+    }
+
+    /// Take the next frame to play. Call exactly once per 20ms mixer tick.
+    pub fn pop(&mut self) -> Playout {
+        if self.buffering {
+            if self.packets.len() < self.target_frames {
+                return Playout::Empty;
+            }
+            self.buffering = false;
+            self.excess_pulls = 0;
+            self.next_seq = self.packets.keys().next().copied();
+        }
+
+        let Some(next) = self.next_seq else {
+            return Playout::Empty;
+        };
+
+        // Sustained excess delay: drop the oldest frame so latency follows the target down.
+        if self.packets.len() > self.target_frames + 1 {
+            self.excess_pulls += 1;
+            if self.excess_pulls >= EXCESS_PULLS_BEFORE_DROP {
+                self.excess_pulls = 0;
+                if let Some((seq, _)) = self.packets.pop_first() {
+                    self.next_seq = Some(seq + 1);
+                    return self.pop();
+                }
+            }
+        } else {
+            self.excess_pulls = 0;
+        }
+
+        if let Some(payload) = self.packets.remove(&next) {
+            self.next_seq = Some(next + 1);
+            return Playout::Packet(payload);
+        }
+
+        if self.packets.is_empty() {
+            // Underrun: rebuffer up to the (possibly grown) target before resuming.
+            self.buffering = true;
+            return Playout::Empty;
+        }
+
+        self.next_seq = Some(next + 1);
+        self.lost_packets += 1;
+        Playout::Lost
+    }
+
+    /// The packet that will play next, if it has already arrived. After a
+    /// [`Playout::Lost`] this is the packet following the gap, whose in-band
+    /// FEC data can reconstruct the missing frame.
+    pub fn peek_next(&self) -> Option<&Bytes> {
+        self.packets.get(&self.next_seq?)
+    }
+
+    /// Current queue depth in milliseconds.
+    pub fn depth_ms(&self) -> u32 {
+        self.packets.len() as u32 * FRAME_MS
+    }
+
+    /// Current playout delay target in milliseconds.
+    pub fn target_ms(&self) -> u32 {
+        self.target_frames as u32 * FRAME_MS
+    }
+
+    /// Smoothed interarrival jitter in milliseconds.
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter * 1000.0 / SAMPLE_RATE as f64
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        JitterStats {
+            depth_ms: self.depth_ms(),
+            target_ms: self.target_ms(),
+            jitter_ms: self.jitter_ms(),
+            late_packets: self.late_packets,
+            lost_packets: self.lost_packets,
+            ..Default::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self {
+            late_packets: self.late_packets,
+            lost_packets: self.lost_packets,
+            ..Self::new()
+        };
+    }
+
+    /// Unwrap a 16-bit RTP sequence number relative to the highest one seen.
+    fn extend_seq(&self, seq: u16) -> u64 {
+        // Start well above zero so early reordered packets don't underflow.
+        let Some(highest) = self.highest_seq else {
+            return (1 << 32) | u64::from(seq);
+        };
+        let base = (highest & !0xffff) | u64::from(seq);
+        [base.wrapping_sub(1 << 16), base, base + (1 << 16)]
+            .into_iter()
+            .min_by_key(|candidate| candidate.abs_diff(highest))
+            .unwrap_or(base)
+    }
+
+    fn update_jitter(&mut self, timestamp: u32, marker: bool, arrival: Instant) {
+        let last = self.last_arrival.replace((arrival, timestamp));
+        // A talkspurt starts a new measurement: the gap before it is silence
+        let Some((last_arrival, last_timestamp)) = last.filter(|_| !marker) else {
+            return;
+        };
+        let arrival_delta = arrival
+            .saturating_duration_since(last_arrival)
+            .as_secs_f64()
+            * SAMPLE_RATE as f64;
+        let timestamp_delta = f64::from(timestamp.wrapping_sub(last_timestamp) as i32);
+        let d = (arrival_delta - timestamp_delta).abs();
+        if timestamp_delta < 0.0 || d > CLOCK_MISMATCH {
+            return;
+        }
+        self.jitter += (d - self.jitter) / 16.0;
+
+        let desired = ((JITTER_MARGIN * self.jitter / FRAME_SIZE as f64).ceil() as usize + 1)
+            .clamp(MIN_TARGET_FRAMES, MAX_TARGET_FRAMES);
+        if desired > self.target_frames {
+            self.target_frames = desired;
+            self.calm_packets = 0;
+        } else if desired < self.target_frames {
+            self.calm_packets += 1;
+            if self.calm_packets >= SHRINK_AFTER_PACKETS {
+                self.target_frames -= 1;
+                self.calm_packets = 0;
+            }
+        } else {
+            self.calm_packets = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn payload(n: u8) -> Bytes {
+        Bytes::from(vec![n])
+    }
+
+    fn push_steady(jb: &mut JitterBuffer, start: Instant, seqs: impl IntoIterator<Item = u16>) {
+        for seq in seqs {
+            let at = start + Duration::from_millis(u64::from(seq) * 20);
+            jb.push(
+                seq,
+                u32::from(seq) * FRAME_SIZE as u32,
+                false,
+                payload(seq as u8),
+                at,
+            );
+        }
+    }
+
+    #[test]
+    fn waits_for_target_depth_before_playout() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, [0]);
+        assert_eq!(jb.pop(), Playout::Empty);
+
+        push_steady(&mut jb, start, [1]);
+        assert_eq!(jb.pop(), Playout::Packet(payload(0)));
+        assert_eq!(jb.pop(), Playout::Packet(payload(1)));
+    }
+
+    #[test]
+    fn reorders_out_of_order_packets() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, [0, 2, 1, 3]);
+
+        for n in 0..4 {
+            assert_eq!(jb.pop(), Playout::Packet(payload(n)));
+        }
+    }
+
+    #[test]
+    fn reports_gap_and_discards_packet_that_arrives_after_its_slot() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, [0, 2, 3]);
+
+        assert_eq!(jb.pop(), Playout::Packet(payload(0)));
+        assert_eq!(jb.pop(), Playout::Lost);
+
+        push_steady(&mut jb, start, [1]);
+        assert_eq!(jb.pop(), Playout::Packet(payload(2)));
+        assert_eq!(jb.late_packets, 1);
+    }
+
+    #[test]
+    fn peek_after_gap_returns_packet_carrying_fec() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, [0, 2]);
+
+        assert_eq!(jb.pop(), Playout::Packet(payload(0)));
+        assert_eq!(jb.pop(), Playout::Lost);
+        assert_eq!(jb.peek_next(), Some(&payload(2)));
+        assert_eq!(jb.pop(), Playout::Packet(payload(2)));
+        assert_eq!(jb.peek_next(), None);
+    }
+
+    #[test]
+    fn extends_sequence_numbers_across_wraparound() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        for (i, seq) in [65_534u16, 65_535, 0, 1].into_iter().enumerate() {
+            let at = start + Duration::from_millis(i as u64 * 20);
+            jb.push(
+                seq,
+                i as u32 * FRAME_SIZE as u32,
+                false,
+                payload(i as u8),
+                at,
+            );
+        }
+
+        for n in 0..4 {
+            assert_eq!(jb.pop(), Playout::Packet(payload(n)));
+        }
+    }
+
+    #[test]
+    fn target_delay_grows_with_jitter() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, 0..50);
+        assert_eq!(jb.target_ms(), MIN_TARGET_FRAMES as u32 * FRAME_MS);
+
+        // Alternate packets arrive 60ms late.
+        for seq in 50u16..100 {
+            let late = if seq % 2 == 0 { 60 } else { 0 };
+            let at = start + Duration::from_millis(u64::from(seq) * 20 + late);
+            jb.push(
+                seq,
+                u32::from(seq) * FRAME_SIZE as u32,
+                false,
+                payload(0),
+                at,
+            );
+        }
+
+        assert!(jb.jitter_ms() > 20.0);
+        assert!(jb.target_ms() > MIN_TARGET_FRAMES as u32 * FRAME_MS);
+    }
+
+    #[test]
+    fn transmission_gaps_do_not_count_as_jitter() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+        push_steady(&mut jb, start, 0..50);
+        let target = jb.target_frames;
+
+        // Five seconds of silence, after which the sender's clock had only
+        // moved a frame on: first flagged as a talkspurt, then without the
+        // marker bit
+        let mut at = start + Duration::from_millis(49 * 20);
+        let mut timestamp = 49 * FRAME_SIZE as u32;
+        for (n, marker) in [true, false].into_iter().enumerate() {
+            at += Duration::from_secs(5);
+            for i in 0..10u16 {
+                let seq = 50 + n as u16 * 10 + i;
+                timestamp += FRAME_SIZE as u32;
+                jb.push(seq, timestamp, marker && i == 0, payload(0), at);
+                at += Duration::from_millis(20);
+            }
+        }
+
+        assert!(jb.jitter_ms() < 1.0, "jitter {}ms", jb.jitter_ms());
+        assert_eq!(jb.target_frames, target);
+    }
+
+    #[test]
+    fn rebuffers_after_underrun() {
+        let mut jb = JitterBuffer::new();
+        let start = Instant::now();
+
+        push_steady(&mut jb, start, [0, 1]);
+        assert!(matches!(jb.pop(), Playout::Packet(_)));
+        assert!(matches!(jb.pop(), Playout::Packet(_)));
+        assert_eq!(jb.pop(), Playout::Empty);
+
+        push_steady(&mut jb, start, [2]);
+        assert_eq!(jb.pop(), Playout::Empty);
+        assert_eq!(jb.depth_ms(), FRAME_MS);
+    }
+}
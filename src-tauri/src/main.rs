@@ -1,13 +1,30 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod aec;
 mod audio;
+mod bitrate;
+mod chat;
+mod comfort_noise;
+mod cue;
+mod devices;
+mod e2ee;
 mod engine;
+mod file_transfer;
+mod jitter;
+mod music;
 mod peer;
+mod recording;
+mod resampler;
+mod sas;
+mod settings;
 mod signaling;
+mod source;
 mod types;
+mod vad;
 
 use engine::Engine;
+use settings::Settings;
 use std::sync::Arc;
 use tauri::{
     menu::{MenuBuilder, MenuItem},
@@ -16,7 +33,10 @@ use tauri::{
 };
 use tauri_plugin_notification::NotificationExt;
 use tauri_plugin_updater::UpdaterExt;
-use types::AudioDevice;
+use types::{
+    AudioDevice, ChatMessageEvent, DeviceConfig, DeviceFormats, DeviceKind, InputMode,
+    PeerCallStats, PeerJitterStats, RecordingFormat, RecordingLayout, SasSymbol,
+};
 
 #[tauri::command]
 async fn show_notification(
@@ -76,9 +96,11 @@ async fn create_room(
     engine: tauri::State<'_, Engine>,
     room_name: String,
     name: String,
+    password: Option<String>,
+    e2ee: bool,
 ) -> Result<String, String> {
     engine
-        .create_room(room_name, name)
+        .create_room(room_name, name, password, e2ee)
         .await
         .map_err(|e| e.to_string())
 }
@@ -106,6 +128,199 @@ async fn set_muted(engine: tauri::State<'_, Engine>, muted: bool) -> Result<(),
     engine.set_muted(muted).await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn set_room_locked(engine: tauri::State<'_, Engine>, locked: bool) -> Result<(), String> {
+    engine
+        .set_room_locked(locked)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn kick_peer(engine: tauri::State<'_, Engine>, peer_id: String) -> Result<(), String> {
+    engine.kick_peer(peer_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn request_peer_mute(
+    engine: tauri::State<'_, Engine>,
+    peer_id: String,
+) -> Result<(), String> {
+    engine
+        .request_peer_mute(peer_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_recording(
+    engine: tauri::State<'_, Engine>,
+    format: RecordingFormat,
+    layout: RecordingLayout,
+) -> Result<String, String> {
+    engine
+        .start_recording(format, layout)
+        .await
+        .map(|dir| dir.display().to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_recording(engine: tauri::State<'_, Engine>) -> Result<Vec<String>, String> {
+    engine
+        .stop_recording()
+        .await
+        .map(|files| files.iter().map(|f| f.display().to_string()).collect())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_peer_sas(
+    engine: tauri::State<'_, Engine>,
+    peer_id: String,
+) -> Result<Vec<SasSymbol>, String> {
+    engine.peer_sas(peer_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_chat_message(
+    engine: tauri::State<'_, Engine>,
+    text: String,
+) -> Result<ChatMessageEvent, String> {
+    engine
+        .send_chat_message(text)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_file(
+    engine: tauri::State<'_, Engine>,
+    peer_id: String,
+    path: String,
+) -> Result<String, String> {
+    engine
+        .send_file(peer_id, path.into())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn accept_file_transfer(engine: tauri::State<'_, Engine>, id: String) -> Result<(), String> {
+    engine
+        .accept_file_transfer(id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn decline_file_transfer(engine: tauri::State<'_, Engine>, id: String) -> Result<(), String> {
+    engine
+        .decline_file_transfer(id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_file_transfer(engine: tauri::State<'_, Engine>, id: String) -> Result<(), String> {
+    engine
+        .cancel_file_transfer(id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_received_file(
+    engine: tauri::State<'_, Engine>,
+    id: String,
+    path: String,
+) -> Result<(), String> {
+    engine
+        .save_received_file(id, path.into())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_input_mode(
+    engine: tauri::State<'_, Engine>,
+    mode: InputMode,
+    shortcut: Option<String>,
+) -> Result<(), String> {
+    engine
+        .set_input_mode(mode, shortcut)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_music_mode(engine: tauri::State<'_, Engine>, enabled: bool) -> Result<(), String> {
+    engine.set_music_mode(enabled).await;
+    Ok(())
+}
+
+#[tauri::command]
+fn list_audio_share_sources(engine: tauri::State<'_, Engine>) -> Vec<AudioDevice> {
+    engine.list_audio_share_sources()
+}
+
+#[tauri::command]
+async fn set_audio_share(
+    engine: tauri::State<'_, Engine>,
+    device_name: Option<String>,
+) -> Result<(), String> {
+    engine
+        .set_audio_share(device_name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_shared_audio_gain(engine: tauri::State<'_, Engine>, gain: f32) {
+    engine.set_shared_audio_gain(gain);
+}
+
+#[tauri::command]
+async fn play_audio_clip(engine: tauri::State<'_, Engine>, path: String) -> Result<(), String> {
+    engine
+        .play_audio_clip(path.into())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn play_test_tone(engine: tauri::State<'_, Engine>) -> Result<(), String> {
+    engine.play_test_tone().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn stop_audio_clip(engine: tauri::State<'_, Engine>) {
+    engine.stop_audio_clip();
+}
+
+#[tauri::command]
+async fn set_input_file(
+    engine: tauri::State<'_, Engine>,
+    path: Option<String>,
+) -> Result<(), String> {
+    engine
+        .set_input_file(path.map(Into::into))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_peer_volume(
+    engine: tauri::State<'_, Engine>,
+    peer_id: String,
+    gain: f32,
+) -> Result<(), String> {
+    engine
+        .set_peer_volume(peer_id, gain)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn list_input_devices(engine: tauri::State<'_, Engine>) -> Vec<AudioDevice> {
     engine.list_input_devices()
@@ -123,8 +338,24 @@ async fn set_input_device(
 }
 
 #[tauri::command]
-fn set_signaling_url(engine: tauri::State<'_, Engine>, url: Option<String>) {
-    engine.set_signaling_url(url);
+fn get_settings(engine: tauri::State<'_, Engine>) -> Settings {
+    engine.settings()
+}
+
+#[tauri::command]
+async fn update_settings(
+    engine: tauri::State<'_, Engine>,
+    settings: Settings,
+) -> Result<Settings, String> {
+    engine
+        .update_settings(settings)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_signaling_url(engine: tauri::State<'_, Engine>, url: Option<String>) -> Result<(), String> {
+    engine.set_signaling_url(url).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -132,6 +363,38 @@ fn set_noise_suppression(engine: tauri::State<'_, Engine>, enabled: bool) {
     engine.set_noise_suppression(enabled);
 }
 
+#[tauri::command]
+fn set_echo_cancellation(engine: tauri::State<'_, Engine>, enabled: bool) {
+    engine.set_echo_cancellation(enabled);
+}
+
+#[tauri::command]
+async fn set_bitrate_bounds(
+    engine: tauri::State<'_, Engine>,
+    min_bitrate: u32,
+    max_bitrate: u32,
+) -> Result<(), String> {
+    engine
+        .set_bitrate_bounds(min_bitrate, max_bitrate)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_vad_threshold(engine: tauri::State<'_, Engine>, threshold: f32) {
+    engine.set_vad_threshold(threshold);
+}
+
+#[tauri::command]
+fn set_agc_enabled(engine: tauri::State<'_, Engine>, enabled: bool) {
+    engine.set_agc_enabled(enabled);
+}
+
+#[tauri::command]
+async fn calibrate_vad(engine: tauri::State<'_, Engine>) -> Result<f32, String> {
+    engine.calibrate_vad().await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn list_output_devices(engine: tauri::State<'_, Engine>) -> Vec<AudioDevice> {
     engine.list_output_devices()
@@ -148,6 +411,30 @@ async fn set_output_device(
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn get_device_formats(
+    engine: tauri::State<'_, Engine>,
+    kind: DeviceKind,
+    device_name: Option<String>,
+) -> Result<DeviceFormats, String> {
+    engine
+        .device_formats(kind, device_name)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_device_config(
+    engine: tauri::State<'_, Engine>,
+    kind: DeviceKind,
+    device_name: String,
+    config: DeviceConfig,
+) -> Result<(), String> {
+    engine
+        .set_device_config(kind, device_name, config)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn start_mic_test(engine: tauri::State<'_, Engine>) -> Result<(), String> {
     engine.start_mic_test().map_err(|e| e.to_string())
@@ -158,6 +445,18 @@ fn stop_mic_test(engine: tauri::State<'_, Engine>) {
     engine.stop_mic_test();
 }
 
+#[tauri::command]
+async fn get_call_stats(engine: tauri::State<'_, Engine>) -> Result<Vec<PeerCallStats>, String> {
+    Ok(engine.call_stats().await)
+}
+
+#[tauri::command]
+async fn get_jitter_stats(
+    engine: tauri::State<'_, Engine>,
+) -> Result<Vec<PeerJitterStats>, String> {
+    Ok(engine.jitter_stats().await)
+}
+
 fn show_window(app: &tauri::AppHandle) {
     // Switch to Regular so macOS gives the app keyboard focus
     #[cfg(target_os = "macos")]
@@ -191,6 +490,7 @@ fn main() {
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .on_window_event(|window, event| {
             if let tauri::WindowEvent::CloseRequested { api, .. } = event {
                 api.prevent_close();
@@ -202,6 +502,11 @@ fn main() {
             let engine = Engine::new(app.handle().clone());
             app.manage(engine);
 
+            let device_watch_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                device_watch_handle.state::<Engine>().watch_devices().await;
+            });
+
             let main_window = app.get_webview_window("main").unwrap();
 
             let open = MenuItem::with_id(app, "open", "Open", true, None::<&str>)?;
@@ -274,14 +579,47 @@ fn main() {
             join_room,
             leave_room,
             set_muted,
+            set_room_locked,
+            kick_peer,
+            request_peer_mute,
+            start_recording,
+            stop_recording,
+            get_peer_sas,
+            send_chat_message,
+            send_file,
+            accept_file_transfer,
+            decline_file_transfer,
+            cancel_file_transfer,
+            save_received_file,
+            set_input_mode,
+            set_music_mode,
+            list_audio_share_sources,
+            set_audio_share,
+            set_shared_audio_gain,
+            play_audio_clip,
+            play_test_tone,
+            stop_audio_clip,
+            set_input_file,
+            set_peer_volume,
             list_input_devices,
             set_input_device,
             list_output_devices,
             set_output_device,
+            get_device_formats,
+            set_device_config,
+            get_settings,
+            update_settings,
             set_signaling_url,
             set_noise_suppression,
+            set_echo_cancellation,
+            set_vad_threshold,
+            set_agc_enabled,
+            set_bitrate_bounds,
+            calibrate_vad,
             start_mic_test,
             stop_mic_test,
+            get_jitter_stats,
+            get_call_stats,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
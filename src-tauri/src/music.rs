@@ -0,0 +1,82 @@
+use crate::bitrate::BitrateBounds;
+
+/// Opus parameters we offer and answer with. `stereo=1` says we can receive
+/// stereo (every decoder here is stereo); `sprop-stereo=1` that we may send it.
+pub const OPUS_FMTP_LINE: &str = "minptime=10;useinbandfec=1;stereo=1;sprop-stereo=1";
+/// Music mode's bitrate on a clean link. Congestion scales it down in the same
+/// proportion as the voice bitrate.
+pub const MUSIC_STEREO_BITRATE: u32 = 160_000;
+pub const MUSIC_MONO_BITRATE: u32 = 96_000;
+
+/// Encoder bitrate in music mode for the adaptive voice bitrate: the share of
+/// the voice maximum the link currently allows, applied to the music rate.
+pub fn music_bitrate(voice_bitrate: u32, bounds: BitrateBounds, stereo: bool) -> u32 {
+    let full = if stereo {
+        MUSIC_STEREO_BITRATE
+    } else {
+        MUSIC_MONO_BITRATE
+    };
+    let share = voice_bitrate.min(bounds.max) as f64 / bounds.max.max(1) as f64;
+    (full as f64 * share) as u32
+}
+
+/// Whether the remote side of an SDP accepts stereo Opus, i.e. its Opus
+/// payload type carries `stereo=1` in its fmtp parameters (RFC 7587 §7.1).
+pub fn sdp_accepts_stereo(sdp: &str) -> bool {
+    let attribute = |name: &str| {
+        let prefix = format!("a={name}:");
+        sdp.lines()
+            .filter_map(move |line| line.trim().strip_prefix(prefix.as_str()))
+            .filter_map(|value| value.split_once(' '))
+            .map(|(pt, rest)| (pt.to_string(), rest.trim().to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    let opus_payload_types: Vec<String> = attribute("rtpmap")
+        .into_iter()
+        .filter(|(_, codec)| codec.to_ascii_lowercase().starts_with("opus/"))
+        .map(|(pt, _)| pt)
+        .collect();
+    attribute("fmtp").into_iter().any(|(pt, params)| {
+        opus_payload_types.contains(&pt)
+            && params
+                .split(';')
+                .any(|param| param.trim().eq_ignore_ascii_case("stereo=1"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sdp(fmtp: &str) -> String {
+        format!(
+            "v=0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111 9\r\n\
+             a=rtpmap:111 opus/48000/2\r\na=fmtp:111 {fmtp}\r\n\
+             a=rtpmap:9 G722/8000\r\na=fmtp:9 stereo=1\r\n"
+        )
+    }
+
+    #[test]
+    fn reads_stereo_from_the_opus_fmtp() {
+        assert!(sdp_accepts_stereo(&sdp(OPUS_FMTP_LINE)));
+        assert!(sdp_accepts_stereo(&sdp("minptime=10; stereo=1")));
+        // A browser's default offer, and stereo on some other codec
+        assert!(!sdp_accepts_stereo(&sdp("minptime=10;useinbandfec=1")));
+        assert!(!sdp_accepts_stereo(&sdp("sprop-stereo=1;stereo=0")));
+        assert!(!sdp_accepts_stereo(""));
+    }
+
+    #[test]
+    fn music_bitrate_follows_the_link() {
+        let bounds = BitrateBounds::default();
+        assert_eq!(
+            music_bitrate(bounds.max, bounds, true),
+            MUSIC_STEREO_BITRATE
+        );
+        assert_eq!(
+            music_bitrate(bounds.max / 2, bounds, false),
+            MUSIC_MONO_BITRATE / 2
+        );
+    }
+}
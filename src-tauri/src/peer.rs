@@ -1,10 +1,20 @@
-use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
+use bytes::Bytes;
+use nnnoiseless::DenoiseState;
+use tokio::sync::Notify;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_OPUS};
 use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice::candidate::CandidatePairState;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::interceptor::registry::Registry;
 use webrtc::peer_connection::configuration::RTCConfiguration;
@@ -12,35 +22,254 @@ use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
-use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::rtcp::sender_report::SenderReport;
+use webrtc::rtp_transceiver::rtp_codec::{
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+};
+use webrtc::stats::StatsReportType;
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
 
+use crate::bitrate::LinkReport;
+use crate::chat::{ChatPayload, CHAT_CHANNEL_ID, CHAT_CHANNEL_LABEL};
+use crate::comfort_noise::ComfortNoise;
+use crate::e2ee::{FrameCipher, FrameSealer, KeyCheck};
+use crate::file_transfer::{
+    FileChannelEvent, FilePayload, ReconnectWatch, FILE_BUFFER_HIGH_WATER, FILE_BUFFER_LOW_WATER,
+    FILE_CHANNEL_ID, FILE_CHANNEL_LABEL,
+};
+use crate::jitter::{JitterBuffer, Playout};
+use crate::music::{sdp_accepts_stereo, OPUS_FMTP_LINE};
+use crate::resampler::{downmix_stereo, upmix_mono};
+use crate::sas;
 use crate::types::{
-    DecodedFrame, EncodedFrame, PcmFrame, SignalPayload, TurnServerInfo, AUDIO_DECODE_QUEUE_FRAMES,
-    CHANNELS, FRAME_SIZE, SAMPLE_RATE,
+    E2eeState, EncodedFrame, JitterStats, PcmFrame, PeerCallStats, SasSymbol, SignalPayload,
+    StereoFrame, TurnServerInfo, CHANNELS, FRAME_SIZE, SAMPLE_RATE,
 };
+use crate::vad::{denoise_frame, VoiceDetector, REMOTE_LEVEL_THRESHOLD};
+
+/// Longest stretch of packet loss concealment before the peer is treated as silent.
+const MAX_CONCEALED_FRAMES: u32 = 5; // 100ms
+
+/// Opus as we offer it and send it.
+fn opus_capability() -> RTCRtpCodecCapability {
+    RTCRtpCodecCapability {
+        mime_type: MIME_TYPE_OPUS.to_string(),
+        clock_rate: SAMPLE_RATE,
+        channels: CHANNELS,
+        sdp_fmtp_line: OPUS_FMTP_LINE.to_string(),
+        rtcp_feedback: vec![],
+    }
+}
+
+/// Frames queued for a peer's voice detector before newer ones are dropped.
+const SPEECH_QUEUE_FRAMES: usize = 4;
+
+/// Voice detection for one remote peer. RNNoise runs on its own thread, so the
+/// mixer tick only copies the decoded frame across; the thread exits when
+/// this is dropped.
+struct RemoteSpeech {
+    /// A decoded mono frame and its length, or `None` for a silent tick.
+    tx: flume::Sender<Option<(PcmFrame, usize)>>,
+    speaking: Arc<AtomicBool>,
+}
+
+impl RemoteSpeech {
+    fn spawn() -> Self {
+        let (tx, rx) = flume::bounded::<Option<(PcmFrame, usize)>>(SPEECH_QUEUE_FRAMES);
+        let speaking = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&speaking);
+        let spawned = std::thread::Builder::new()
+            .name("peer-vad".into())
+            .spawn(move || {
+                let mut denoise = DenoiseState::new();
+                let mut vad = VoiceDetector::default();
+                while let Ok(frame) = rx.recv() {
+                    let speaking = match frame {
+                        Some((mut frame, len)) => {
+                            let mono = &mut frame[..len];
+                            let voice_probability = denoise_frame(&mut denoise, mono, false);
+                            let peak = mono.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+                            vad.update(voice_probability, peak, REMOTE_LEVEL_THRESHOLD)
+                        }
+                        None => vad.update(0.0, 0.0, REMOTE_LEVEL_THRESHOLD),
+                    };
+                    flag.store(speaking, Ordering::Relaxed);
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::warn!("Failed to start remote voice detector: {e}");
+        }
+        Self { tx, speaking }
+    }
+
+    /// Hand a frame to the detector; dropped if it has fallen behind.
+    fn push(&self, mono: Option<&[f32]>) {
+        let frame = mono.map(|mono| {
+            let mut frame = [0.0; FRAME_SIZE];
+            frame[..mono.len()].copy_from_slice(mono);
+            (frame, mono.len())
+        });
+        let _ = self.tx.try_send(frame);
+    }
+
+    fn is_speaking(&self) -> bool {
+        self.speaking.load(Ordering::Relaxed)
+    }
+}
+
+/// Receive side of a remote peer's audio. The RTP reader task fills the jitter
+/// buffer; the engine mixer drains it once per tick and decodes on pull.
+struct RemoteAudio {
+    jitter: JitterBuffer,
+    /// Stereo, so music mode comes through as sent; mono packets decode to
+    /// the same signal on both sides.
+    decoder: opus::Decoder,
+    /// Mono mix of the decoded frame, for the voice detector and comfort noise.
+    mono: PcmFrame,
+    /// Consecutive frames synthesized by PLC/FEC since the last real packet.
+    /// `None` until the first packet plays (or after concealment gives up).
+    concealed_run: Option<u32>,
+    fec_recovered: u64,
+    concealed_frames: u64,
+    /// Speaking indicator, fed the decoded frames.
+    speech: RemoteSpeech,
+    /// Fills the silence while the peer isn't transmitting (DTX).
+    comfort_noise: ComfortNoise,
+    /// Whether the peer's frames decrypt with our room key.
+    key_check: KeyCheck,
+}
+
+impl RemoteAudio {
+    fn pull(&mut self, out: &mut StereoFrame) -> Option<usize> {
+        let Some(len) = self.decode_next(out) else {
+            self.speech.push(None);
+            if !self.comfort_noise.fill(&mut self.mono) {
+                return None;
+            }
+            upmix_mono(&self.mono, out);
+            return Some(FRAME_SIZE);
+        };
+
+        let mono = &mut self.mono[..len];
+        downmix_stereo(&out[..len * CHANNELS as usize], mono);
+        self.speech.push(Some(mono));
+        self.comfort_noise.observe(mono);
+        Some(len)
+    }
+
+    /// Decode into `out`, returning the samples per channel.
+    fn decode_next(&mut self, out: &mut StereoFrame) -> Option<usize> {
+        let result = match self.jitter.pop() {
+            Playout::Packet(payload) => {
+                self.concealed_run = Some(0);
+                self.decoder.decode_float(&payload, out, false)
+            }
+            Playout::Lost => match self.jitter.peek_next() {
+                // The packet after the gap carries in-band FEC for the one we lost.
+                Some(next) => {
+                    self.fec_recovered += 1;
+                    self.concealed_run = Some(0);
+                    self.decoder.decode_float(next, out, true)
+                }
+                None => self.conceal(out)?,
+            },
+            Playout::Empty => self.conceal(out)?,
+        };
+
+        match result {
+            Ok(samples) => Some(samples.min(FRAME_SIZE)),
+            Err(e) => {
+                tracing::warn!("Opus decode error: {e}");
+                None
+            }
+        }
+    }
+
+    /// Let the decoder extrapolate a missing frame (PLC), but only briefly and
+    /// only mid-stream; before the first packet or after a long gap, stay silent.
+    fn conceal(&mut self, out: &mut StereoFrame) -> Option<opus::Result<usize>> {
+        let run = self.concealed_run?;
+        if run >= MAX_CONCEALED_FRAMES {
+            self.concealed_run = None;
+            return None;
+        }
+        self.concealed_run = Some(run + 1);
+        self.concealed_frames += 1;
+        Some(self.decoder.decode_float(&[], out, false))
+    }
+}
+
+struct StatsSample {
+    at: Instant,
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// Bitrates over the window that ended at this sample.
+    send_bitrate: Option<u32>,
+    receive_bitrate: Option<u32>,
+}
+
+/// Channels every PeerConn of a call reports on, tagged with the peer ID.
+#[derive(Clone)]
+pub struct PeerEvents {
+    pub ice_candidate: flume::Sender<(String, SignalPayload)>,
+    pub conn_state: flume::Sender<(String, RTCPeerConnectionState)>,
+    pub link_report: flume::Sender<(String, LinkReport)>,
+    pub chat: flume::Sender<(String, ChatPayload)>,
+    pub file: flume::Sender<(String, FileChannelEvent)>,
+}
 
 pub struct PeerConn {
     pub peer_id: String,
     pub connection: Arc<RTCPeerConnection>,
     pub audio_track: Arc<TrackLocalStaticRTP>,
-    pub decoded_rx: flume::Receiver<DecodedFrame>,
+    remote_audio: Arc<Mutex<RemoteAudio>>,
+    /// Reliable, ordered channel for chat; never touches signaling.
+    chat_channel: Arc<RTCDataChannel>,
+    /// Reliable, ordered channel for file transfers, separate from chat so a
+    /// large file never holds up messages.
+    file_channel: Arc<RTCDataChannel>,
+    /// Woken when the file channel's send buffer drains below the low-water mark.
+    file_buffer_low: Arc<Notify>,
+    /// Byte counters at the previous stats sample, for bitrate. Only the
+    /// periodic stats loop advances it, so on-demand reads don't cut its window.
+    stats_sample: Mutex<Option<StatsSample>>,
     rtp_seq: AtomicU16,
-    rtp_ts: AtomicU32,
+    /// Random offset from the capture clock to this stream's RTP timestamps.
+    rtp_ts_offset: u32,
     rtp_ssrc: u32,
+    /// End-to-end encryption of Opus payloads, when the call code has a key.
+    sealer: Option<FrameSealer>,
+    /// The peer's SDP said it accepts stereo Opus.
+    remote_stereo: AtomicBool,
 }
 
 impl PeerConn {
     pub async fn new(
         peer_id: String,
-        on_ice_candidate: flume::Sender<(String, SignalPayload)>,
         turn_servers: &[TurnServerInfo],
-        conn_state_tx: flume::Sender<(String, RTCPeerConnectionState)>,
+        events: PeerEvents,
+        cipher: Option<FrameCipher>,
     ) -> Result<Self> {
-        // Set up media engine with Opus
+        let PeerEvents {
+            ice_candidate: on_ice_candidate,
+            conn_state: conn_state_tx,
+            link_report: link_report_tx,
+            chat: chat_tx,
+            file: file_tx,
+        } = events;
+
+        // Set up media engine with Opus, offering stereo for music mode
         let mut media_engine = MediaEngine::default();
-        media_engine.register_default_codecs()?;
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: opus_capability(),
+                payload_type: 111,
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        )?;
 
         // Interceptors for RTCP etc.
         let mut registry = Registry::new();
@@ -79,23 +308,133 @@ impl PeerConn {
 
         // Create local audio track
         let audio_track = Arc::new(TrackLocalStaticRTP::new(
-            RTCRtpCodecCapability {
-                mime_type: MIME_TYPE_OPUS.to_string(),
-                clock_rate: SAMPLE_RATE,
-                channels: CHANNELS,
-                ..Default::default()
-            },
+            opus_capability(),
             "audio".to_string(),
             "entavi-audio".to_string(),
         ));
 
         // Add the track to the peer connection
-        connection
+        let rtp_sender = connection
             .add_track(Arc::clone(&audio_track) as Arc<dyn TrackLocal + Send + Sync>)
             .await?;
 
-        // Channel for decoded audio from this remote peer
-        let (decoded_tx, decoded_rx) = flume::bounded::<DecodedFrame>(AUDIO_DECODE_QUEUE_FRAMES);
+        // Read RTCP for our track. This keeps the interceptors (NACK, reports)
+        // running and forwards reception reports to the bitrate controller.
+        let report_pid = peer_id.clone();
+        tokio::spawn(async move {
+            while let Ok((packets, _)) = rtp_sender.read_rtcp().await {
+                for packet in packets {
+                    let any = packet.as_any();
+                    let reports = if let Some(rr) = any.downcast_ref::<ReceiverReport>() {
+                        &rr.reports
+                    } else if let Some(sr) = any.downcast_ref::<SenderReport>() {
+                        &sr.reports
+                    } else {
+                        continue;
+                    };
+                    for report in reports {
+                        let link = LinkReport::from_rtcp(
+                            report.fraction_lost,
+                            report.last_sender_report,
+                            report.delay,
+                        );
+                        let _ = link_report_tx.try_send((report_pid.clone(), link));
+                    }
+                }
+            }
+        });
+
+        // Chat channel, created identically on both sides (negotiated ID)
+        let chat_channel = connection
+            .create_data_channel(
+                CHAT_CHANNEL_LABEL,
+                Some(RTCDataChannelInit {
+                    ordered: Some(true),
+                    negotiated: Some(CHAT_CHANNEL_ID),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        let chat_pid = peer_id.clone();
+        chat_channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let chat_tx = chat_tx.clone();
+            let peer_id = chat_pid.clone();
+            Box::pin(async move {
+                match ChatPayload::decode(&msg.data) {
+                    Ok(payload) => {
+                        let _ = chat_tx.send_async((peer_id, payload)).await;
+                    }
+                    Err(e) => tracing::warn!("Dropping chat payload from {peer_id}: {e}"),
+                }
+            })
+        }));
+
+        // File transfer channel: JSON control messages plus binary chunks
+        let file_channel = connection
+            .create_data_channel(
+                FILE_CHANNEL_LABEL,
+                Some(RTCDataChannelInit {
+                    ordered: Some(true),
+                    negotiated: Some(FILE_CHANNEL_ID),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        let file_buffer_low = Arc::new(Notify::new());
+        file_channel
+            .set_buffered_amount_low_threshold(FILE_BUFFER_LOW_WATER)
+            .await;
+        let buffer_low = Arc::clone(&file_buffer_low);
+        file_channel
+            .on_buffered_amount_low(Box::new(move || {
+                buffer_low.notify_waiters();
+                Box::pin(async {})
+            }))
+            .await;
+        let open_tx = file_tx.clone();
+        let open_pid = peer_id.clone();
+        file_channel.on_open(Box::new(move || {
+            Box::pin(async move {
+                let _ = open_tx
+                    .send_async((open_pid, FileChannelEvent::Resume))
+                    .await;
+            })
+        }));
+        let resume_tx = file_tx.clone();
+        let file_pid = peer_id.clone();
+        file_channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let file_tx = file_tx.clone();
+            let peer_id = file_pid.clone();
+            Box::pin(async move {
+                let event = if msg.is_string {
+                    match FilePayload::decode(&msg.data) {
+                        Ok(payload) => FileChannelEvent::Control(payload),
+                        Err(e) => {
+                            tracing::warn!("Dropping file payload from {peer_id}: {e}");
+                            return;
+                        }
+                    }
+                } else {
+                    FileChannelEvent::Chunk(msg.data)
+                };
+                let _ = file_tx.send_async((peer_id, event)).await;
+            })
+        }));
+
+        // Jitter buffer + decoder for audio from this remote peer
+        let decoder = opus::Decoder::new(SAMPLE_RATE, opus::Channels::Stereo)
+            .map_err(|e| anyhow::anyhow!("Failed to create opus decoder: {e}"))?;
+        let remote_audio = Arc::new(Mutex::new(RemoteAudio {
+            jitter: JitterBuffer::new(),
+            decoder,
+            mono: [0.0; FRAME_SIZE],
+            concealed_run: None,
+            speech: RemoteSpeech::spawn(),
+            comfort_noise: ComfortNoise::new(),
+            key_check: KeyCheck::new(cipher.is_some()),
+            fec_recovered: 0,
+            concealed_frames: 0,
+        }));
 
         // ICE candidate callback
         let pid = peer_id.clone();
@@ -124,9 +463,11 @@ impl PeerConn {
             })
         }));
 
-        // Connection state changes - log and notify engine
+        // Connection state changes - log and notify engine. Coming back after
+        // an ICE restart also resumes interrupted downloads.
         let pid_log = peer_id.clone();
         let conn_state_tx_clone = conn_state_tx.clone();
+        let reconnect = ReconnectWatch::default();
         connection.on_peer_connection_state_change(Box::new(move |state| {
             tracing::info!("Peer {pid_log} connection state: {state}");
             let tx = conn_state_tx_clone.clone();
@@ -136,59 +477,68 @@ impl PeerConn {
                 || state == RTCPeerConnectionState::Closed
             {
                 tracing::warn!("Peer {pid_log} connection lost");
+                reconnect.dropped();
             }
+            let resume = (state == RTCPeerConnectionState::Connected && reconnect.connected())
+                .then(|| resume_tx.clone());
             Box::pin(async move {
+                if let Some(resume_tx) = resume {
+                    let _ = resume_tx
+                        .send_async((peer_id.clone(), FileChannelEvent::Resume))
+                        .await;
+                }
                 let _ = tx.send_async((peer_id, state)).await;
             })
         }));
 
-        // On incoming track: decode opus → send decoded PCM to engine
+        // On incoming track: queue RTP packets in the jitter buffer for the mixer
         let remote_pid = peer_id.clone();
+        let track_audio = Arc::clone(&remote_audio);
+        let track_cipher = cipher.clone();
         connection.on_track(Box::new(move |track, _receiver, _transceiver| {
-            let decoded_tx = decoded_tx.clone();
+            let remote_audio = Arc::clone(&track_audio);
             let remote_pid = remote_pid.clone();
+            let cipher = track_cipher.clone();
 
             Box::pin(async move {
                 tracing::info!("Received remote audio track from {remote_pid}");
 
-                // Spawn a task to read RTP packets and decode opus
+                // Spawn a task to read RTP packets into the jitter buffer
                 tokio::spawn(async move {
-                    let mut decoder = match opus::Decoder::new(SAMPLE_RATE, opus::Channels::Mono) {
-                        Ok(d) => d,
-                        Err(e) => {
-                            tracing::error!("Failed to create opus decoder: {e}");
-                            return;
-                        }
-                    };
-
-                    let mut pcm_buf: PcmFrame = [0.0; FRAME_SIZE];
-
                     loop {
                         match track.read_rtp().await {
                             Ok((rtp_packet, _)) => {
-                                let payload = &rtp_packet.payload;
-                                if payload.is_empty() {
+                                if rtp_packet.payload.is_empty() {
                                     continue;
                                 }
-
-                                match decoder.decode_float(payload, &mut pcm_buf, false) {
-                                    Ok(samples) => {
-                                        let len = samples.min(FRAME_SIZE);
-                                        let frame = DecodedFrame {
-                                            samples: pcm_buf,
-                                            len,
-                                        };
-                                        match decoded_tx.try_send(frame) {
-                                            Ok(()) | Err(flume::TrySendError::Full(_)) => {}
-                                            Err(flume::TrySendError::Disconnected(_)) => {
-                                                break; // engine dropped
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        tracing::warn!("Opus decode error: {e}");
-                                    }
+                                let header = &rtp_packet.header;
+                                // Undecryptable frames are dropped and play as loss
+                                let payload = match &cipher {
+                                    Some(cipher) => cipher
+                                        .decrypt(
+                                            header.timestamp,
+                                            header.sequence_number,
+                                            &rtp_packet.payload,
+                                        )
+                                        .ok(),
+                                    None => Some(rtp_packet.payload.clone()),
+                                };
+                                let Ok(mut audio) = remote_audio.lock() else {
+                                    break;
+                                };
+                                if cipher.is_some() {
+                                    audio.key_check.record(payload.is_some());
                                 }
+                                let Some(payload) = payload else {
+                                    continue;
+                                };
+                                audio.jitter.push(
+                                    header.sequence_number,
+                                    header.timestamp,
+                                    header.marker,
+                                    payload,
+                                    Instant::now(),
+                                );
                             }
                             Err(e) => {
                                 tracing::warn!("RTP read error for {remote_pid}: {e}");
@@ -206,10 +556,16 @@ impl PeerConn {
             peer_id,
             connection,
             audio_track,
-            decoded_rx,
+            remote_audio,
+            chat_channel,
+            file_channel,
+            file_buffer_low,
+            stats_sample: Mutex::new(None),
             rtp_seq: AtomicU16::new(0),
-            rtp_ts: AtomicU32::new(0),
+            rtp_ts_offset: rand::random(),
             rtp_ssrc,
+            sealer: cipher.map(FrameSealer::new),
+            remote_stereo: AtomicBool::new(false),
         })
     }
 
@@ -231,10 +587,55 @@ impl PeerConn {
         Ok(offer.sdp)
     }
 
+    /// Send a chat payload to this peer. Fails if the channel isn't open yet.
+    pub async fn send_chat(&self, payload: &ChatPayload) -> Result<()> {
+        anyhow::ensure!(
+            self.chat_channel.ready_state() == RTCDataChannelState::Open,
+            "Chat channel to {} is not open",
+            self.peer_id
+        );
+        self.chat_channel.send_text(payload.encode()).await?;
+        Ok(())
+    }
+
+    /// Send a file transfer control message to this peer.
+    pub async fn send_file_control(&self, payload: &FilePayload) -> Result<()> {
+        self.ensure_file_channel_open()?;
+        self.file_channel.send_text(payload.encode()).await?;
+        Ok(())
+    }
+
+    /// Queue one framed file chunk, waiting while the channel's send buffer
+    /// is above the high-water mark.
+    pub async fn send_file_chunk(&self, chunk: &Bytes) -> Result<()> {
+        while self.file_channel.buffered_amount().await > FILE_BUFFER_HIGH_WATER {
+            self.ensure_file_channel_open()?;
+            // The low-water notification can fire before we start waiting,
+            // so recheck the buffer periodically as well.
+            let _ =
+                tokio::time::timeout(Duration::from_millis(100), self.file_buffer_low.notified())
+                    .await;
+        }
+        self.ensure_file_channel_open()?;
+        self.file_channel.send(chunk).await?;
+        Ok(())
+    }
+
+    fn ensure_file_channel_open(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.file_channel.ready_state() == RTCDataChannelState::Open,
+            "File channel to {} is not open",
+            self.peer_id
+        );
+        Ok(())
+    }
+
     /// Handle a remote SDP offer and return our answer
     pub async fn handle_offer(&self, sdp: &str) -> Result<String> {
         let offer = RTCSessionDescription::offer(sdp.to_string())?;
         self.connection.set_remote_description(offer).await?;
+        self.remote_stereo
+            .store(sdp_accepts_stereo(sdp), Ordering::Relaxed);
 
         let answer = self.connection.create_answer(None).await?;
         self.connection
@@ -247,6 +648,8 @@ impl PeerConn {
     pub async fn handle_answer(&self, sdp: &str) -> Result<()> {
         let answer = RTCSessionDescription::answer(sdp.to_string())?;
         self.connection.set_remote_description(answer).await?;
+        self.remote_stereo
+            .store(sdp_accepts_stereo(sdp), Ordering::Relaxed);
         Ok(())
     }
 
@@ -276,7 +679,11 @@ impl PeerConn {
         use webrtc::rtp::packet::Packet;
 
         let seq = self.rtp_seq.fetch_add(1, Ordering::Relaxed);
-        let ts = self.rtp_ts.fetch_add(FRAME_SIZE as u32, Ordering::Relaxed);
+        let ts = frame.timestamp.wrapping_add(self.rtp_ts_offset);
+        let payload = match &self.sealer {
+            Some(sealer) => sealer.seal(ts, seq, &frame.data)?,
+            None => frame.data.clone(),
+        };
 
         let packet = Packet {
             header: Header {
@@ -285,10 +692,10 @@ impl PeerConn {
                 sequence_number: seq,
                 timestamp: ts,
                 ssrc: self.rtp_ssrc,
-                marker: false,
+                marker: frame.talkspurt_start,
                 ..Default::default()
             },
-            payload: frame.data.clone(),
+            payload,
         };
         self.audio_track
             .write_rtp(&packet)
@@ -297,6 +704,161 @@ impl PeerConn {
         Ok(())
     }
 
+    /// Pull the next 20ms of decoded stereo audio from this peer's jitter buffer.
+    /// Returns the number of samples written per channel, or `None` if there
+    /// is nothing to play.
+    /// Gaps are filled from the next packet's in-band FEC when it is already
+    /// buffered, otherwise by Opus packet loss concealment.
+    pub fn pull_audio(&self, out: &mut StereoFrame) -> Option<usize> {
+        self.remote_audio.lock().ok()?.pull(out)
+    }
+
+    /// Media quality for this connection from WebRTC stats plus our jitter
+    /// buffer. With `advance_bitrate` the bitrates cover the time since the
+    /// previous advancing call, which starts a new window; without it they
+    /// repeat the last window's values.
+    pub async fn call_stats(&self, advance_bitrate: bool) -> PeerCallStats {
+        let report = self.connection.get_stats().await;
+        let jitter = self.jitter_stats();
+        let mut stats = PeerCallStats {
+            peer_id: self.peer_id.clone(),
+            jitter_ms: jitter.jitter_ms,
+            jitter_buffer_ms: jitter.depth_ms,
+            e2ee: self.e2ee_state(),
+            ..Default::default()
+        };
+
+        let mut selected_pair = None;
+        let mut candidate_types = HashMap::new();
+        let mut remote_rtt = None;
+        let (mut bytes_sent, mut bytes_received, mut packets_received) = (0u64, 0u64, 0u64);
+
+        for entry in report.reports.values() {
+            match entry {
+                StatsReportType::CandidatePair(pair)
+                    if pair.nominated && pair.state == CandidatePairState::Succeeded =>
+                {
+                    selected_pair = Some(pair);
+                }
+                StatsReportType::LocalCandidate(candidate)
+                | StatsReportType::RemoteCandidate(candidate) => {
+                    candidate_types.insert(candidate.id.as_str(), candidate.candidate_type);
+                }
+                StatsReportType::Codec(codec) if codec.mime_type.starts_with("audio/") => {
+                    stats.codec = Some(codec.mime_type.clone());
+                }
+                StatsReportType::InboundRTP(inbound) if inbound.kind == "audio" => {
+                    bytes_received += inbound.bytes_received;
+                    packets_received += inbound.packets_received;
+                }
+                StatsReportType::OutboundRTP(outbound) if outbound.kind == "audio" => {
+                    bytes_sent += outbound.bytes_sent;
+                }
+                StatsReportType::RemoteInboundRTP(remote) if remote.kind == "audio" => {
+                    stats.outbound_loss = Some(remote.fraction_lost);
+                    remote_rtt = remote.round_trip_time;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(pair) = selected_pair {
+            stats.local_candidate_type = candidate_types
+                .get(pair.local_candidate_id.as_str())
+                .map(ToString::to_string);
+            stats.remote_candidate_type = candidate_types
+                .get(pair.remote_candidate_id.as_str())
+                .map(ToString::to_string);
+            if pair.current_round_trip_time > 0.0 {
+                stats.rtt_ms = Some(pair.current_round_trip_time * 1000.0);
+            }
+        }
+        if stats.rtt_ms.is_none() {
+            stats.rtt_ms = remote_rtt.map(|rtt| rtt * 1000.0);
+        }
+
+        let expected = packets_received + jitter.lost_packets;
+        if expected > 0 {
+            stats.inbound_loss = jitter.lost_packets as f64 / expected as f64;
+        }
+
+        let Ok(mut sample) = self.stats_sample.lock() else {
+            return stats;
+        };
+        if advance_bitrate {
+            let now = Instant::now();
+            let mut next = StatsSample {
+                at: now,
+                bytes_sent,
+                bytes_received,
+                send_bitrate: None,
+                receive_bitrate: None,
+            };
+            if let Some(previous) = sample.as_ref() {
+                let secs = now.duration_since(previous.at).as_secs_f64();
+                if secs > 0.0 {
+                    let bitrate = |bytes: u64| (bytes as f64 * 8.0 / secs) as u32;
+                    next.send_bitrate =
+                        Some(bitrate(bytes_sent.saturating_sub(previous.bytes_sent)));
+                    next.receive_bitrate = Some(bitrate(
+                        bytes_received.saturating_sub(previous.bytes_received),
+                    ));
+                }
+            }
+            *sample = Some(next);
+        }
+        if let Some(sample) = sample.as_ref() {
+            stats.send_bitrate = sample.send_bitrate;
+            stats.receive_bitrate = sample.receive_bitrate;
+        }
+
+        stats
+    }
+
+    /// Short authentication string over the DTLS fingerprints of this
+    /// connection: ours, and the one the peer's SDP told DTLS to expect.
+    /// `None` until both descriptions are in place.
+    pub async fn short_auth_string(&self) -> Option<Vec<SasSymbol>> {
+        let local = self.connection.local_description().await?;
+        let remote = self.connection.remote_description().await?;
+        let local = sas::sdp_fingerprint(&local.sdp)?;
+        let remote = sas::sdp_fingerprint(&remote.sdp)?;
+        Some(sas::short_auth_string(&local, &remote))
+    }
+
+    /// Whether the peer negotiated stereo Opus, so music mode can send it.
+    pub fn accepts_stereo(&self) -> bool {
+        self.remote_stereo.load(Ordering::Relaxed)
+    }
+
+    pub fn e2ee_state(&self) -> E2eeState {
+        self.remote_audio
+            .lock()
+            .map(|audio| audio.key_check.state())
+            .unwrap_or_default()
+    }
+
+    /// Whether this peer is currently talking, per the VAD fed by `pull_audio`.
+    pub fn is_speaking(&self) -> bool {
+        self.remote_audio
+            .lock()
+            .map(|audio| audio.speech.is_speaking())
+            .unwrap_or(false)
+    }
+
+    /// Snapshot of this peer's jitter buffer (depth, target delay, measured jitter)
+    /// plus how many frames were recovered by FEC or concealed.
+    pub fn jitter_stats(&self) -> JitterStats {
+        self.remote_audio
+            .lock()
+            .map(|audio| JitterStats {
+                fec_recovered: audio.fec_recovered,
+                concealed_frames: audio.concealed_frames,
+                ..audio.jitter.stats()
+            })
+            .unwrap_or_default()
+    }
+
     pub async fn close(&self) {
         let _ = self.connection.close().await;
     }
@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::{Context, Result};
+
+use crate::file_transfer::sanitize_file_name;
+use crate::types::{
+    PcmFrame, RecordingFormat, RecordingLayout, FRAME_SIZE, RECORDING_QUEUE_FRAMES, SAMPLE_RATE,
+};
+
+/// Make everything written so far playable about once a second, so a crash
+/// loses at most the last second of a recording.
+const CHECKPOINT_FRAMES: u32 = 50;
+/// Opus packets per Ogg page; a page is also written at every checkpoint.
+const OGG_PAGE_PACKETS: usize = 50;
+const OGG_MAX_SEGMENTS: usize = 255;
+const OPUS_RECORDING_BITRATE: i32 = 64_000;
+
+/// One mixer tick of call audio for the recorder.
+pub struct RecordingFrame {
+    /// Our own processed microphone signal (silence while muted).
+    pub local: PcmFrame,
+    /// Mixed remote audio, before sound cues.
+    pub remote: PcmFrame,
+    /// Each remote participant that produced audio this tick; only filled in
+    /// for per-participant recordings.
+    pub tracks: Vec<RecordedTrack>,
+}
+
+pub struct RecordedTrack {
+    pub peer_id: String,
+    pub name: String,
+    pub frame: PcmFrame,
+}
+
+/// A recording in progress. Encoding and disk writes happen on a dedicated
+/// thread; dropping the recording finalizes its files.
+pub struct Recording {
+    layout: RecordingLayout,
+    /// Each frame comes with the number of frames dropped just before it.
+    frame_tx: Option<flume::Sender<(u32, RecordingFrame)>>,
+    /// Frames dropped since the last one that made it into the queue.
+    dropped: AtomicU32,
+    thread: Option<std::thread::JoinHandle<Vec<PathBuf>>>,
+    pub dir: PathBuf,
+}
+
+impl Recording {
+    pub fn start(
+        dir: PathBuf,
+        format: RecordingFormat,
+        layout: RecordingLayout,
+        local_name: String,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut recorder = Recorder::new(dir.clone(), format, layout, local_name)?;
+        let (frame_tx, frame_rx) = flume::bounded(RECORDING_QUEUE_FRAMES);
+
+        let thread = std::thread::Builder::new()
+            .name("recording".into())
+            .spawn(move || {
+                while let Ok((dropped, frame)) = frame_rx.recv() {
+                    recorder.write_silence(dropped);
+                    recorder.write(frame);
+                }
+                recorder.finish()
+            })?;
+
+        Ok(Self {
+            layout,
+            frame_tx: Some(frame_tx),
+            dropped: AtomicU32::new(0),
+            thread: Some(thread),
+            dir,
+        })
+    }
+
+    pub fn per_participant(&self) -> bool {
+        self.layout == RecordingLayout::PerParticipant
+    }
+
+    /// Queue one tick of audio. Never blocks the mixer: if the disk falls
+    /// behind, the frame is dropped and recorded as silence on every track,
+    /// so the tracks keep lining up.
+    pub fn push(&self, frame: RecordingFrame) {
+        if let Some(tx) = &self.frame_tx {
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if tx.try_send((dropped, frame)).is_err() {
+                tracing::warn!("Recording queue full, dropping a frame");
+                self.dropped.fetch_add(dropped + 1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Stop recording and finalize the files. Returns the files written.
+    pub fn stop(mut self) -> Vec<PathBuf> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Vec<PathBuf> {
+        // Closing the queue ends the thread once it has written the backlog
+        self.frame_tx = None;
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Runs on the recording thread and owns the track writers.
+struct Recorder {
+    dir: PathBuf,
+    format: RecordingFormat,
+    layout: RecordingLayout,
+    /// Mixed file, or our own track in a per-participant recording.
+    main: Option<TrackWriter>,
+    /// Remote participants' tracks, by peer ID.
+    tracks: HashMap<String, TrackWriter>,
+    frames: u32,
+    mixed: PcmFrame,
+}
+
+impl Recorder {
+    fn new(
+        dir: PathBuf,
+        format: RecordingFormat,
+        layout: RecordingLayout,
+        local_name: String,
+    ) -> Result<Self> {
+        let main_name = match layout {
+            RecordingLayout::Mixed => "Call".to_string(),
+            RecordingLayout::PerParticipant => local_name,
+        };
+        let main = TrackWriter::create(&track_path(&dir, &main_name, format), format)?;
+        Ok(Self {
+            dir,
+            format,
+            layout,
+            main: Some(main),
+            tracks: HashMap::new(),
+            frames: 0,
+            mixed: [0.0; FRAME_SIZE],
+        })
+    }
+
+    fn write(&mut self, frame: RecordingFrame) {
+        match self.layout {
+            RecordingLayout::Mixed => {
+                for ((out, local), remote) in
+                    self.mixed.iter_mut().zip(&frame.local).zip(&frame.remote)
+                {
+                    *out = (local + remote).clamp(-1.0, 1.0);
+                }
+                let mixed = self.mixed;
+                self.write_main(&mixed);
+            }
+            RecordingLayout::PerParticipant => {
+                self.write_main(&frame.local);
+                self.write_tracks(frame.tracks);
+            }
+        }
+
+        self.frames += 1;
+        if self.frames.is_multiple_of(CHECKPOINT_FRAMES) {
+            self.checkpoint();
+        }
+    }
+
+    /// Fill in frames the mixer had to drop, on every track.
+    fn write_silence(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.write(RecordingFrame {
+                local: [0.0; FRAME_SIZE],
+                remote: [0.0; FRAME_SIZE],
+                tracks: Vec::new(),
+            });
+        }
+    }
+
+    fn write_main(&mut self, frame: &PcmFrame) {
+        if let Some(main) = self.main.as_mut() {
+            if let Err(e) = main.write(frame) {
+                tracing::error!("Recording write failed, stopping this track: {e}");
+                self.main = None;
+            }
+        }
+    }
+
+    fn write_tracks(&mut self, frames: Vec<RecordedTrack>) {
+        let silence = [0.0; FRAME_SIZE];
+        let mut frames: HashMap<String, RecordedTrack> = frames
+            .into_iter()
+            .map(|track| (track.peer_id.clone(), track))
+            .collect();
+
+        // Participants heard for the first time get a new track, padded with
+        // silence so every track lines up with the start of the recording
+        for (peer_id, track) in &frames {
+            if self.tracks.contains_key(peer_id) {
+                continue;
+            }
+            let path = track_path(
+                &self.dir,
+                &format!("{} {}", track.name, &peer_id[..peer_id.len().min(8)]),
+                self.format,
+            );
+            let created = TrackWriter::create(&path, self.format).and_then(|mut writer| {
+                for _ in 0..self.frames {
+                    writer.write(&silence)?;
+                }
+                Ok(writer)
+            });
+            match created {
+                Ok(writer) => {
+                    self.tracks.insert(peer_id.clone(), writer);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to start recording track for {}: {e}", track.name)
+                }
+            }
+        }
+
+        // Everyone else is silent this tick
+        self.tracks.retain(|peer_id, writer| {
+            let frame = frames.remove(peer_id).map(|t| t.frame);
+            match writer.write(frame.as_ref().unwrap_or(&silence)) {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("Recording write failed, stopping this track: {e}");
+                    false
+                }
+            }
+        });
+    }
+
+    fn checkpoint(&mut self) {
+        for writer in self.main.iter_mut().chain(self.tracks.values_mut()) {
+            if let Err(e) = writer.checkpoint() {
+                tracing::warn!("Recording checkpoint failed: {e}");
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for writer in self.main.into_iter().chain(self.tracks.into_values()) {
+            let path = writer.path().to_path_buf();
+            match writer.finish() {
+                Ok(()) => files.push(path),
+                Err(e) => tracing::error!("Failed to finalize {}: {e}", path.display()),
+            }
+        }
+        files
+    }
+}
+
+fn track_path(dir: &Path, name: &str, format: RecordingFormat) -> PathBuf {
+    let extension = match format {
+        RecordingFormat::Wav => "wav",
+        RecordingFormat::OggOpus => "opus",
+    };
+    dir.join(format!("{}.{extension}", sanitize_file_name(name)))
+}
+
+/// One audio file of a recording: 48kHz mono.
+enum TrackWriter {
+    Wav {
+        path: PathBuf,
+        writer: hound::WavWriter<BufWriter<File>>,
+    },
+    OggOpus(OggOpusWriter),
+}
+
+impl TrackWriter {
+    fn create(path: &Path, format: RecordingFormat) -> Result<Self> {
+        match format {
+            RecordingFormat::Wav => {
+                let spec = hound::WavSpec {
+                    channels: 1,
+                    sample_rate: SAMPLE_RATE,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                let writer = hound::WavWriter::create(path, spec)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                Ok(Self::Wav {
+                    path: path.to_path_buf(),
+                    writer,
+                })
+            }
+            RecordingFormat::OggOpus => Ok(Self::OggOpus(OggOpusWriter::create(path)?)),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Wav { path, .. } => path,
+            Self::OggOpus(writer) => &writer.path,
+        }
+    }
+
+    fn write(&mut self, frame: &PcmFrame) -> Result<()> {
+        match self {
+            Self::Wav { writer, .. } => {
+                for sample in frame {
+                    writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+                }
+                Ok(())
+            }
+            Self::OggOpus(writer) => writer.write(frame),
+        }
+    }
+
+    /// Bring the file on disk to a consistent, playable state.
+    fn checkpoint(&mut self) -> Result<()> {
+        match self {
+            // Rewrites the RIFF and data chunk sizes for what was written so far
+            Self::Wav { writer, .. } => Ok(writer.flush()?),
+            Self::OggOpus(writer) => writer.checkpoint(),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Wav { writer, .. } => Ok(writer.finalize()?),
+            Self::OggOpus(writer) => writer.finish(),
+        }
+    }
+}
+
+/// Minimal Ogg Opus (RFC 7845) writer for a single mono stream.
+pub(crate) struct OggOpusWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    encoder: opus::Encoder,
+    serial: u32,
+    page_seq: u32,
+    /// Samples (at 48kHz) in all packets written or pending.
+    granule: u64,
+    pending: Vec<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl OggOpusWriter {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut encoder =
+            opus::Encoder::new(SAMPLE_RATE, opus::Channels::Mono, opus::Application::Audio)
+                .map_err(|e| anyhow::anyhow!("Failed to create opus encoder: {e}"))?;
+        let _ = encoder.set_bitrate(opus::Bitrate::Bits(OPUS_RECORDING_BITRATE));
+        let pre_skip = encoder.get_lookahead().unwrap_or(312) as u16;
+
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            encoder,
+            serial: rand::random(),
+            page_seq: 0,
+            granule: 0,
+            pending: Vec::new(),
+            buf: vec![0u8; 4000],
+        };
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(1); // channels
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        writer.write_page(&[head], 0, OGG_BOS)?;
+
+        let vendor = b"entavi";
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+        writer.write_page(&[tags], 0, 0)?;
+        writer.file.flush()?;
+
+        Ok(writer)
+    }
+
+    pub(crate) fn write(&mut self, frame: &PcmFrame) -> Result<()> {
+        let len = self
+            .encoder
+            .encode_float(frame, &mut self.buf)
+            .map_err(|e| anyhow::anyhow!("Opus encode error: {e}"))?;
+        let segments: usize = self.pending.iter().map(|p| lacing_len(p.len())).sum();
+        if segments + lacing_len(len) > OGG_MAX_SEGMENTS {
+            self.flush_page(0)?;
+        }
+        self.pending.push(self.buf[..len].to_vec());
+        self.granule += FRAME_SIZE as u64;
+        if self.pending.len() >= OGG_PAGE_PACKETS {
+            self.flush_page(0)?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.flush_page(0)?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        // The last page carries the end-of-stream flag, even if it is empty
+        self.flush_page(OGG_EOS)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    fn flush_page(&mut self, flags: u8) -> Result<()> {
+        let packets = std::mem::take(&mut self.pending);
+        self.write_page(&packets, self.granule, flags)
+    }
+
+    fn write_page(&mut self, packets: &[Vec<u8>], granule: u64, flags: u8) -> Result<()> {
+        let page = ogg_page(packets, granule, self.serial, self.page_seq, flags);
+        self.page_seq += 1;
+        self.file.write_all(&page)?;
+        Ok(())
+    }
+}
+
+const OGG_BOS: u8 = 0x02;
+const OGG_EOS: u8 = 0x04;
+
+/// Lacing values a packet of `len` bytes takes in the segment table.
+fn lacing_len(len: usize) -> usize {
+    len / 255 + 1
+}
+
+/// Build one Ogg page holding whole packets.
+fn ogg_page(packets: &[Vec<u8>], granule: u64, serial: u32, seq: u32, flags: u8) -> Vec<u8> {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+    debug_assert!(lacing.len() <= OGG_MAX_SEGMENTS);
+
+    let mut page =
+        Vec::with_capacity(27 + lacing.len() + packets.iter().map(Vec::len).sum::<usize>());
+    page.extend_from_slice(b"OggS");
+    page.push(0); // version
+    page.push(flags);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&seq.to_le_bytes());
+    page.extend_from_slice(&[0; 4]); // CRC, filled in below
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// CRC-32 as used by Ogg: polynomial 0x04c11db7, no reflection, zero init.
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(tag: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("entavi-test-{tag}-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tone(i: usize) -> PcmFrame {
+        let mut frame = [0.0; FRAME_SIZE];
+        for (n, s) in frame.iter_mut().enumerate() {
+            *s = 0.3 * ((i * FRAME_SIZE + n) as f32 * 0.05).sin();
+        }
+        frame
+    }
+
+    /// Walk the pages of an Ogg file, checking each CRC. Returns the header
+    /// flags and granule position of every page.
+    fn read_pages(data: &[u8]) -> Vec<(u8, u64)> {
+        let mut pages = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            assert_eq!(&data[pos..pos + 4], b"OggS");
+            let segments = data[pos + 26] as usize;
+            let body: usize = data[pos + 27..pos + 27 + segments]
+                .iter()
+                .map(|&l| l as usize)
+                .sum();
+            let len = 27 + segments + body;
+            let mut page = data[pos..pos + len].to_vec();
+            let crc = u32::from_le_bytes(page[22..26].try_into().unwrap());
+            page[22..26].fill(0);
+            assert_eq!(ogg_crc(&page), crc);
+            pages.push((
+                data[pos + 5],
+                u64::from_le_bytes(data[pos + 6..pos + 14].try_into().unwrap()),
+            ));
+            pos += len;
+        }
+        pages
+    }
+
+    #[test]
+    fn dropped_frames_keep_tracks_aligned() {
+        let dir = temp_dir("gap");
+        let mut recorder = Recorder::new(
+            dir.clone(),
+            RecordingFormat::Wav,
+            RecordingLayout::PerParticipant,
+            "Me".to_string(),
+        )
+        .unwrap();
+        let frame = |i| RecordingFrame {
+            local: tone(i),
+            remote: tone(i),
+            tracks: vec![RecordedTrack {
+                peer_id: "peer-1234".to_string(),
+                name: "Ana".to_string(),
+                frame: tone(i),
+            }],
+        };
+
+        recorder.write(frame(0));
+        recorder.write_silence(3);
+        recorder.write(frame(4));
+        let files = recorder.finish();
+
+        assert_eq!(files.len(), 2);
+        for file in files {
+            let reader = hound::WavReader::open(&file).unwrap();
+            assert_eq!(reader.len(), 5 * FRAME_SIZE as u32, "{}", file.display());
+            let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+            let gap = &samples[FRAME_SIZE..4 * FRAME_SIZE];
+            assert!(gap.iter().all(|&s| s == 0));
+            assert!(samples[4 * FRAME_SIZE..].iter().any(|&s| s != 0));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wav_checkpoint_survives_a_crash() {
+        let dir = temp_dir("wav");
+        let path = dir.join("crash.wav");
+        let mut writer = TrackWriter::create(&path, RecordingFormat::Wav).unwrap();
+        for i in 0..CHECKPOINT_FRAMES as usize {
+            writer.write(&tone(i)).unwrap();
+        }
+        writer.checkpoint().unwrap();
+        // Simulate the process dying without finalizing
+        std::mem::forget(writer);
+
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        assert_eq!(reader.len(), CHECKPOINT_FRAMES * FRAME_SIZE as u32);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ogg_opus_file_has_valid_pages_and_duration() {
+        let dir = temp_dir("ogg");
+        let path = dir.join("track.opus");
+        let mut writer = OggOpusWriter::create(&path).unwrap();
+        let frames = 120;
+        for i in 0..frames {
+            writer.write(&tone(i)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[28..36], b"OpusHead");
+        let pages = read_pages(&data);
+        assert_eq!(pages.first().unwrap().0, OGG_BOS);
+        let (flags, granule) = *pages.last().unwrap();
+        assert_eq!(flags, OGG_EOS);
+        assert_eq!(granule, (frames * FRAME_SIZE) as u64);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn per_participant_tracks_line_up_with_late_joiners() {
+        let dir = temp_dir("tracks");
+        let recording = Recording::start(
+            dir.clone(),
+            RecordingFormat::Wav,
+            RecordingLayout::PerParticipant,
+            "Me".to_string(),
+        )
+        .unwrap();
+        for i in 0..20 {
+            let mut tracks = Vec::new();
+            if i >= 10 {
+                tracks.push(RecordedTrack {
+                    peer_id: "0123456789abcdef".to_string(),
+                    name: "Alex".to_string(),
+                    frame: tone(i),
+                });
+            }
+            recording.push(RecordingFrame {
+                local: tone(i),
+                remote: [0.0; FRAME_SIZE],
+                tracks,
+            });
+        }
+
+        let mut files = recording.stop();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        for file in &files {
+            let reader = hound::WavReader::open(file).unwrap();
+            assert_eq!(reader.len(), 20 * FRAME_SIZE as u32);
+        }
+        assert!(files[0].ends_with("Alex 01234567.wav"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,570 @@
+use crate::types::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+
+/// Zero crossings of the sinc on each side of the filter centre. More gives
+/// a steeper roll-off between the passband and the new Nyquist frequency.
+const ZERO_CROSSINGS: usize = 32;
+/// Passband edge as a fraction of the lower Nyquist frequency; the
+/// transition band sits between it and Nyquist.
+const CUTOFF: f64 = 0.92;
+/// Kaiser window shape, for roughly 90 dB of stopband attenuation.
+const KAISER_BETA: f64 = 8.6;
+/// Filter phases tabulated per input sample. Positions in between are
+/// interpolated linearly between neighbouring phases.
+const PHASES: usize = 256;
+
+/// Band-limited sample rate converter for mono audio: a windowed-sinc
+/// polyphase filter that keeps its input history between calls, so a stream
+/// can be fed in chunks of any size without clicks at the boundaries.
+pub struct Resampler {
+    /// Input samples advanced per output sample.
+    step: f64,
+    /// `step` at the nominal rates, before any drift correction.
+    base_step: f64,
+    /// Filter taps on each side of the output position.
+    half_taps: usize,
+    /// `PHASES + 1` rows of `2 * half_taps` coefficients.
+    table: Vec<f32>,
+    /// Input not yet consumed, starting with the history the next output needs.
+    buf: Vec<f32>,
+    /// Position of the next output sample in `buf`.
+    pos: f64,
+    passthrough: bool,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let input_rate = input_rate.max(1);
+        let output_rate = output_rate.max(1);
+        let step = input_rate as f64 / output_rate as f64;
+        // Relative to the input Nyquist; downsampling must also remove
+        // everything above the output Nyquist
+        let cutoff = CUTOFF * (output_rate as f64 / input_rate as f64).min(1.0);
+        let half_taps = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+        let taps = 2 * half_taps;
+
+        let mut table = vec![0.0f32; (PHASES + 1) * taps];
+        for (phase, row) in table.chunks_exact_mut(taps).enumerate() {
+            let frac = phase as f64 / PHASES as f64;
+            let coeffs: Vec<f64> = (0..taps)
+                .map(|j| {
+                    // Distance from the output position to this tap's input sample
+                    let t = j as f64 + 1.0 - half_taps as f64 - frac;
+                    cutoff * sinc(cutoff * t) * kaiser(t / half_taps as f64)
+                })
+                .collect();
+            // Unity gain at DC for every phase
+            let sum: f64 = coeffs.iter().sum();
+            for (c, coeff) in row.iter_mut().zip(&coeffs) {
+                *c = (coeff / sum) as f32;
+            }
+        }
+
+        Self {
+            step,
+            base_step: step,
+            half_taps,
+            table,
+            buf: vec![0.0; half_taps - 1],
+            pos: (half_taps - 1) as f64,
+            passthrough: input_rate == output_rate,
+        }
+    }
+
+    /// Stretch the conversion ratio by `correction`: positive values consume
+    /// input faster (1e-4 is 100ppm), producing slightly less output. Used to
+    /// follow a clock that runs off its nominal rate.
+    pub fn set_drift(&mut self, correction: f64) {
+        if correction != 0.0 {
+            self.passthrough = false;
+        }
+        self.step = self.base_step * (1.0 + correction);
+    }
+
+    /// Resample `input`, appending to `output` every sample that can be
+    /// produced so far. The rest waits for the next call.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.passthrough {
+            output.extend_from_slice(input);
+            // Keep the history resampling would start from, so turning on
+            // drift correction later doesn't jump in the signal
+            self.buf.extend_from_slice(input);
+            let excess = self.buf.len() - (self.half_taps - 1);
+            self.buf.drain(..excess);
+            return;
+        }
+
+        self.buf.extend_from_slice(input);
+        let taps = 2 * self.half_taps;
+        loop {
+            let base = self.pos.floor();
+            let i = base as usize;
+            if i + self.half_taps >= self.buf.len() {
+                break;
+            }
+
+            let phase = (self.pos - base) * PHASES as f64;
+            let row = (phase as usize).min(PHASES - 1);
+            let mix = (phase - row as f64) as f32;
+            let a = &self.table[row * taps..(row + 1) * taps];
+            let b = &self.table[(row + 1) * taps..(row + 2) * taps];
+            let window = &self.buf[i + 1 - self.half_taps..=i + self.half_taps];
+
+            let (mut acc_a, mut acc_b) = (0.0f32, 0.0f32);
+            for ((x, ca), cb) in window.iter().zip(a).zip(b) {
+                acc_a += x * ca;
+                acc_b += x * cb;
+            }
+            output.push(acc_a + (acc_b - acc_a) * mix);
+            self.pos += self.step;
+        }
+
+        // Keep only the history the next output sample reaches back to
+        let consumed = (self.pos.floor() as usize + 1)
+            .saturating_sub(self.half_taps)
+            .min(self.buf.len());
+        self.buf.drain(..consumed);
+        self.pos -= consumed as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+/// Kaiser window at `x` in [-1, 1].
+fn kaiser(x: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(KAISER_BETA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_BETA)
+}
+
+/// Modified Bessel function of the first kind, order 0 (power series).
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_sq = x * x / 4.0;
+    for k in 1..50 {
+        term *= half_sq / (k * k) as f64;
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Turns interleaved device audio at any rate into mono or stereo 48kHz
+/// frames of exactly `FRAME_SIZE` samples per channel, however the device
+/// chunks its callbacks.
+pub struct InputConverter {
+    channels: usize,
+    /// One per output channel.
+    resamplers: Vec<Resampler>,
+    /// One output channel's device samples, before resampling.
+    split: Vec<f32>,
+    resampled: Vec<Vec<f32>>,
+    /// Resampled interleaved audio not yet handed out as a frame.
+    pending: Vec<f32>,
+}
+
+impl InputConverter {
+    /// `output_channels` is 1 for a mono mix of every device channel, or 2
+    /// for the device's first two channels as left and right.
+    pub fn new(device_rate: u32, device_channels: u16, output_channels: u16) -> Self {
+        let outputs = usize::from(output_channels.clamp(1, CHANNELS));
+        Self {
+            channels: usize::from(device_channels.max(1)),
+            resamplers: (0..outputs)
+                .map(|_| Resampler::new(device_rate, SAMPLE_RATE))
+                .collect(),
+            split: Vec::new(),
+            resampled: vec![Vec::new(); outputs],
+            pending: Vec::with_capacity(2 * FRAME_SIZE * outputs),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// 1 for mono frames, 2 for stereo.
+    pub fn output_channels(&self) -> usize {
+        self.resamplers.len()
+    }
+
+    /// Add device samples. Should hold whole interleaved frames; a trailing
+    /// partial frame is ignored.
+    pub fn push(&mut self, interleaved: &[f32]) {
+        let channels = self.channels;
+        let outputs = self.resamplers.len();
+        for (out, (resampler, resampled)) in self
+            .resamplers
+            .iter_mut()
+            .zip(&mut self.resampled)
+            .enumerate()
+        {
+            self.split.clear();
+            let frames = interleaved.chunks_exact(channels);
+            if outputs == 1 {
+                self.split
+                    .extend(frames.map(|frame| frame.iter().sum::<f32>() / channels as f32));
+            } else {
+                // A mono device feeds both sides
+                let channel = out.min(channels - 1);
+                self.split.extend(frames.map(|frame| frame[channel]));
+            }
+            resampled.clear();
+            resampler.process(&self.split, resampled);
+        }
+
+        // Every channel was resampled from the same input, so they're equally long
+        for i in 0..self.resampled[0].len() {
+            self.pending.extend(self.resampled.iter().map(|r| r[i]));
+        }
+    }
+
+    /// Take the next 20ms frame, if enough audio has been pushed. `frame`
+    /// holds `FRAME_SIZE` samples per output channel.
+    pub fn pop_frame(&mut self, frame: &mut [f32]) -> bool {
+        let len = frame.len();
+        if self.pending.len() < len {
+            return false;
+        }
+        frame.copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        true
+    }
+
+    /// Resampled audio waiting to be handed out, in frames per channel.
+    pub fn pending_frames(&self) -> usize {
+        self.pending.len() / self.resamplers.len()
+    }
+
+    /// See [`Resampler::set_drift`].
+    pub fn set_drift(&mut self, correction: f64) {
+        for resampler in &mut self.resamplers {
+            resampler.set_drift(correction);
+        }
+    }
+}
+
+/// Turns mono or interleaved stereo 48kHz audio into interleaved samples at
+/// the device's rate and channel count, carrying resampler state from one
+/// frame to the next.
+pub struct OutputConverter {
+    channels: usize,
+    /// One per source channel.
+    resamplers: Vec<Resampler>,
+    split: Vec<f32>,
+    resampled: Vec<Vec<f32>>,
+}
+
+impl OutputConverter {
+    pub fn new(source_channels: u16, device_rate: u32, device_channels: u16) -> Self {
+        let sources = usize::from(source_channels.clamp(1, CHANNELS));
+        Self {
+            channels: usize::from(device_channels.max(1)),
+            resamplers: (0..sources)
+                .map(|_| Resampler::new(SAMPLE_RATE, device_rate))
+                .collect(),
+            split: Vec::with_capacity(FRAME_SIZE),
+            resampled: vec![Vec::with_capacity(2 * FRAME_SIZE); sources],
+        }
+    }
+
+    /// Convert `input`, replacing the contents of `output`. Stereo goes to
+    /// the first two device channels and a mono mix to any others; a mono
+    /// device gets the mix.
+    pub fn convert(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let sources = self.resamplers.len();
+        for (source, (resampler, resampled)) in self
+            .resamplers
+            .iter_mut()
+            .zip(&mut self.resampled)
+            .enumerate()
+        {
+            self.split.clear();
+            self.split
+                .extend(input.iter().skip(source).step_by(sources).copied());
+            resampled.clear();
+            resampler.process(&self.split, resampled);
+        }
+
+        output.clear();
+        let left = &self.resampled[0];
+        let right = &self.resampled[sources - 1];
+        for (&l, &r) in left.iter().zip(right) {
+            let mid = (l + r) * 0.5;
+            if self.channels == 1 {
+                output.push(mid);
+            } else {
+                output.extend([l, r]);
+                output.extend(std::iter::repeat_n(mid, self.channels - 2));
+            }
+        }
+    }
+
+    /// See [`Resampler::set_drift`].
+    pub fn set_drift(&mut self, correction: f64) {
+        for resampler in &mut self.resamplers {
+            resampler.set_drift(correction);
+        }
+    }
+}
+
+/// Average interleaved stereo into mono.
+pub fn downmix_stereo(stereo: &[f32], mono: &mut [f32]) {
+    for (sample, frame) in mono.iter_mut().zip(stereo.chunks_exact(2)) {
+        *sample = (frame[0] + frame[1]) * 0.5;
+    }
+}
+
+/// Copy mono into both channels of interleaved stereo.
+pub fn upmix_mono(mono: &[f32], stereo: &mut [f32]) {
+    for (frame, &sample) in stereo.chunks_exact_mut(2).zip(mono) {
+        frame.fill(sample);
+    }
+}
+
+/// Buffer level readings averaged to find the latency to hold: 2s of frames.
+const DRIFT_WARMUP_UPDATES: u32 = 100;
+/// Weight of each new reading in the smoothed buffer level.
+const DRIFT_SMOOTHING: f64 = 0.02;
+/// Ratio correction per second of buffered audio away from the target.
+const DRIFT_GAIN: f64 = 0.1;
+/// Largest ratio correction. 0.2% is well past real crystal drift and still
+/// far too small a pitch change to hear.
+const MAX_DRIFT_CORRECTION: f64 = 0.002;
+
+/// Estimates the drift between the clock writing into a buffer and the one
+/// reading from it (a sound card and the system clock, or two sound cards),
+/// from the buffer's fill level. Returns the resampling correction that
+/// holds the level, and so the latency, where it settled at the start.
+pub struct DriftController {
+    device_rate: f64,
+    warmup_updates: u32,
+    warmup_sum: f64,
+    /// Buffered seconds to hold, once warmed up.
+    target: Option<f64>,
+    smoothed: f64,
+}
+
+impl DriftController {
+    pub fn new(device_rate: u32) -> Self {
+        Self {
+            device_rate: f64::from(device_rate.max(1)),
+            warmup_updates: 0,
+            warmup_sum: 0.0,
+            target: None,
+            smoothed: 0.0,
+        }
+    }
+
+    /// Start over, e.g. after a gap in playback emptied the buffer.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.device_rate as u32);
+    }
+
+    /// Feed the buffer level, in frames at the controller's rate, after each
+    /// write. Returns the correction for the writer's converter
+    /// ([`OutputConverter::set_drift`] or [`InputConverter::set_drift`]).
+    pub fn update(&mut self, buffered_frames: usize) -> f64 {
+        let level = buffered_frames as f64 / self.device_rate;
+        let Some(target) = self.target else {
+            self.warmup_sum += level;
+            self.warmup_updates += 1;
+            if self.warmup_updates >= DRIFT_WARMUP_UPDATES {
+                let mean = self.warmup_sum / f64::from(self.warmup_updates);
+                self.target = Some(mean);
+                self.smoothed = mean;
+            }
+            return 0.0;
+        };
+
+        self.smoothed += (level - self.smoothed) * DRIFT_SMOOTHING;
+        ((self.smoothed - target) * DRIFT_GAIN).clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, freq: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| {
+                (0.5 * (2.0 * std::f64::consts::PI * freq * n as f64 / rate as f64).sin()) as f32
+            })
+            .collect()
+    }
+
+    /// Resample a sine fed in uneven chunks and measure the SNR against the
+    /// ideal sine at the output rate, past the start-up edge.
+    fn resampled_sine_snr_db(from: u32, to: u32, freq: f64) -> f64 {
+        let input = sine(from, freq, from as usize);
+        let mut resampler = Resampler::new(from, to);
+        let mut output = Vec::new();
+        for chunk in input.chunks(441).flat_map(|c| c.chunks(317)) {
+            resampler.process(chunk, &mut output);
+        }
+
+        let expected = sine(to, freq, output.len());
+        let skip = 200;
+        let signal: f64 = expected[skip..].iter().map(|&s| (s as f64).powi(2)).sum();
+        let noise: f64 = output[skip..]
+            .iter()
+            .zip(&expected[skip..])
+            .map(|(&y, &r)| (y as f64 - r as f64).powi(2))
+            .sum();
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn sine_survives_resampling_with_high_snr() {
+        for (from, to) in [
+            (44_100, 48_000),
+            (48_000, 44_100),
+            (16_000, 48_000),
+            (48_000, 96_000),
+        ] {
+            for freq in [1_000.0, 6_000.0] {
+                let snr = resampled_sine_snr_db(from, to, freq);
+                assert!(snr > 70.0, "{from} -> {to} at {freq}Hz: SNR {snr:.1} dB");
+            }
+        }
+    }
+
+    #[test]
+    fn downsampling_removes_content_above_the_new_nyquist() {
+        // 23kHz can't exist at 44.1kHz; without filtering it aliases to 21.1kHz
+        let input = sine(48_000, 23_000.0, 48_000);
+        let mut resampler = Resampler::new(48_000, 44_100);
+        let mut output = Vec::new();
+        resampler.process(&input, &mut output);
+
+        let rms = |s: &[f32]| {
+            (s.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / s.len() as f64).sqrt()
+        };
+        let attenuation_db = 20.0 * (rms(&output[200..]) / rms(&input)).log10();
+        assert!(attenuation_db < -60.0, "alias at {attenuation_db:.1} dB");
+    }
+
+    #[test]
+    fn input_converter_yields_exact_frames_from_any_chunking() {
+        // 11.025kHz doesn't divide into 20ms device chunks
+        let mut converter = InputConverter::new(11_025, 2, 1);
+        let mut frame = [0.0; FRAME_SIZE];
+        let mut frames = 0;
+        for _ in 0..100 {
+            converter.push(&[0.25; 2 * 37]);
+            while converter.pop_frame(&mut frame) {
+                frames += 1;
+            }
+        }
+
+        // 3700 input frames at 11.025kHz is 16108 samples at 48kHz, less
+        // the filter's lookahead
+        assert_eq!(frames, 16);
+        assert!(frame.iter().all(|&s| (s - 0.25).abs() < 1e-3));
+    }
+
+    #[test]
+    fn converters_pass_48k_through_and_map_channels() {
+        let mut input = InputConverter::new(SAMPLE_RATE, 2, 1);
+        let mut frame = [0.0; FRAME_SIZE];
+        let stereo: Vec<f32> = (0..FRAME_SIZE).flat_map(|_| [0.2, 0.6]).collect();
+        input.push(&stereo);
+        assert!(input.pop_frame(&mut frame));
+        assert!(frame.iter().all(|&s| (s - 0.4).abs() < f32::EPSILON));
+        assert!(!input.pop_frame(&mut frame));
+
+        // Stereo capture keeps the sides apart
+        let mut input = InputConverter::new(SAMPLE_RATE, 2, 2);
+        let mut stereo_frame = [0.0; 2 * FRAME_SIZE];
+        input.push(&stereo);
+        assert!(input.pop_frame(&mut stereo_frame));
+        assert_eq!(stereo_frame[..], stereo[..]);
+
+        let mut output = OutputConverter::new(1, SAMPLE_RATE, 2);
+        let mut out = Vec::with_capacity(16);
+        let capacity = out.capacity();
+        output.convert(&[0.25, -0.5], &mut out);
+        assert_eq!(out, vec![0.25, 0.25, -0.5, -0.5]);
+        output.convert(&[1.0], &mut out);
+        assert_eq!(out, vec![1.0, 1.0]);
+        assert_eq!(out.capacity(), capacity);
+
+        // Stereo to a 4-channel device: sides first, then the mix
+        let mut output = OutputConverter::new(2, SAMPLE_RATE, 4);
+        output.convert(&[0.25, 0.75], &mut out);
+        assert_eq!(out, vec![0.25, 0.75, 0.5, 0.5]);
+        let mut output = OutputConverter::new(2, SAMPLE_RATE, 1);
+        output.convert(&[0.25, 0.75], &mut out);
+        assert_eq!(out, vec![0.5]);
+    }
+
+    #[test]
+    fn drift_correction_changes_the_ratio_without_a_jump() {
+        let input = sine(SAMPLE_RATE, 1_000.0, SAMPLE_RATE as usize);
+        let (first, rest) = input.split_at(FRAME_SIZE);
+        let mut resampler = Resampler::new(SAMPLE_RATE, SAMPLE_RATE);
+        let mut output = Vec::new();
+        resampler.process(first, &mut output);
+        assert_eq!(output, first);
+
+        // Leaving passthrough continues the signal, just later
+        resampler.set_drift(0.001);
+        for chunk in rest.chunks(FRAME_SIZE) {
+            resampler.process(chunk, &mut output);
+        }
+        let max_step = output
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(max_step < 0.07, "discontinuity of {max_step}");
+
+        // 0.1% faster through the remaining input, less the filter's lookahead
+        let expected = rest.len() as f64 / 1.001 - 35.0;
+        let produced = (output.len() - FRAME_SIZE) as f64;
+        assert!(
+            (produced - expected).abs() < 2.0,
+            "{produced} vs {expected}"
+        );
+    }
+
+    /// Simulate a mixer writing 20ms frames on the system clock into a buffer
+    /// that a sound card drains `drift` faster than nominal.
+    fn buffered_frames_with_drift(drift: f64) -> (f64, Vec<f64>) {
+        let mut controller = DriftController::new(SAMPLE_RATE);
+        let mut correction = 0.0;
+        let mut level = 2.0 * FRAME_SIZE as f64;
+        let mut levels = Vec::new();
+        // Ten minutes
+        for _ in 0..30_000 {
+            level += FRAME_SIZE as f64 / (1.0 + correction);
+            correction = controller.update(level.round() as usize);
+            levels.push(level);
+            level -= FRAME_SIZE as f64 * (1.0 + drift);
+        }
+        let target = controller.target.unwrap() * f64::from(SAMPLE_RATE);
+        (target, levels)
+    }
+
+    #[test]
+    fn drift_controller_holds_the_buffer_level() {
+        for drift in [300e-6, -300e-6] {
+            let (target, levels) = buffered_frames_with_drift(drift);
+            assert!(levels.iter().all(|&l| l > 0.0), "buffer ran dry");
+            // Settled within 5ms of the latency it started with
+            for &level in &levels[levels.len() / 2..] {
+                assert!((level - target).abs() < 240.0, "{level} vs {target}");
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use sha2::{Digest, Sha256};
+
+use crate::types::SasSymbol;
+
+/// Symbols in a short authentication string: 7 × 6 bits = 42 bits.
+const SAS_LEN: usize = 7;
+const SAS_INFO: &[u8] = b"entavi sas v1";
+
+/// 64 symbols that are easy to tell apart and to name over a call.
+const SYMBOLS: [(&str, &str); 64] = [
+    ("🐶", "dog"),
+    ("🐱", "cat"),
+    ("🦁", "lion"),
+    ("🐎", "horse"),
+    ("🦄", "unicorn"),
+    ("🐷", "pig"),
+    ("🐘", "elephant"),
+    ("🐰", "rabbit"),
+    ("🐼", "panda"),
+    ("🐓", "rooster"),
+    ("🐧", "penguin"),
+    ("🐢", "turtle"),
+    ("🐟", "fish"),
+    ("🐙", "octopus"),
+    ("🦋", "butterfly"),
+    ("🌷", "flower"),
+    ("🌳", "tree"),
+    ("🌵", "cactus"),
+    ("🍄", "mushroom"),
+    ("🌏", "globe"),
+    ("🌙", "moon"),
+    ("☁️", "cloud"),
+    ("🔥", "fire"),
+    ("🍌", "banana"),
+    ("🍎", "apple"),
+    ("🍓", "strawberry"),
+    ("🌽", "corn"),
+    ("🍕", "pizza"),
+    ("🎂", "cake"),
+    ("❤️", "heart"),
+    ("😀", "smiley"),
+    ("🤖", "robot"),
+    ("🎩", "hat"),
+    ("👓", "glasses"),
+    ("🔧", "spanner"),
+    ("🎅", "santa"),
+    ("👍", "thumbs up"),
+    ("☂️", "umbrella"),
+    ("⌛", "hourglass"),
+    ("⏰", "clock"),
+    ("🎁", "gift"),
+    ("💡", "light bulb"),
+    ("📕", "book"),
+    ("✏️", "pencil"),
+    ("📎", "paperclip"),
+    ("✂️", "scissors"),
+    ("🔒", "lock"),
+    ("🔑", "key"),
+    ("🔨", "hammer"),
+    ("☎️", "telephone"),
+    ("🏁", "flag"),
+    ("🚂", "train"),
+    ("🚲", "bicycle"),
+    ("✈️", "aeroplane"),
+    ("🚀", "rocket"),
+    ("🏆", "trophy"),
+    ("⚽", "ball"),
+    ("🎸", "guitar"),
+    ("🎺", "trumpet"),
+    ("🔔", "bell"),
+    ("⚓", "anchor"),
+    ("🎧", "headphones"),
+    ("📁", "folder"),
+    ("📌", "pin"),
+];
+
+/// DTLS certificate fingerprint from an SDP, as `<hash> <hex>` with the hex
+/// upper-cased. Session-level and media-level attributes are both accepted.
+pub fn sdp_fingerprint(sdp: &str) -> Option<String> {
+    sdp.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("a=fingerprint:")?;
+        let (hash, hex) = value.split_once(' ')?;
+        Some(format!(
+            "{} {}",
+            hash.to_ascii_lowercase(),
+            hex.trim().to_ascii_uppercase()
+        ))
+    })
+}
+
+/// Short authentication string over both ends' DTLS fingerprints. The pair is
+/// sorted first so each side gets the same result from its own point of view.
+/// A relay that swaps fingerprints to sit in the middle ends up with a
+/// different pair on each side, and so different symbols.
+pub fn short_auth_string(local_fingerprint: &str, remote_fingerprint: &str) -> Vec<SasSymbol> {
+    let (first, second) = if local_fingerprint <= remote_fingerprint {
+        (local_fingerprint, remote_fingerprint)
+    } else {
+        (remote_fingerprint, local_fingerprint)
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(SAS_INFO);
+    hasher.update(first.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(second.as_bytes());
+    let digest = hasher.finalize();
+
+    let bits = u64::from_be_bytes(digest[..8].try_into().expect("8 byte slice"));
+    (0..SAS_LEN)
+        .map(|i| {
+            let index = (bits >> (58 - 6 * i)) & 0x3f;
+            let (emoji, name) = SYMBOLS[index as usize];
+            SasSymbol {
+                emoji: emoji.to_string(),
+                name: name.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FP_A: &str = "sha-256 3A:96:6D:57:B2:C2:C7:61:A0:46:3E:1C:97:39:D3:F7";
+    const FP_B: &str = "sha-256 0F:74:31:25:CB:A2:13:EC:28:6F:6D:2C:61:FF:5D:C2";
+    const FP_M: &str = "sha-256 AA:BB:CC:DD:EE:FF:00:11:22:33:44:55:66:77:88:99";
+
+    #[test]
+    fn parses_fingerprint_from_sdp() {
+        let sdp = "v=0\r\no=- 1 2 IN IP4 0.0.0.0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+                   a=fingerprint:SHA-256 3a:96:6d:57:b2:c2:c7:61:a0:46:3e:1c:97:39:d3:f7\r\n";
+
+        assert_eq!(sdp_fingerprint(sdp).as_deref(), Some(FP_A));
+        assert_eq!(sdp_fingerprint("v=0\r\n"), None);
+    }
+
+    #[test]
+    fn both_ends_agree_and_a_swapped_fingerprint_changes_the_string() {
+        let at_a = short_auth_string(FP_A, FP_B);
+        let at_b = short_auth_string(FP_B, FP_A);
+        assert_eq!(at_a.len(), SAS_LEN);
+        assert_eq!(at_a, at_b);
+
+        // A relay answering each side with its own certificate
+        let a_via_relay = short_auth_string(FP_A, FP_M);
+        let b_via_relay = short_auth_string(FP_B, FP_M);
+        assert_ne!(a_via_relay, b_via_relay);
+        assert_ne!(a_via_relay, at_a);
+    }
+}
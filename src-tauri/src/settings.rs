@@ -0,0 +1,373 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::bitrate::{BitrateBounds, DEFAULT_MAX_BITRATE, DEFAULT_MIN_BITRATE};
+use crate::types::DeviceConfigs;
+use crate::vad::{MAX_VAD_THRESHOLD, MIN_VAD_THRESHOLD};
+
+pub const SETTINGS_FILE: &str = "settings.json";
+/// Bump when the file layout changes, and add a step to `migrate`.
+pub const SETTINGS_VERSION: u32 = 3;
+const DEFAULT_VAD_THRESHOLD: f32 = 0.01;
+/// Loudest shared system audio can be mixed into the call.
+pub const MAX_SHARED_AUDIO_GAIN: f32 = 2.0;
+
+/// Preferences kept across launches, stored as JSON in the app config dir.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub input_device: Option<String>,
+    pub output_device: Option<String>,
+    /// Stream settings chosen per device, by device name.
+    pub input_configs: DeviceConfigs,
+    pub output_configs: DeviceConfigs,
+    /// Custom signaling server; `None` uses the public one.
+    pub signaling_url: Option<String>,
+    pub noise_suppression: bool,
+    pub echo_cancellation: bool,
+    pub vad_threshold: f32,
+    pub agc_enabled: bool,
+    pub min_bitrate: u32,
+    pub max_bitrate: u32,
+    /// Gain applied to shared system audio before it's mixed with the mic.
+    pub shared_audio_gain: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            input_device: None,
+            output_device: None,
+            input_configs: DeviceConfigs::new(),
+            output_configs: DeviceConfigs::new(),
+            signaling_url: None,
+            noise_suppression: true,
+            echo_cancellation: true,
+            vad_threshold: DEFAULT_VAD_THRESHOLD,
+            agc_enabled: true,
+            min_bitrate: DEFAULT_MIN_BITRATE,
+            max_bitrate: DEFAULT_MAX_BITRATE,
+            shared_audio_gain: 1.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings file. A missing file gives the defaults; a file
+    /// that can't be understood is an error, so it isn't silently replaced.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let value: Value = serde_json::from_slice(&data)
+            .with_context(|| format!("Malformed settings file {}", path.display()))?;
+        Self::from_value(value)
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        let value = migrate(value)?;
+        let settings: Self = serde_json::from_value(value).context("Invalid settings")?;
+        Ok(settings.validated())
+    }
+
+    /// Write the settings atomically: a crash mid-write leaves the previous
+    /// file in place rather than a truncated one. Blocking; the engine saves
+    /// through a [`SettingsWriter`].
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let dir = path
+            .parent()
+            .context("Settings path has no parent folder")?;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        // A temp file of its own, so another writer can't rename ours half done
+        let temp_path = path.with_extension(format!("json.{}.tmp", uuid::Uuid::new_v4().simple()));
+        let written = self.write_to(&temp_path).and_then(|()| {
+            std::fs::rename(&temp_path, path)
+                .with_context(|| format!("Failed to replace {}", path.display()))
+        });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        written
+    }
+
+    fn write_to(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        let mut file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Bring every field into its accepted range, falling back to the default
+    /// for values that can't be repaired.
+    pub fn validated(mut self) -> Self {
+        let defaults = Self::default();
+        self.version = SETTINGS_VERSION;
+        self.input_device = self.input_device.filter(|d| !d.trim().is_empty());
+        self.output_device = self.output_device.filter(|d| !d.trim().is_empty());
+        for configs in [&mut self.input_configs, &mut self.output_configs] {
+            configs.retain(|_, config| !config.is_default() && config.clone().validated().is_ok());
+        }
+        self.signaling_url = validate_signaling_url(self.signaling_url).unwrap_or(None);
+        self.vad_threshold = if self.vad_threshold.is_finite() {
+            self.vad_threshold
+                .clamp(MIN_VAD_THRESHOLD, MAX_VAD_THRESHOLD)
+        } else {
+            defaults.vad_threshold
+        };
+        self.shared_audio_gain = if self.shared_audio_gain.is_finite() {
+            self.shared_audio_gain.clamp(0.0, MAX_SHARED_AUDIO_GAIN)
+        } else {
+            defaults.shared_audio_gain
+        };
+        if BitrateBounds::new(self.min_bitrate, self.max_bitrate).is_err() {
+            self.min_bitrate = defaults.min_bitrate;
+            self.max_bitrate = defaults.max_bitrate;
+        }
+        self
+    }
+
+    pub fn bitrate_bounds(&self) -> BitrateBounds {
+        BitrateBounds::new(self.min_bitrate, self.max_bitrate).unwrap_or_default()
+    }
+}
+
+/// A custom signaling server has to be a WebSocket URL. Blank means none.
+pub fn validate_signaling_url(url: Option<String>) -> Result<Option<String>> {
+    let Some(url) = url.map(|url| url.trim().to_string()) else {
+        return Ok(None);
+    };
+    if url.is_empty() {
+        return Ok(None);
+    }
+    anyhow::ensure!(
+        url.starts_with("ws://") || url.starts_with("wss://"),
+        "Signaling server must be a ws:// or wss:// URL"
+    );
+    Ok(Some(url))
+}
+
+/// Saves settings on a thread of its own, one write at a time, so callers
+/// never wait on the disk. Saves requested while a write is in progress
+/// collapse into a single write of the newest settings. Dropping the writer
+/// finishes any pending save.
+pub struct SettingsWriter {
+    tx: Option<flume::Sender<Settings>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SettingsWriter {
+    pub fn spawn(path: PathBuf) -> Result<Self> {
+        let (tx, rx) = flume::unbounded::<Settings>();
+        let thread = std::thread::Builder::new()
+            .name("settings".into())
+            .spawn(move || {
+                while let Ok(mut settings) = rx.recv() {
+                    // Only the newest of a burst is worth writing
+                    while let Ok(newer) = rx.try_recv() {
+                        settings = newer;
+                    }
+                    if let Err(e) = settings.save(&path) {
+                        tracing::warn!("Failed to save settings: {e:#}");
+                    }
+                }
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn save(&self, settings: Settings) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(settings);
+        }
+    }
+}
+
+impl Drop for SettingsWriter {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Upgrade a settings file written by an older version, one step at a time.
+fn migrate(mut value: Value) -> Result<Value> {
+    let object = value
+        .as_object_mut()
+        .context("Settings file is not a JSON object")?;
+    // Files from before the version field are version 0
+    let mut version = object.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    anyhow::ensure!(
+        version <= SETTINGS_VERSION,
+        "Settings were saved by a newer version of Entavi"
+    );
+
+    while version < SETTINGS_VERSION {
+        match version {
+            // 0 → 1: only the version field was added
+            0 => {}
+            // 1 → 2: per-device stream configs, empty until chosen
+            1 => {}
+            // 2 → 3: shared audio gain, defaulting to unity
+            2 => {}
+            _ => unreachable!("no migration from settings version {version}"),
+        }
+        version += 1;
+    }
+    object.insert("version".to_string(), Value::from(version));
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DeviceConfig;
+
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("entavi-test-settings-{}", uuid::Uuid::new_v4()))
+            .join(SETTINGS_FILE)
+    }
+
+    #[test]
+    fn saves_and_loads_atomically() {
+        let path = temp_path();
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+
+        let mut output_configs = DeviceConfigs::new();
+        output_configs.insert(
+            "USB Headset".to_string(),
+            DeviceConfig {
+                sample_rate: Some(44_100),
+                channels: Some(2),
+                buffer_size: Some(256),
+            },
+        );
+        let settings = Settings {
+            input_device: Some("USB Mic".to_string()),
+            output_configs,
+            vad_threshold: 0.05,
+            agc_enabled: false,
+            ..Settings::default()
+        };
+        settings.save(&path).unwrap();
+
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+        let files: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, [SETTINGS_FILE]);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_saves_leave_the_newest_settings() {
+        let path = temp_path();
+        let writer = std::sync::Arc::new(SettingsWriter::spawn(path.clone()).unwrap());
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let writer = std::sync::Arc::clone(&writer);
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        writer.save(Settings {
+                            min_bitrate: 8_000 + t * 1_000 + i,
+                            ..Settings::default()
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        writer.save(Settings {
+            vad_threshold: 0.05,
+            ..Settings::default()
+        });
+        drop(std::sync::Arc::into_inner(writer));
+
+        let saved = Settings::load(&path).unwrap();
+        assert_eq!(saved.vad_threshold, 0.05);
+        let files = std::fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(files, 1, "temp files left behind");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn signaling_url_must_be_a_websocket() {
+        assert_eq!(
+            validate_signaling_url(Some(" wss://example.com/ws ".to_string())).unwrap(),
+            Some("wss://example.com/ws".to_string())
+        );
+        assert_eq!(validate_signaling_url(Some(String::new())).unwrap(), None);
+        assert_eq!(validate_signaling_url(None).unwrap(), None);
+        assert!(validate_signaling_url(Some("https://example.com".to_string())).is_err());
+    }
+
+    #[test]
+    fn migrates_unversioned_files_and_rejects_newer_ones() {
+        let old = serde_json::json!({"noise_suppression": false, "unknown_key": 1});
+        let settings = Settings::from_value(old).unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert!(!settings.noise_suppression);
+        assert!(settings.echo_cancellation);
+
+        let newer = serde_json::json!({"version": SETTINGS_VERSION + 1});
+        assert!(Settings::from_value(newer).is_err());
+        assert!(Settings::from_value(serde_json::json!([1, 2])).is_err());
+    }
+
+    #[test]
+    fn repairs_out_of_range_values() {
+        let mut input_configs = DeviceConfigs::new();
+        input_configs.insert(
+            "USB Mic".to_string(),
+            DeviceConfig {
+                channels: Some(0),
+                ..DeviceConfig::default()
+            },
+        );
+        input_configs.insert("Built-in".to_string(), DeviceConfig::default());
+        let settings = Settings {
+            input_device: Some("  ".to_string()),
+            input_configs,
+            signaling_url: Some("http://example.com".to_string()),
+            vad_threshold: 9.0,
+            shared_audio_gain: -1.0,
+            min_bitrate: 64_000,
+            max_bitrate: 16_000,
+            ..Settings::default()
+        }
+        .validated();
+
+        assert_eq!(settings.input_device, None);
+        assert!(settings.input_configs.is_empty());
+        assert_eq!(settings.signaling_url, None);
+        assert_eq!(settings.vad_threshold, MAX_VAD_THRESHOLD);
+        assert_eq!(settings.shared_audio_gain, 0.0);
+        assert_eq!(settings.bitrate_bounds(), BitrateBounds::default());
+
+        let nan = Settings {
+            vad_threshold: f32::NAN,
+            ..Settings::default()
+        };
+        assert_eq!(nan.validated().vad_threshold, DEFAULT_VAD_THRESHOLD);
+    }
+}
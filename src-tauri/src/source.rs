@@ -0,0 +1,529 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::resampler::{downmix_stereo, InputConverter};
+use crate::types::{StereoFrame, CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+/// A paced source this far behind starts over from now instead of catching
+/// up in a burst.
+const MAX_PACING_LAG: Duration = Duration::from_millis(100);
+/// Largest Opus packet: 120ms at 48kHz, per channel.
+const MAX_OPUS_FRAME: usize = 5760;
+/// Frames a `Prefetched` source decodes ahead: 200ms.
+const PREFETCH_FRAMES: usize = 10;
+
+/// What asking a source for a frame gave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceRead {
+    Frame,
+    /// Nothing yet; ask again shortly.
+    Pending,
+    /// The source has run out or stopped working.
+    Ended,
+}
+
+/// Somewhere captured audio comes from: a device, a file or a generated
+/// signal. Hands out 20ms frames at 48kHz, mono or interleaved stereo
+/// depending on the length of the buffer it's given.
+pub trait AudioSource {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead;
+}
+
+// ── FileSource ──
+
+/// A WAV or Ogg Opus file, at whatever rate and channel count it was
+/// written with.
+pub struct FileSource {
+    path: PathBuf,
+    looping: bool,
+    decoder: FileDecoder,
+    converter: InputConverter,
+    chunk: Vec<f32>,
+    /// Whether the file has given any audio since it was (re)opened, so an
+    /// empty file doesn't loop forever.
+    produced: bool,
+    ended: bool,
+}
+
+impl FileSource {
+    /// `looping` starts the file over when it ends instead of ending.
+    pub fn open(path: &Path, looping: bool) -> Result<Self> {
+        let decoder = FileDecoder::open(path)?;
+        let (rate, channels) = decoder.format();
+        tracing::info!("Opened {}: {rate}Hz, {channels} channels", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            looping,
+            converter: InputConverter::new(rate, channels, 1),
+            decoder,
+            chunk: Vec::new(),
+            produced: false,
+            ended: false,
+        })
+    }
+
+    /// Decode the next chunk into the converter. At the end of the file, it
+    /// starts over or is padded with silence to finish the last frame.
+    fn decode_more(&mut self) {
+        self.chunk.clear();
+        let more = match self.decoder.read(&mut self.chunk) {
+            Ok(more) => more,
+            Err(e) => {
+                tracing::warn!("Stopped reading {}: {e:#}", self.path.display());
+                false
+            }
+        };
+        if more || !self.chunk.is_empty() {
+            self.produced = true;
+            self.converter.push(&self.chunk);
+            return;
+        }
+
+        if self.looping && self.produced {
+            match FileDecoder::open(&self.path) {
+                Ok(decoder) => {
+                    self.decoder = decoder;
+                    self.produced = false;
+                    return;
+                }
+                Err(e) => tracing::warn!("Failed to restart {}: {e:#}", self.path.display()),
+            }
+        }
+        let (rate, channels) = self.decoder.format();
+        let padding = (rate as usize / 50) * usize::from(channels);
+        self.converter.push(&vec![0.0; padding]);
+        self.ended = true;
+    }
+}
+
+impl AudioSource for FileSource {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead {
+        let outputs = frame.len() / FRAME_SIZE;
+        if outputs != self.converter.output_channels() {
+            let (rate, channels) = self.decoder.format();
+            self.converter = InputConverter::new(rate, channels, outputs as u16);
+        }
+        loop {
+            if self.converter.pop_frame(frame) {
+                return SourceRead::Frame;
+            }
+            if self.ended {
+                return SourceRead::Ended;
+            }
+            self.decode_more();
+        }
+    }
+}
+
+enum FileDecoder {
+    Wav(hound::WavReader<BufReader<File>>),
+    OggOpus(OggOpusReader),
+}
+
+impl FileDecoder {
+    fn open(path: &Path) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .with_context(|| format!("{} is not an audio file", path.display()))?;
+        file.seek(SeekFrom::Start(0))?;
+        let file = BufReader::new(file);
+
+        match &magic {
+            b"RIFF" => Ok(Self::Wav(
+                hound::WavReader::new(file)
+                    .with_context(|| format!("Invalid WAV file {}", path.display()))?,
+            )),
+            b"OggS" => Ok(Self::OggOpus(
+                OggOpusReader::new(file)
+                    .with_context(|| format!("Can't play {}", path.display()))?,
+            )),
+            _ => anyhow::bail!("{} is not a WAV or Ogg Opus file", path.display()),
+        }
+    }
+
+    /// Sample rate and channel count.
+    fn format(&self) -> (u32, u16) {
+        match self {
+            Self::Wav(reader) => (reader.spec().sample_rate, reader.spec().channels),
+            Self::OggOpus(reader) => (SAMPLE_RATE, reader.channels),
+        }
+    }
+
+    /// Append about 20ms of interleaved samples to `out`. Returns false at
+    /// the end of the file.
+    fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+        match self {
+            Self::Wav(reader) => {
+                let spec = reader.spec();
+                let len = (spec.sample_rate as usize / 50) * usize::from(spec.channels);
+                match spec.sample_format {
+                    hound::SampleFormat::Float => {
+                        for sample in reader.samples::<f32>().take(len) {
+                            out.push(sample?);
+                        }
+                    }
+                    hound::SampleFormat::Int => {
+                        let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                        for sample in reader.samples::<i32>().take(len) {
+                            out.push(sample? as f32 * scale);
+                        }
+                    }
+                }
+                Ok(out.len() == len)
+            }
+            Self::OggOpus(reader) => reader.read(out),
+        }
+    }
+}
+
+/// Minimal Ogg Opus (RFC 7845) reader for a single mono or stereo stream,
+/// such as our own recordings.
+struct OggOpusReader {
+    packets: OggPackets,
+    decoder: opus::Decoder,
+    channels: u16,
+    /// Decoded samples (per channel) still to drop: the encoder's lookahead.
+    pre_skip: usize,
+    decoded: Vec<f32>,
+}
+
+impl OggOpusReader {
+    fn new(file: BufReader<File>) -> Result<Self> {
+        let mut packets = OggPackets {
+            file,
+            packets: VecDeque::new(),
+            partial: Vec::new(),
+        };
+
+        let head = packets.next()?.context("Empty Ogg file")?;
+        anyhow::ensure!(
+            head.len() >= 19 && head.starts_with(b"OpusHead"),
+            "Only Ogg Opus files are supported"
+        );
+        let (channels, opus_channels) = match (head[9], head[18]) {
+            (1, 0) => (1, opus::Channels::Mono),
+            (2, 0) => (2, opus::Channels::Stereo),
+            _ => anyhow::bail!("Only mono and stereo Ogg Opus files are supported"),
+        };
+        let pre_skip = usize::from(u16::from_le_bytes([head[10], head[11]]));
+        let tags = packets.next()?.unwrap_or_default();
+        anyhow::ensure!(tags.starts_with(b"OpusTags"), "Missing Opus tags");
+
+        Ok(Self {
+            packets,
+            decoder: opus::Decoder::new(SAMPLE_RATE, opus_channels)
+                .map_err(|e| anyhow::anyhow!("Failed to create opus decoder: {e}"))?,
+            channels,
+            pre_skip,
+            decoded: vec![0.0; MAX_OPUS_FRAME * usize::from(channels)],
+        })
+    }
+
+    fn read(&mut self, out: &mut Vec<f32>) -> Result<bool> {
+        let Some(packet) = self.packets.next()? else {
+            return Ok(false);
+        };
+        let frames = self
+            .decoder
+            .decode_float(&packet, &mut self.decoded, false)
+            .map_err(|e| anyhow::anyhow!("Opus decode error: {e}"))?;
+        let skip = self.pre_skip.min(frames);
+        self.pre_skip -= skip;
+        let channels = usize::from(self.channels);
+        out.extend_from_slice(&self.decoded[skip * channels..frames * channels]);
+        Ok(true)
+    }
+}
+
+/// The packets of an Ogg stream, in order, whichever pages they span.
+struct OggPackets {
+    file: BufReader<File>,
+    packets: VecDeque<Vec<u8>>,
+    /// Start of a packet continued on the next page.
+    partial: Vec<u8>,
+}
+
+impl OggPackets {
+    fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        while self.packets.is_empty() {
+            if !self.read_page()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.packets.pop_front())
+    }
+
+    /// Split the next page into packets. Returns false at the end of the file.
+    fn read_page(&mut self) -> Result<bool> {
+        let mut header = [0u8; 27];
+        match self.file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        anyhow::ensure!(&header[..4] == b"OggS", "Corrupt Ogg page");
+        let mut lacing = vec![0u8; usize::from(header[26])];
+        self.file.read_exact(&mut lacing)?;
+
+        for len in lacing {
+            let start = self.partial.len();
+            self.partial.resize(start + usize::from(len), 0);
+            self.file.read_exact(&mut self.partial[start..])?;
+            // A lacing value under 255 ends the packet
+            if len < 255 {
+                self.packets.push_back(std::mem::take(&mut self.partial));
+            }
+        }
+        Ok(true)
+    }
+}
+
+// ── ToneSource ──
+
+/// A sine tone, for checking that audio gets through.
+pub struct ToneSource {
+    amplitude: f32,
+    phase: f32,
+    step: f32,
+    frames_left: u32,
+}
+
+impl ToneSource {
+    pub fn new(frequency: f32, amplitude: f32, duration: Duration) -> Self {
+        Self {
+            amplitude,
+            phase: 0.0,
+            step: std::f32::consts::TAU * frequency / SAMPLE_RATE as f32,
+            frames_left: (duration.as_millis() / FRAME_DURATION.as_millis()) as u32,
+        }
+    }
+}
+
+impl AudioSource for ToneSource {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead {
+        if self.frames_left == 0 {
+            return SourceRead::Ended;
+        }
+        self.frames_left -= 1;
+        let channels = frame.len() / FRAME_SIZE;
+        for samples in frame.chunks_exact_mut(channels) {
+            samples.fill(self.amplitude * self.phase.sin());
+            self.phase = (self.phase + self.step) % std::f32::consts::TAU;
+        }
+        SourceRead::Frame
+    }
+}
+
+// ── Paced ──
+
+/// Hands out another source's frames in real time, for sources that would
+/// give them as fast as they're asked, like files.
+pub struct Paced<S> {
+    source: S,
+    next_due: Option<Instant>,
+}
+
+impl<S> Paced<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            next_due: None,
+        }
+    }
+}
+
+impl<S: AudioSource> AudioSource for Paced<S> {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead {
+        let now = Instant::now();
+        let due = *self.next_due.get_or_insert(now);
+        if now < due {
+            return SourceRead::Pending;
+        }
+        let next = due + FRAME_DURATION;
+        self.next_due = Some(if now.duration_since(due) > MAX_PACING_LAG {
+            now + FRAME_DURATION
+        } else {
+            next
+        });
+        self.source.read_frame(frame)
+    }
+}
+
+// ── Prefetched ──
+
+/// Another source's frames, read ahead on a worker thread so whoever takes
+/// them (the real-time capture) never waits on the disk or a decoder. Frames
+/// are read in stereo and mixed down when a mono frame is asked for. The
+/// worker exits once this is dropped.
+pub struct Prefetched {
+    rx: flume::Receiver<StereoFrame>,
+}
+
+impl Prefetched {
+    pub fn spawn(mut source: impl AudioSource + Send + 'static) -> Result<Self> {
+        let (tx, rx) = flume::bounded::<StereoFrame>(PREFETCH_FRAMES);
+        std::thread::Builder::new()
+            .name("audio-prefetch".into())
+            .spawn(move || {
+                let mut frame: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+                loop {
+                    match source.read_frame(&mut frame) {
+                        SourceRead::Frame => {
+                            // Waits while the queue is full; fails once the reader is gone
+                            if tx.send(frame).is_err() {
+                                break;
+                            }
+                        }
+                        SourceRead::Pending => std::thread::sleep(Duration::from_millis(5)),
+                        SourceRead::Ended => break,
+                    }
+                }
+            })?;
+        Ok(Self { rx })
+    }
+}
+
+impl AudioSource for Prefetched {
+    fn read_frame(&mut self, frame: &mut [f32]) -> SourceRead {
+        match self.rx.try_recv() {
+            Ok(stereo) if frame.len() == stereo.len() => frame.copy_from_slice(&stereo),
+            Ok(stereo) => downmix_stereo(&stereo, frame),
+            Err(flume::TryRecvError::Empty) => return SourceRead::Pending,
+            Err(flume::TryRecvError::Disconnected) => return SourceRead::Ended,
+        }
+        SourceRead::Frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::OggOpusWriter;
+    use crate::types::PcmFrame;
+
+    fn temp_path(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "entavi-test-source-{}.{extension}",
+            uuid::Uuid::new_v4()
+        ))
+    }
+
+    fn peak(frame: &[f32]) -> f32 {
+        frame.iter().map(|s| s.abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn wav_files_come_out_as_48khz_frames() {
+        // Half a second of a stereo 44.1kHz tone, left side only
+        let path = temp_path("wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for n in 0..22_050 {
+            let left = 0.5 * (n as f32 * 0.05).sin();
+            writer
+                .write_sample((left * i16::MAX as f32) as i16)
+                .unwrap();
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut source = FileSource::open(&path, false).unwrap();
+        let mut frame: StereoFrame = [0.0; FRAME_SIZE * CHANNELS as usize];
+        let mut frames = 0;
+        while source.read_frame(&mut frame) == SourceRead::Frame {
+            if frames == 10 {
+                let right: Vec<f32> = frame.iter().skip(1).step_by(2).copied().collect();
+                assert!((peak(&frame) - 0.5).abs() < 0.05);
+                assert!(peak(&right) < 1e-3);
+            }
+            frames += 1;
+        }
+        // 25 frames of audio, the last one finished with silence
+        assert!((25..=26).contains(&frames), "{frames} frames");
+        assert_eq!(source.read_frame(&mut frame), SourceRead::Ended);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn plays_back_our_ogg_opus_recordings() {
+        let path = temp_path("opus");
+        let mut writer = OggOpusWriter::create(&path).unwrap();
+        let mut tone = ToneSource::new(440.0, 0.3, Duration::from_secs(1));
+        let mut frame: PcmFrame = [0.0; FRAME_SIZE];
+        while tone.read_frame(&mut frame) == SourceRead::Frame {
+            writer.write(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut source = FileSource::open(&path, false).unwrap();
+        let mut frames = 0;
+        let mut loudest = 0.0f32;
+        while source.read_frame(&mut frame) == SourceRead::Frame {
+            loudest = loudest.max(peak(&frame));
+            frames += 1;
+        }
+        // The encoder's lookahead is skipped, so it's exactly as long
+        assert_eq!(frames, 50);
+        assert!((loudest - 0.3).abs() < 0.05);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn prefetched_frames_arrive_whole_and_then_end() {
+        let tone = ToneSource::new(440.0, 0.3, Duration::from_millis(200));
+        let mut source = Prefetched::spawn(tone).unwrap();
+        let mut frame: PcmFrame = [0.0; FRAME_SIZE];
+        let mut frames = 0;
+        loop {
+            match source.read_frame(&mut frame) {
+                SourceRead::Frame => {
+                    // Both channels carry the tone, so the mono mix does too
+                    assert!((peak(&frame) - 0.3).abs() < 0.01);
+                    frames += 1;
+                }
+                SourceRead::Pending => std::thread::sleep(Duration::from_millis(1)),
+                SourceRead::Ended => break,
+            }
+        }
+        assert_eq!(frames, 10);
+    }
+
+    #[test]
+    fn looping_files_start_over_and_pacing_holds_frames_back() {
+        let path = temp_path("wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..FRAME_SIZE {
+            writer.write_sample(0.25f32).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut source = Paced::new(FileSource::open(&path, true).unwrap());
+        let mut frame: PcmFrame = [0.0; FRAME_SIZE];
+        assert_eq!(source.read_frame(&mut frame), SourceRead::Frame);
+        assert_eq!(source.read_frame(&mut frame), SourceRead::Pending);
+        for _ in 0..5 {
+            std::thread::sleep(FRAME_DURATION);
+            assert_eq!(source.read_frame(&mut frame), SourceRead::Frame);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,18 +1,28 @@
+use std::collections::BTreeMap;
+
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 // ── Audio constants ──
 
 pub const SAMPLE_RATE: u32 = 48_000;
-pub const CHANNELS: u16 = 1; // mono
+pub const CHANNELS: u16 = 2; // mixer and playback are stereo; voice is captured mono
 pub const FRAME_SIZE: usize = 960; // 20ms at 48kHz
 pub type PcmFrame = [f32; FRAME_SIZE];
+/// 20ms of interleaved stereo: what the mixer works in and playback takes.
+pub type StereoFrame = [f32; FRAME_SIZE * CHANNELS as usize];
 
 // Keep real-time audio queues short. If processing falls behind, dropping audio
 // is preferable to unbounded memory growth and high-latency playback.
 pub const AUDIO_CAPTURE_QUEUE_FRAMES: usize = 3; // 60ms
-pub const AUDIO_DECODE_QUEUE_FRAMES: usize = 4; // 80ms
 pub const AUDIO_PLAYBACK_QUEUE_FRAMES: usize = 4; // 80ms
+                                                  // Far-end reference for echo cancellation: enough slack to absorb scheduling
+                                                  // jitter between the mixer tick and the capture thread.
+pub const AUDIO_ECHO_REFERENCE_QUEUE_FRAMES: usize = 10; // 200ms
+                                                         // Recordings go to disk, which can stall for a moment; allow a few seconds of backlog.
+pub const RECORDING_QUEUE_FRAMES: usize = 150; // 3s
+                                               // Shared system audio runs on its own device clock; absorb some callback jitter.
+pub const AUDIO_SHARE_QUEUE_FRAMES: usize = 5; // 100ms
 
 // ── Peer info (sent in room_joined / peer_joined) ──
 
@@ -53,11 +63,29 @@ pub enum SignalMessage {
     MuteState {
         muted: bool,
     },
+    /// We started or stopped recording the call; everyone else is told.
+    RecordingState {
+        recording: bool,
+    },
+    // Host moderation (ignored by the server unless sent by the host)
+    LockRoom {
+        locked: bool,
+    },
+    KickPeer {
+        peer_id: String,
+    },
+    RequestMute {
+        peer_id: String,
+    },
     // Server → Client
     PeerMuteState {
         peer_id: String,
         muted: bool,
     },
+    PeerRecordingState {
+        peer_id: String,
+        recording: bool,
+    },
     RoomJoined {
         room_id: String,
         peers: Vec<PeerInfo>,
@@ -79,6 +107,24 @@ pub enum SignalMessage {
         peer_id: String,
     },
     RoomNotFound,
+    /// Join rejected: the room has a password and ours was missing or wrong.
+    WrongPassword,
+    /// Join rejected: the host locked the room.
+    RoomLocked,
+    RoomLockChanged {
+        locked: bool,
+    },
+    /// The host removed us from the room.
+    Kicked,
+    /// The host asked us to mute.
+    MuteRequested {
+        #[serde(default)]
+        by: String,
+    },
+    /// The previous host left; `peer_id` hosts the room now.
+    HostChanged {
+        peer_id: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,6 +154,52 @@ pub struct TurnServerInfo {
     pub credential: String,
 }
 
+// ── Microphone input mode ──
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputMode {
+    /// Open mic: transmit whenever unmuted.
+    VoiceActivity,
+    /// Transmit only while the push-to-talk shortcut is held.
+    PushToTalk,
+}
+
+// ── Recording ──
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    /// 16-bit PCM WAV.
+    Wav,
+    /// Opus in an Ogg container (`.opus`).
+    OggOpus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingLayout {
+    /// One file with everyone mixed together.
+    Mixed,
+    /// One file per participant, all starting at the same moment.
+    PerParticipant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStateEvent {
+    pub recording: bool,
+    /// Folder the recording is written to.
+    pub dir: Option<String>,
+    /// Finished files, once the recording has stopped.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerRecordingEvent {
+    pub peer_id: String,
+    pub recording: bool,
+}
+
 // ── Call state ──
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -141,15 +233,120 @@ pub const EVENT_PING_UPDATE: &str = "ping-update";
 pub const EVENT_VOICE_ACTIVITY: &str = "voice-activity";
 pub const EVENT_PEER_MUTE_CHANGED: &str = "peer-mute-changed";
 pub const EVENT_MIC_TEST_LEVEL: &str = "mic-test-level";
+pub const EVENT_PEER_VOLUME_CHANGED: &str = "peer-volume-changed";
+pub const EVENT_PTT_STATE: &str = "ptt-state";
+pub const EVENT_CALL_STATS: &str = "call-stats";
+pub const EVENT_KICKED: &str = "kicked";
+pub const EVENT_MUTE_REQUESTED: &str = "mute-requested";
+pub const EVENT_HOST_CHANGED: &str = "host-changed";
+pub const EVENT_CHAT_MESSAGE: &str = "chat-message";
+pub const EVENT_CHAT_DELIVERED: &str = "chat-delivered";
+pub const EVENT_FILE_OFFER: &str = "file-offer";
+pub const EVENT_FILE_PROGRESS: &str = "file-progress";
+pub const EVENT_FILE_TRANSFER_STATE: &str = "file-transfer-state";
+pub const EVENT_E2EE_STATE: &str = "e2ee-state";
+pub const EVENT_PEER_SAS: &str = "peer-sas";
+pub const EVENT_RECORDING_STATE: &str = "recording-state";
+pub const EVENT_PEER_RECORDING: &str = "peer-recording";
+pub const EVENT_DEVICES_CHANGED: &str = "devices-changed";
+pub const EVENT_DEVICE_SWITCHED: &str = "device-switched";
+pub const EVENT_MUSIC_MODE: &str = "music-mode";
+pub const EVENT_AUDIO_SHARE: &str = "audio-share";
 
 // ── Audio device info (for mic selector) ──
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct AudioDevice {
     pub name: String,
     pub is_default: bool,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct DevicesChangedEvent {
+    pub inputs: Vec<AudioDevice>,
+    pub outputs: Vec<AudioDevice>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Input,
+    Output,
+}
+
+/// Stream settings picked for one device. Unset fields, and values the
+/// device turns out not to support, use the device's default config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Frames per audio callback.
+    pub buffer_size: Option<u32>,
+}
+
+impl DeviceConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Reject values no audio device uses.
+    pub fn validated(self) -> anyhow::Result<Self> {
+        if let Some(rate) = self.sample_rate {
+            anyhow::ensure!(
+                (8_000..=384_000).contains(&rate),
+                "Sample rate must be between 8 and 384 kHz"
+            );
+        }
+        if let Some(channels) = self.channels {
+            anyhow::ensure!(
+                (1..=32).contains(&channels),
+                "Channel count must be between 1 and 32"
+            );
+        }
+        if let Some(frames) = self.buffer_size {
+            anyhow::ensure!(
+                (16..=16_384).contains(&frames),
+                "Buffer size must be between 16 and 16384 frames"
+            );
+        }
+        Ok(self)
+    }
+}
+
+/// Per-device stream settings, by device name.
+pub type DeviceConfigs = BTreeMap<String, DeviceConfig>;
+
+/// One range of stream configs a device supports, for the settings UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportedDeviceConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+    /// Frames per callback the device accepts, when it reports a range.
+    pub min_buffer_size: Option<u32>,
+    pub max_buffer_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceFormats {
+    pub default_sample_rate: u32,
+    pub default_channels: u16,
+    pub default_sample_format: String,
+    pub supported: Vec<SupportedDeviceConfig>,
+}
+
+/// A call's audio moved to another device without the user asking.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSwitchedEvent {
+    pub kind: DeviceKind,
+    /// Device now in use; `None` is the system default.
+    pub device: Option<String>,
+    /// The preferred device is missing and the default stands in for it.
+    pub fallback: bool,
+}
+
 // ── Voice activity event (emitted to frontend) ──
 
 #[derive(Debug, Clone, Serialize)]
@@ -166,17 +363,181 @@ pub struct PeerMuteEvent {
     pub muted: bool,
 }
 
-// ── Encoded audio frame (mic → network) ──
+// ── Peer volume event (emitted to frontend) ──
 
-#[derive(Debug, Clone)]
-pub struct EncodedFrame {
-    pub data: Bytes,
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerVolumeEvent {
+    pub peer_id: String,
+    pub gain: f32,
 }
 
-// ── Decoded audio frame (network → speaker) ──
+// ── Music mode event (emitted to frontend) ──
+
+/// Music mode bypasses voice processing and raises the bitrate. It's sent in
+/// stereo once every peer has said it accepts stereo, and mono until then.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MusicModeEvent {
+    pub enabled: bool,
+    pub stereo: bool,
+}
+
+// ── Audio share event (emitted to frontend) ──
+
+/// The input device whose sound is mixed into the call, or `None` when
+/// nothing is shared.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AudioShareEvent {
+    pub device: Option<String>,
+}
+
+// ── Chat events (emitted to frontend) ──
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessageEvent {
+    pub id: String,
+    /// Sender; our own peer ID for messages we sent.
+    pub peer_id: String,
+    pub name: String,
+    pub text: String,
+    /// Sender's wall clock, milliseconds since the Unix epoch.
+    pub sent_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatDeliveredEvent {
+    pub id: String,
+    /// Peer that acknowledged the message.
+    pub peer_id: String,
+}
+
+// ── File transfer events (emitted to frontend) ──
+
+/// A peer wants to send us a file; accept or decline it by `id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileOfferEvent {
+    pub id: String,
+    pub peer_id: String,
+    pub name: String,
+    pub size: u64,
+}
+
+/// Bytes moved so far, for transfers in either direction.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProgressEvent {
+    pub id: String,
+    pub peer_id: String,
+    pub transferred: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileTransferState {
+    Accepted,
+    Declined,
+    Cancelled,
+    /// Every byte arrived and the SHA-256 matched.
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTransferStateEvent {
+    pub id: String,
+    pub peer_id: String,
+    pub state: FileTransferState,
+    pub error: Option<String>,
+}
+
+// ── Jitter buffer stats (per remote peer) ──
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JitterStats {
+    pub depth_ms: u32,
+    pub target_ms: u32,
+    pub jitter_ms: f64,
+    pub late_packets: u64,
+    pub lost_packets: u64,
+    /// Lost frames rebuilt from the following packet's in-band FEC.
+    pub fec_recovered: u64,
+    /// Frames synthesized by packet loss concealment.
+    pub concealed_frames: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerJitterStats {
+    pub peer_id: String,
+    #[serde(flatten)]
+    pub stats: JitterStats,
+}
+
+/// End-to-end encryption status of a peer's audio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum E2eeState {
+    /// The call code carries no room key.
+    #[default]
+    Disabled,
+    /// Waiting for the peer's first frame.
+    Pending,
+    /// The peer's frames decrypt with our room key.
+    Verified,
+    /// The peer's frames don't decrypt: they hold a different key, or none.
+    Mismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct E2eeStateEvent {
+    pub peer_id: String,
+    pub state: E2eeState,
+}
+
+/// One symbol of a short authentication string, with a word to read aloud.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SasSymbol {
+    pub emoji: String,
+    pub name: String,
+}
+
+/// Short authentication string for the connection to one peer. Both ends
+/// see the same symbols unless the DTLS fingerprints were tampered with.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerSasEvent {
+    pub peer_id: String,
+    pub symbols: Vec<SasSymbol>,
+}
+
+/// Media quality for one peer connection, sampled from WebRTC stats.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerCallStats {
+    pub peer_id: String,
+    /// Round trip time on the selected ICE candidate pair.
+    pub rtt_ms: Option<f64>,
+    /// Interarrival jitter of the peer's audio.
+    pub jitter_ms: f64,
+    /// Fraction (0..1) of the peer's packets that never arrived.
+    pub inbound_loss: f64,
+    /// Fraction (0..1) of our packets the peer reported lost.
+    pub outbound_loss: Option<f64>,
+    /// Bits per second since the previous sample.
+    pub send_bitrate: Option<u32>,
+    pub receive_bitrate: Option<u32>,
+    pub jitter_buffer_ms: u32,
+    /// ICE candidate types on the selected pair: host, srflx, prflx or relay.
+    pub local_candidate_type: Option<String>,
+    pub remote_candidate_type: Option<String>,
+    pub codec: Option<String>,
+    pub e2ee: E2eeState,
+}
+
+// ── Encoded audio frame (mic → network) ──
 
 #[derive(Debug, Clone)]
-pub struct DecodedFrame {
-    pub samples: PcmFrame,
-    pub len: usize,
+pub struct EncodedFrame {
+    pub data: Bytes,
+    /// Capture clock in samples at the start of this frame. It keeps running
+    /// through untransmitted (DTX) frames, so receivers can tell silence from loss.
+    pub timestamp: u32,
+    /// First frame sent after a transmission gap (sets the RTP marker bit).
+    pub talkspurt_start: bool,
 }
@@ -0,0 +1,200 @@
+use nnnoiseless::DenoiseState;
+
+const DENOISE_FRAME: usize = nnnoiseless::FRAME_SIZE; // 480
+
+// ── Voice activity detection ──
+
+/// RNNoise voice probability that starts a speech segment.
+const ONSET_PROBABILITY: f32 = 0.7;
+/// Lower probability that keeps an ongoing segment alive (hysteresis).
+const CONTINUE_PROBABILITY: f32 = 0.3;
+/// Consecutive voiced frames needed to start speaking, so a single click or
+/// tap that briefly fools the network doesn't light the indicator.
+const ONSET_FRAMES: u32 = 2;
+/// Frames to keep speaking after voice drops out, bridging gaps between words.
+const HANGOVER_FRAMES: u32 = 15; // 300ms
+/// Level gate for remote peers, whose audio the sender has already normalized.
+pub const REMOTE_LEVEL_THRESHOLD: f32 = 0.01;
+
+/// Run RNNoise over a frame in 10ms chunks and return the highest voice
+/// probability it reported. The denoised audio replaces `frame` only when
+/// `suppress` is set, so this also serves as a pure detector.
+pub fn denoise_frame(state: &mut DenoiseState, frame: &mut [f32], suppress: bool) -> f32 {
+    let mut input = [0.0f32; DENOISE_FRAME];
+    let mut output = [0.0f32; DENOISE_FRAME];
+    let mut voice_probability = 0.0f32;
+
+    for chunk in frame.chunks_exact_mut(DENOISE_FRAME) {
+        // nnnoiseless expects i16-range floats [-32768, 32767]
+        for (i, sample) in input.iter_mut().zip(chunk.iter()) {
+            *i = sample * 32767.0;
+        }
+        voice_probability = voice_probability.max(state.process_frame(&mut output, &input));
+        if suppress {
+            for (sample, o) in chunk.iter_mut().zip(&output) {
+                *sample = o / 32767.0;
+            }
+        }
+    }
+
+    voice_probability
+}
+
+/// Speech/non-speech decision per 20ms frame from the RNNoise voice
+/// probability, gated by level so faint background voices don't count, with
+/// onset confirmation and hangover smoothing.
+#[derive(Default)]
+pub struct VoiceDetector {
+    onset: u32,
+    hangover: u32,
+}
+
+impl VoiceDetector {
+    pub fn update(&mut self, voice_probability: f32, level: f32, level_threshold: f32) -> bool {
+        let loud = level > level_threshold;
+
+        if self.hangover > 0 {
+            if loud && voice_probability >= CONTINUE_PROBABILITY {
+                self.hangover = HANGOVER_FRAMES;
+            } else {
+                self.hangover -= 1;
+            }
+        } else if loud && voice_probability >= ONSET_PROBABILITY {
+            self.onset += 1;
+            if self.onset >= ONSET_FRAMES {
+                self.onset = 0;
+                self.hangover = HANGOVER_FRAMES;
+            }
+        } else {
+            self.onset = 0;
+        }
+
+        self.is_speaking()
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.hangover > 0
+    }
+}
+
+// ── Threshold calibration ──
+
+/// Proposed threshold sits this far above the measured noise floor (≈ 6 dB).
+const CALIBRATION_MARGIN: f32 = 2.0;
+/// Fraction of calibration frames treated as noise; the loudest few (a cough,
+/// a chair creak) are ignored so one transient doesn't push the threshold up.
+const NOISE_PERCENTILE: f32 = 0.95;
+pub const MIN_VAD_THRESHOLD: f32 = 0.002;
+pub const MAX_VAD_THRESHOLD: f32 = 0.5;
+
+/// Collects per-frame peak levels while the user stays quiet and proposes a
+/// voice activity threshold just above the room's noise floor.
+#[derive(Default)]
+pub struct NoiseFloor {
+    levels: Vec<f32>,
+}
+
+impl NoiseFloor {
+    pub fn push(&mut self, level: f32) {
+        if level.is_finite() {
+            self.levels.push(level);
+        }
+    }
+
+    /// `None` if no audio was measured.
+    pub fn proposed_threshold(&self) -> Option<f32> {
+        if self.levels.is_empty() {
+            return None;
+        }
+        let mut sorted = self.levels.clone();
+        sorted.sort_by(f32::total_cmp);
+        let index = ((sorted.len() - 1) as f32 * NOISE_PERCENTILE).round() as usize;
+        let floor = sorted[index];
+        Some((floor * CALIBRATION_MARGIN).clamp(MIN_VAD_THRESHOLD, MAX_VAD_THRESHOLD))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: f32 = 0.01;
+
+    #[test]
+    fn single_voiced_frame_is_not_speech() {
+        let mut vad = VoiceDetector::default();
+        assert!(!vad.update(0.95, 0.5, THRESHOLD));
+        assert!(!vad.update(0.05, 0.5, THRESHOLD));
+        assert!(!vad.update(0.95, 0.5, THRESHOLD));
+    }
+
+    #[test]
+    fn hangover_bridges_short_pauses() {
+        let mut vad = VoiceDetector::default();
+        vad.update(0.9, 0.3, THRESHOLD);
+        assert!(vad.update(0.9, 0.3, THRESHOLD));
+
+        // A 200ms pause between words keeps the segment open.
+        for _ in 0..10 {
+            assert!(vad.update(0.0, 0.001, THRESHOLD));
+        }
+        // Weaker voicing is enough to continue an ongoing segment.
+        assert!(vad.update(0.4, 0.3, THRESHOLD));
+
+        for _ in 0..HANGOVER_FRAMES - 1 {
+            assert!(vad.update(0.0, 0.001, THRESHOLD));
+        }
+        assert!(!vad.update(0.0, 0.001, THRESHOLD));
+    }
+
+    #[test]
+    fn quiet_voice_below_level_gate_is_ignored() {
+        let mut vad = VoiceDetector::default();
+        for _ in 0..10 {
+            assert!(!vad.update(0.9, 0.005, THRESHOLD));
+        }
+    }
+
+    #[test]
+    fn loud_non_voice_is_ignored() {
+        let mut vad = VoiceDetector::default();
+        for _ in 0..10 {
+            assert!(!vad.update(0.1, 0.8, THRESHOLD));
+        }
+    }
+
+    #[test]
+    fn proposes_threshold_above_steady_noise() {
+        let mut floor = NoiseFloor::default();
+        for i in 0..150 {
+            floor.push(0.02 + (i % 10) as f32 * 0.001);
+        }
+        let threshold = floor.proposed_threshold().unwrap();
+        assert!(
+            threshold > 0.029 && threshold < 0.1,
+            "threshold {threshold}"
+        );
+    }
+
+    #[test]
+    fn ignores_isolated_transients() {
+        let mut floor = NoiseFloor::default();
+        for i in 0..150 {
+            floor.push(if i == 75 { 0.8 } else { 0.01 });
+        }
+        assert_eq!(floor.proposed_threshold(), Some(0.02));
+    }
+
+    #[test]
+    fn clamps_to_usable_range() {
+        let mut silent = NoiseFloor::default();
+        silent.push(0.0);
+        assert_eq!(silent.proposed_threshold(), Some(MIN_VAD_THRESHOLD));
+
+        let mut loud = NoiseFloor::default();
+        loud.push(0.9);
+        assert_eq!(loud.proposed_threshold(), Some(MAX_VAD_THRESHOLD));
+
+        assert_eq!(NoiseFloor::default().proposed_threshold(), None);
+    }
+}
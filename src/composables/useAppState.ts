@@ -1,5 +1,15 @@
 import { reactive, computed } from "vue";
-import type { View } from "../types";
+import type {
+  AudioDevice,
+  ChatMessage,
+  DeviceConfig,
+  E2eeState,
+  SasSymbol,
+  FileTransfer,
+  InputMode,
+  PeerCallStats,
+  View,
+} from "../types";
 
 const state = reactive({
   currentView: "home" as View,
@@ -7,7 +17,13 @@ const state = reactive({
   roomName: null as string | null,
   roomCode: null as string | null,
   isMuted: false,
+  inputMode: "voice_activity" as InputMode,
+  pttActive: false,
   selectedMic: null as string | null,
+  inputDevices: [] as AudioDevice[],
+  outputDevices: [] as AudioDevice[],
+  inputConfigs: {} as Record<string, DeviceConfig>,
+  outputConfigs: {} as Record<string, DeviceConfig>,
   isMicTesting: false,
   noiseSuppression: true,
   voiceSensitivity: 50,
@@ -18,11 +34,37 @@ const state = reactive({
   speakingPeers: new Set<string>(),
   selfSpeaking: false,
   mutedPeers: new Set<string>(),
+  peerVolumes: new Map<string, number>(),
+  callStats: [] as PeerCallStats[],
+  chatMessages: [] as ChatMessage[],
+  // Peers that acknowledged each of our chat messages, by message ID
+  chatDelivered: new Map<string, Set<string>>(),
+  fileTransfers: new Map<string, FileTransfer>(),
+  e2eeStates: new Map<string, E2eeState>(),
+  // Short authentication string per peer, to compare out loud
+  peerSas: new Map<string, SasSymbol[]>(),
+  isRecording: false,
+  recordingDir: null as string | null,
+  // Sharing music; stays on for the session
+  musicMode: false,
+  musicStereo: false,
+  // Input device mixed into what we send, and its volume (1 = as captured)
+  sharedAudioDevice: null as string | null,
+  sharedAudioGain: 1,
+  // Peers currently recording the call
+  recordingPeers: new Set<string>(),
   signalingUrl: "",
   isReconnecting: false,
   reconnectAttempt: 0,
   isJoining: false,
   roomNotFound: false,
+  wrongPassword: false,
+  roomPassword: "",
+  // Put a room key in new call codes and encrypt audio end to end
+  e2eeEnabled: false,
+  isHost: false,
+  roomLocked: false,
+  hostPeerId: null as string | null,
   statusText: "Connecting...",
   statusState: "connecting" as "connecting" | "connected" | "error",
   copyFeedback: false,
@@ -55,10 +97,26 @@ function resetRoomState() {
   state.speakingPeers = new Set();
   state.selfSpeaking = false;
   state.mutedPeers = new Set();
+  state.peerVolumes = new Map();
+  state.callStats = [];
+  state.chatMessages = [];
+  state.chatDelivered = new Map();
+  state.fileTransfers = new Map();
+  state.e2eeStates = new Map();
+  state.peerSas = new Map();
+  state.isRecording = false;
+  state.recordingDir = null;
+  state.musicStereo = false;
+  state.sharedAudioDevice = null;
+  state.recordingPeers = new Set();
   state.isReconnecting = false;
   state.reconnectAttempt = 0;
   state.isJoining = false;
   state.roomNotFound = false;
+  state.wrongPassword = false;
+  state.isHost = false;
+  state.roomLocked = false;
+  state.hostPeerId = null;
   state.pingMs = null;
   state.showSettings = false;
 }
@@ -1,14 +1,28 @@
 import { invoke } from "@tauri-apps/api/core";
 import { emit } from "@tauri-apps/api/event";
-import type { AudioDevice } from "../types";
+import type {
+  AudioDevice,
+  ChatMessage,
+  DeviceConfig,
+  DeviceFormats,
+  DeviceKind,
+  InputMode,
+  PeerCallStats,
+  PeerJitterStats,
+  RecordingFormat,
+  RecordingLayout,
+  SasSymbol,
+  Settings,
+} from "../types";
 
 export function useTauri() {
   async function createRoom(
     roomName: string,
     name: string,
     password: string | null,
+    e2ee: boolean,
   ) {
-    return invoke<string>("create_room", { roomName, name, password });
+    return invoke<string>("create_room", { roomName, name, password, e2ee });
   }
 
   async function joinRoom(
@@ -27,6 +41,97 @@ export function useTauri() {
     return invoke("set_muted", { muted });
   }
 
+  async function setRoomLocked(locked: boolean) {
+    return invoke("set_room_locked", { locked });
+  }
+
+  async function kickPeer(peerId: string) {
+    return invoke("kick_peer", { peerId });
+  }
+
+  async function requestPeerMute(peerId: string) {
+    return invoke("request_peer_mute", { peerId });
+  }
+
+  async function startRecording(
+    format: RecordingFormat,
+    layout: RecordingLayout,
+  ) {
+    return invoke<string>("start_recording", { format, layout });
+  }
+
+  async function stopRecording() {
+    return invoke<string[]>("stop_recording");
+  }
+
+  async function getPeerSas(peerId: string) {
+    return invoke<SasSymbol[]>("get_peer_sas", { peerId });
+  }
+
+  async function sendChatMessage(text: string) {
+    return invoke<ChatMessage>("send_chat_message", { text });
+  }
+
+  async function sendFile(peerId: string, path: string) {
+    return invoke<string>("send_file", { peerId, path });
+  }
+
+  async function acceptFileTransfer(id: string) {
+    return invoke("accept_file_transfer", { id });
+  }
+
+  async function declineFileTransfer(id: string) {
+    return invoke("decline_file_transfer", { id });
+  }
+
+  async function cancelFileTransfer(id: string) {
+    return invoke("cancel_file_transfer", { id });
+  }
+
+  async function saveReceivedFile(id: string, path: string) {
+    return invoke("save_received_file", { id, path });
+  }
+
+  async function setInputMode(mode: InputMode, shortcut: string | null) {
+    return invoke("set_input_mode", { mode, shortcut });
+  }
+
+  async function setMusicMode(enabled: boolean) {
+    return invoke("set_music_mode", { enabled });
+  }
+
+  async function listAudioShareSources() {
+    return invoke<AudioDevice[]>("list_audio_share_sources");
+  }
+
+  async function setAudioShare(deviceName: string | null) {
+    return invoke("set_audio_share", { deviceName });
+  }
+
+  async function setSharedAudioGain(gain: number) {
+    return invoke("set_shared_audio_gain", { gain });
+  }
+
+  async function playAudioClip(path: string) {
+    return invoke("play_audio_clip", { path });
+  }
+
+  async function playTestTone() {
+    return invoke("play_test_tone");
+  }
+
+  async function stopAudioClip() {
+    return invoke("stop_audio_clip");
+  }
+
+  async function setInputFile(path: string | null) {
+    return invoke("set_input_file", { path });
+  }
+
+  async function setPeerVolume(peerId: string, gain: number) {
+    return invoke("set_peer_volume", { peerId, gain });
+  }
+
   async function listInputDevices() {
     return invoke<AudioDevice[]>("list_input_devices");
   }
@@ -35,6 +140,14 @@ export function useTauri() {
     return invoke("set_input_device", { deviceName });
   }
 
+  async function getSettings() {
+    return invoke<Settings>("get_settings");
+  }
+
+  async function updateSettings(settings: Settings) {
+    return invoke<Settings>("update_settings", { settings });
+  }
+
   async function setSignalingUrl(url: string | null) {
     return invoke("set_signaling_url", { url });
   }
@@ -51,6 +164,26 @@ export function useTauri() {
     return invoke("set_noise_suppression", { enabled });
   }
 
+  async function setEchoCancellation(enabled: boolean) {
+    return invoke("set_echo_cancellation", { enabled });
+  }
+
+  async function setVadThreshold(threshold: number) {
+    return invoke("set_vad_threshold", { threshold });
+  }
+
+  async function setAgcEnabled(enabled: boolean) {
+    return invoke("set_agc_enabled", { enabled });
+  }
+
+  async function setBitrateBounds(minBitrate: number, maxBitrate: number) {
+    return invoke("set_bitrate_bounds", { minBitrate, maxBitrate });
+  }
+
+  async function calibrateVad() {
+    return invoke<number>("calibrate_vad");
+  }
+
   async function listOutputDevices() {
     return invoke<AudioDevice[]>("list_output_devices");
   }
@@ -59,6 +192,22 @@ export function useTauri() {
     return invoke("set_output_device", { deviceName });
   }
 
+  async function getDeviceFormats(kind: DeviceKind, deviceName: string | null) {
+    return invoke<DeviceFormats>("get_device_formats", { kind, deviceName });
+  }
+
+  async function setDeviceConfig(kind: DeviceKind, deviceName: string, config: DeviceConfig) {
+    return invoke("set_device_config", { kind, deviceName, config });
+  }
+
+  async function getJitterStats() {
+    return invoke<PeerJitterStats[]>("get_jitter_stats");
+  }
+
+  async function getCallStats() {
+    return invoke<PeerCallStats[]>("get_call_stats");
+  }
+
   async function showNotification(title: string, body: string) {
     try {
       await invoke("show_notification", { title, body });
@@ -80,14 +229,47 @@ export function useTauri() {
     joinRoom,
     leaveRoom,
     setMuted,
+    setRoomLocked,
+    kickPeer,
+    requestPeerMute,
+    startRecording,
+    stopRecording,
+    getPeerSas,
+    sendChatMessage,
+    sendFile,
+    acceptFileTransfer,
+    declineFileTransfer,
+    cancelFileTransfer,
+    saveReceivedFile,
+    setInputMode,
+    setMusicMode,
+    listAudioShareSources,
+    setAudioShare,
+    setSharedAudioGain,
+    playAudioClip,
+    playTestTone,
+    stopAudioClip,
+    setInputFile,
+    setPeerVolume,
     listInputDevices,
     setInputDevice,
     listOutputDevices,
     setOutputDevice,
+    getDeviceFormats,
+    setDeviceConfig,
+    getSettings,
+    updateSettings,
     setSignalingUrl,
     startMicTest,
     stopMicTest,
     setNoiseSuppression,
+    setEchoCancellation,
+    setVadThreshold,
+    setAgcEnabled,
+    calibrateVad,
+    setBitrateBounds,
+    getJitterStats,
+    getCallStats,
     showNotification,
     checkForUpdates,
     emitMuteState,
@@ -4,13 +4,28 @@ import type {
   PeerInfo,
   VoiceActivityEvent,
   PeerMuteEvent,
+  PeerVolumeEvent,
+  PeerCallStats,
+  ChatMessage,
+  ChatDeliveredEvent,
+  FileOfferEvent,
+  FileProgressEvent,
+  FileTransferStateEvent,
+  E2eeStateEvent,
+  PeerSasEvent,
+  RecordingStateEvent,
+  PeerRecordingEvent,
+  DevicesChangedEvent,
+  DeviceSwitchedEvent,
+  MusicModeEvent,
+  AudioShareEvent,
 } from "../types";
 import { useAppState } from "./useAppState";
 import { useTauri } from "./useTauri";
 
 export async function setupListeners(): Promise<UnlistenFn[]> {
-  const { state, setStatus } = useAppState();
-  const { showNotification } = useTauri();
+  const { state, setStatus, resetRoomState } = useAppState();
+  const { showNotification, emitMuteState } = useTauri();
 
   const unlisteners: UnlistenFn[] = [];
 
@@ -33,6 +48,8 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
           break;
         case "in_room":
           setStatus("Connected", "connected");
+          state.isHost = s.is_host ?? false;
+          state.roomLocked = s.locked ?? false;
           // Clear reconnecting state
           if (state.isReconnecting) {
             state.isReconnecting = false;
@@ -59,6 +76,9 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
           if (s.message === "Room not found") {
             state.roomNotFound = true;
             state.currentView = "scan";
+          } else if (s.message === "Wrong password") {
+            state.wrongPassword = true;
+            state.currentView = "scan";
           } else {
             setStatus(s.message ?? "An error occurred", "error");
             if (state.currentView === "room" && state.peerList.size === 0) {
@@ -73,7 +93,10 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
   unlisteners.push(
     await listen<PeerInfo>("peer-joined", (event) => {
       console.log("Peer joined:", event.payload);
-      const { peer_id, name } = event.payload;
+      const { peer_id, name, is_host } = event.payload;
+      if (is_host) {
+        state.hostPeerId = peer_id;
+      }
       if (!state.peerList.has(peer_id)) {
         state.peerList.set(peer_id, name);
         // Trigger reactivity by reassigning the map
@@ -91,6 +114,180 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
       state.mutedPeers = new Set(state.mutedPeers);
       state.speakingPeers.delete(event.payload);
       state.speakingPeers = new Set(state.speakingPeers);
+      state.peerVolumes.delete(event.payload);
+      state.peerVolumes = new Map(state.peerVolumes);
+      state.recordingPeers.delete(event.payload);
+      state.recordingPeers = new Set(state.recordingPeers);
+    }),
+  );
+
+  unlisteners.push(
+    await listen("kicked", () => {
+      resetRoomState();
+      state.currentView = "home";
+      showNotification("Removed from room", "The host removed you from the room");
+    }),
+  );
+
+  unlisteners.push(
+    await listen<string>("mute-requested", async () => {
+      state.isMuted = true;
+      await emitMuteState(true);
+      showNotification("Muted", "The host muted your microphone");
+    }),
+  );
+
+  unlisteners.push(
+    await listen<string>("host-changed", (event) => {
+      state.hostPeerId = event.payload;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<ChatMessage>("chat-message", (event) => {
+      state.chatMessages = [...state.chatMessages, event.payload];
+    }),
+  );
+
+  unlisteners.push(
+    await listen<ChatDeliveredEvent>("chat-delivered", (event) => {
+      const { id, peer_id } = event.payload;
+      const delivered = new Set(state.chatDelivered.get(id));
+      delivered.add(peer_id);
+      state.chatDelivered.set(id, delivered);
+      state.chatDelivered = new Map(state.chatDelivered);
+    }),
+  );
+
+  unlisteners.push(
+    await listen<FileOfferEvent>("file-offer", (event) => {
+      const { id, peer_id, name, size } = event.payload;
+      state.fileTransfers.set(id, {
+        id,
+        peer_id,
+        direction: "incoming",
+        name,
+        size,
+        transferred: 0,
+        state: "offered",
+        error: null,
+      });
+      state.fileTransfers = new Map(state.fileTransfers);
+      const from = state.peerList.get(peer_id) ?? "Someone";
+      showNotification("File offered", `${from} wants to send you ${name}`);
+    }),
+  );
+
+  unlisteners.push(
+    await listen<FileProgressEvent>("file-progress", (event) => {
+      const transfer = state.fileTransfers.get(event.payload.id);
+      if (transfer) {
+        transfer.transferred = event.payload.transferred;
+        state.fileTransfers = new Map(state.fileTransfers);
+      }
+    }),
+  );
+
+  unlisteners.push(
+    await listen<FileTransferStateEvent>("file-transfer-state", (event) => {
+      const transfer = state.fileTransfers.get(event.payload.id);
+      if (transfer) {
+        transfer.state = event.payload.state;
+        transfer.error = event.payload.error;
+        state.fileTransfers = new Map(state.fileTransfers);
+      }
+    }),
+  );
+
+  unlisteners.push(
+    await listen<PeerCallStats[]>("call-stats", (event) => {
+      state.callStats = event.payload;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<E2eeStateEvent>("e2ee-state", (event) => {
+      const next = new Map(state.e2eeStates);
+      next.set(event.payload.peer_id, event.payload.state);
+      state.e2eeStates = next;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<RecordingStateEvent>("recording-state", (event) => {
+      state.isRecording = event.payload.recording;
+      state.recordingDir = event.payload.dir;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<PeerRecordingEvent>("peer-recording", (event) => {
+      const { peer_id, recording } = event.payload;
+      const next = new Set(state.recordingPeers);
+      if (recording) {
+        if (!next.has(peer_id)) {
+          const name = state.peerList.get(peer_id) ?? "Someone";
+          showNotification("Call is being recorded", `${name} is recording this call`);
+        }
+        next.add(peer_id);
+      } else {
+        next.delete(peer_id);
+      }
+      state.recordingPeers = next;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<DevicesChangedEvent>("devices-changed", (event) => {
+      state.inputDevices = event.payload.inputs;
+      state.outputDevices = event.payload.outputs;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<DeviceSwitchedEvent>("device-switched", (event) => {
+      const { kind, device, fallback } = event.payload;
+      const label = kind === "input" ? "Microphone" : "Speaker";
+      if (fallback) {
+        const preferred = kind === "input" ? state.selectedMic : state.selectedOutput;
+        showNotification(
+          `${label} disconnected`,
+          `${preferred} is unavailable, using the system default`,
+        );
+      } else {
+        showNotification(`${label} switched`, `Now using ${device ?? "the system default"}`);
+      }
+    }),
+  );
+
+  unlisteners.push(
+    await listen<MusicModeEvent>("music-mode", (event) => {
+      state.musicMode = event.payload.enabled;
+      state.musicStereo = event.payload.stereo;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<AudioShareEvent>("audio-share", (event) => {
+      const stopped = state.sharedAudioDevice && !event.payload.device;
+      state.sharedAudioDevice = event.payload.device;
+      if (stopped) {
+        showNotification("Audio sharing stopped", "The others no longer hear your audio");
+      }
+    }),
+  );
+
+  unlisteners.push(
+    await listen<PeerSasEvent>("peer-sas", (event) => {
+      const next = new Map(state.peerSas);
+      next.set(event.payload.peer_id, event.payload.symbols);
+      state.peerSas = next;
+    }),
+  );
+
+  unlisteners.push(
+    await listen<boolean>("ptt-state", (event) => {
+      state.pttActive = event.payload;
     }),
   );
 
@@ -133,5 +330,13 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
     }),
   );
 
+  unlisteners.push(
+    await listen<PeerVolumeEvent>("peer-volume-changed", (event) => {
+      const { peer_id, gain } = event.payload;
+      state.peerVolumes.set(peer_id, gain);
+      state.peerVolumes = new Map(state.peerVolumes);
+    }),
+  );
+
   return unlisteners;
 }
@@ -15,12 +15,15 @@ export function callLink(code: string): string {
 /**
  * Extract a call code from a pasted/scanned value. Accepts a full link
  * (`https://entavi.app/c/CODE`), a bare path (`entavi.app/c/CODE`), or the
- * code itself. Returns the normalized code, or '' if nothing usable.
+ * code itself. An end-to-end room key (`#` + 32 hex characters) is kept on
+ * the code. Returns the normalized code, or '' if nothing usable.
  */
 export function parseCode(input: string): string {
   let value = input.trim();
   if (!value) return "";
 
+  const key = value.match(/#([0-9a-fA-F]{32})$/)?.[1]?.toLowerCase();
+
   // Strip query/hash if present.
   value = value.split(/[?#]/)[0];
 
@@ -33,5 +36,6 @@ export function parseCode(input: string): string {
 
   // Keep only safe code characters.
   value = value.replace(/[^a-zA-Z0-9_-]/g, "").toLowerCase();
+  if (value && key) value += `#${key}`;
   return value;
 }
@@ -1,11 +1,15 @@
 export type View = "home" | "scan" | "room";
 
+export type InputMode = "voice_activity" | "push_to_talk";
+
 export interface CallState {
   state: "idle" | "connecting" | "in_room" | "reconnecting" | "error";
   room_id?: string;
   room_name?: string;
   attempt?: number;
   message?: string;
+  is_host?: boolean;
+  locked?: boolean;
 }
 
 export interface AudioDevice {
@@ -13,9 +17,60 @@ export interface AudioDevice {
   is_default: boolean;
 }
 
+export type DeviceKind = "input" | "output";
+
+/** Stream settings chosen for a device; null keeps the device default. */
+export interface DeviceConfig {
+  sample_rate: number | null;
+  channels: number | null;
+  buffer_size: number | null;
+}
+
+export interface SupportedDeviceConfig {
+  channels: number;
+  min_sample_rate: number;
+  max_sample_rate: number;
+  sample_format: string;
+  min_buffer_size: number | null;
+  max_buffer_size: number | null;
+}
+
+export interface DeviceFormats {
+  default_sample_rate: number;
+  default_channels: number;
+  default_sample_format: string;
+  supported: SupportedDeviceConfig[];
+}
+
+export interface DevicesChangedEvent {
+  inputs: AudioDevice[];
+  outputs: AudioDevice[];
+}
+
+/** Music mode bypasses voice processing and raises the bitrate; it is sent
+ * in stereo once every peer has accepted stereo. */
+export interface MusicModeEvent {
+  enabled: boolean;
+  stereo: boolean;
+}
+
+/** Input device being shared into the call; null when nothing is. */
+export interface AudioShareEvent {
+  device: string | null;
+}
+
+export interface DeviceSwitchedEvent {
+  kind: DeviceKind;
+  /** Device now in use; null is the system default. */
+  device: string | null;
+  /** The preferred device is missing and the default stands in for it. */
+  fallback: boolean;
+}
+
 export interface PeerInfo {
   peer_id: string;
   name: string;
+  is_host?: boolean;
 }
 
 export interface VoiceActivityEvent {
@@ -27,3 +82,135 @@ export interface PeerMuteEvent {
   peer_id: string;
   muted: boolean;
 }
+
+export interface PeerVolumeEvent {
+  peer_id: string;
+  gain: number;
+}
+
+export interface PeerCallStats {
+  peer_id: string;
+  rtt_ms: number | null;
+  jitter_ms: number;
+  inbound_loss: number;
+  outbound_loss: number | null;
+  send_bitrate: number | null;
+  receive_bitrate: number | null;
+  jitter_buffer_ms: number;
+  local_candidate_type: string | null;
+  remote_candidate_type: string | null;
+  codec: string | null;
+  e2ee: E2eeState;
+}
+
+export type E2eeState = "disabled" | "pending" | "verified" | "mismatch";
+
+/** Preferences saved by the desktop backend across launches. */
+export interface Settings {
+  version: number;
+  input_device: string | null;
+  output_device: string | null;
+  input_configs: Record<string, DeviceConfig>;
+  output_configs: Record<string, DeviceConfig>;
+  signaling_url: string | null;
+  noise_suppression: boolean;
+  echo_cancellation: boolean;
+  vad_threshold: number;
+  agc_enabled: boolean;
+  min_bitrate: number;
+  max_bitrate: number;
+  shared_audio_gain: number;
+}
+
+export type RecordingFormat = "wav" | "ogg_opus";
+
+export type RecordingLayout = "mixed" | "per_participant";
+
+export interface RecordingStateEvent {
+  recording: boolean;
+  dir: string | null;
+  files: string[];
+}
+
+export interface PeerRecordingEvent {
+  peer_id: string;
+  recording: boolean;
+}
+
+export interface SasSymbol {
+  emoji: string;
+  name: string;
+}
+
+export interface PeerSasEvent {
+  peer_id: string;
+  symbols: SasSymbol[];
+}
+
+export interface E2eeStateEvent {
+  peer_id: string;
+  state: E2eeState;
+}
+
+export interface ChatMessage {
+  id: string;
+  peer_id: string;
+  name: string;
+  text: string;
+  sent_at: number;
+}
+
+export interface ChatDeliveredEvent {
+  id: string;
+  peer_id: string;
+}
+
+export interface FileOfferEvent {
+  id: string;
+  peer_id: string;
+  name: string;
+  size: number;
+}
+
+export interface FileProgressEvent {
+  id: string;
+  peer_id: string;
+  transferred: number;
+  size: number;
+}
+
+export type FileTransferState =
+  | "accepted"
+  | "declined"
+  | "cancelled"
+  | "completed"
+  | "failed";
+
+export interface FileTransferStateEvent {
+  id: string;
+  peer_id: string;
+  state: FileTransferState;
+  error: string | null;
+}
+
+export interface FileTransfer {
+  id: string;
+  peer_id: string;
+  direction: "incoming" | "outgoing";
+  name: string;
+  size: number;
+  transferred: number;
+  state: "offered" | FileTransferState;
+  error: string | null;
+}
+
+export interface PeerJitterStats {
+  peer_id: string;
+  depth_ms: number;
+  target_ms: number;
+  jitter_ms: number;
+  late_packets: number;
+  lost_packets: number;
+  fec_recovered: number;
+  concealed_frames: number;
+}
@@ -50,6 +50,8 @@ export function useTauri() {
   }
 
   async function setNoiseSuppression(enabled: boolean): Promise<void> {
+    // The browser has no settings file; keep the choice here instead
+    localStorage.setItem("entavi:noiseSuppression", String(enabled));
     engine.setNoiseSuppression(enabled);
   }
 
@@ -3,6 +3,7 @@ import type {
   PeerInfo,
   VoiceActivityEvent,
   PeerMuteEvent,
+  PeerRecordingEvent,
 } from "@shared/types";
 import { useAppState } from "@shared/composables/useAppState";
 import { useTauri } from "./useWeb";
@@ -12,7 +13,7 @@ type UnlistenFn = () => void;
 
 // Named setupListeners so imports from shared components work via Vite alias
 export async function setupListeners(): Promise<UnlistenFn[]> {
-  const { state, setStatus } = useAppState();
+  const { state, resetRoomState, setStatus } = useAppState();
   const { showNotification } = useTauri();
   const engine = getEngine();
 
@@ -29,6 +30,8 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
           break;
         case "in_room":
           setStatus("Connected", "connected");
+          state.isHost = s.is_host ?? false;
+          state.roomLocked = s.locked ?? false;
           // Switch to room view when server confirms join
           if (state.isJoining) {
             state.isJoining = false;
@@ -42,6 +45,9 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
           if (s.message === "Room not found") {
             state.roomNotFound = true;
             state.currentView = "scan";
+          } else if (s.message === "Wrong password") {
+            state.wrongPassword = true;
+            state.currentView = "scan";
           } else {
             setStatus(s.message ?? "An error occurred", "error");
             if (state.currentView === "room" && state.peerList.size === 0) {
@@ -55,8 +61,11 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
 
   unlisteners.push(
     engine.on("peer-joined", (payload: unknown) => {
-      const { peer_id, name } = payload as PeerInfo;
+      const { peer_id, name, is_host } = payload as PeerInfo;
       console.log("Peer joined:", payload);
+      if (is_host) {
+        state.hostPeerId = peer_id;
+      }
       if (!state.peerList.has(peer_id)) {
         state.peerList.set(peer_id, name);
         state.peerList = new Map(state.peerList);
@@ -77,6 +86,27 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
     }),
   );
 
+  unlisteners.push(
+    engine.on("kicked", () => {
+      resetRoomState();
+      state.currentView = "home";
+      showNotification("Removed from room", "The host removed you from the room");
+    }),
+  );
+
+  unlisteners.push(
+    engine.on("mute-requested", () => {
+      state.isMuted = true;
+      showNotification("Muted", "The host muted your microphone");
+    }),
+  );
+
+  unlisteners.push(
+    engine.on("host-changed", (peerId: unknown) => {
+      state.hostPeerId = peerId as string;
+    }),
+  );
+
   unlisteners.push(
     engine.on("error", (message: unknown) => {
       console.error("Engine error:", message);
@@ -116,5 +146,22 @@ export async function setupListeners(): Promise<UnlistenFn[]> {
     }),
   );
 
+  unlisteners.push(
+    engine.on("peer-recording", (payload: unknown) => {
+      const { peer_id, recording } = payload as PeerRecordingEvent;
+      const next = new Set(state.recordingPeers);
+      if (recording) {
+        if (!next.has(peer_id)) {
+          const name = state.peerList.get(peer_id) ?? "Someone";
+          showNotification("Call is being recorded", `${name} is recording this call`);
+        }
+        next.add(peer_id);
+      } else {
+        next.delete(peer_id);
+      }
+      state.recordingPeers = next;
+    }),
+  );
+
   return unlisteners;
 }
@@ -22,12 +22,18 @@ interface SignalMsg {
   payload?: SignalPayloadMsg;
   // room_joined fields
   room_id?: string;
-  peers?: Array<{ peer_id: string; name: string }>;
+  peers?: Array<{ peer_id: string; name: string; is_host?: boolean }>;
+  is_host?: boolean;
+  locked?: boolean;
   // peer_joined / peer_left
   peer_id?: string;
   name?: string;
   // peer_mute_state
   muted?: boolean;
+  // peer_recording_state
+  recording?: boolean;
+  // mute_requested
+  by?: string;
 }
 
 export class WebEngine {
@@ -40,6 +46,8 @@ export class WebEngine {
   private roomId = "";
   private roomName = "";
   private displayName = "";
+  private isHost = false;
+  private roomLocked = false;
   private signalingUrl: string | null = null;
   private selectedDeviceName: string | null = null;
   private noiseSuppression = true;
@@ -68,6 +76,7 @@ export class WebEngine {
       room_id: this.roomId,
       peer_id: this.peerId,
       name,
+      password,
       create: true,
     });
 
@@ -85,6 +94,10 @@ export class WebEngine {
     name: string,
     password: string | null,
   ): Promise<void> {
+    // Browsers can't open the desktop app's encrypted audio frames
+    if (roomId.includes("#")) {
+      throw new Error("End-to-end encrypted calls need the desktop app");
+    }
     this.peerId = crypto.randomUUID();
     this.roomId = roomId;
     this.roomName = `Room ${roomId}`;
@@ -202,17 +215,16 @@ export class WebEngine {
   private async handleMessage(msg: SignalMsg): Promise<void> {
     switch (msg.type) {
       case "room_joined": {
-        this.emit("state-changed", {
-          state: "in_room",
-          room_id: this.roomId,
-          room_name: this.roomName,
-        });
+        this.isHost = msg.is_host ?? false;
+        this.roomLocked = msg.locked ?? false;
+        this.emitInRoom();
 
         // Emit peer-joined for each existing peer
         for (const peer of msg.peers ?? []) {
           this.emit("peer-joined", {
             peer_id: peer.peer_id,
             name: peer.name,
+            is_host: peer.is_host ?? false,
           });
         }
 
@@ -256,6 +268,54 @@ export class WebEngine {
         break;
       }
 
+      case "wrong_password": {
+        this.cleanup();
+        this.emit("state-changed", {
+          state: "error",
+          message: "Wrong password",
+        });
+        break;
+      }
+
+      case "room_locked": {
+        this.cleanup();
+        this.emit("state-changed", {
+          state: "error",
+          message: "This room is locked",
+        });
+        break;
+      }
+
+      case "kicked": {
+        this.emit("kicked");
+        this.cleanup();
+        this.emit("state-changed", {
+          state: "error",
+          message: "You were removed from the room by the host",
+        });
+        break;
+      }
+
+      case "room_lock_changed": {
+        this.roomLocked = msg.locked ?? false;
+        this.emitInRoom();
+        break;
+      }
+
+      case "mute_requested": {
+        this.setMuted(true);
+        this.emit("mute-requested", msg.by ?? "");
+        break;
+      }
+
+      case "host_changed": {
+        const hostId = msg.peer_id!;
+        this.emit("host-changed", hostId);
+        this.isHost = hostId === this.peerId;
+        this.emitInRoom();
+        break;
+      }
+
       case "peer_mute_state": {
         this.emit("peer-mute-changed", {
           peer_id: msg.peer_id,
@@ -263,9 +323,27 @@ export class WebEngine {
         });
         break;
       }
+
+      case "peer_recording_state": {
+        this.emit("peer-recording", {
+          peer_id: msg.peer_id,
+          recording: msg.recording,
+        });
+        break;
+      }
     }
   }
 
+  private emitInRoom(): void {
+    this.emit("state-changed", {
+      state: "in_room",
+      room_id: this.roomId,
+      room_name: this.roomName,
+      is_host: this.isHost,
+      locked: this.roomLocked,
+    });
+  }
+
   private async handleSignal(msg: SignalMsg): Promise<void> {
     const fromId = msg.from!;
     const payload = msg.payload!;
@@ -398,6 +476,8 @@ export class WebEngine {
     this.signaling.disconnect();
     this.roomId = "";
     this.displayName = "";
+    this.isHost = false;
+    this.roomLocked = false;
   }
 }
 